@@ -0,0 +1,181 @@
+//! Memory and speed of the chat state cache under a million synthetic chats.
+//!
+//! Run with `cargo bench --bench state_cache`. Heap usage is measured by a counting
+//! allocator and printed every 100 000 chats; after the cache is full it has to stay flat.
+//! Every update commits its offset, which flushes the cache, so the time of an update must
+//! not depend on the capacity.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    collections::BTreeMap,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Instant,
+};
+
+use echo_bot::storage::{
+    AuditEntry, CacheConfig, CachedStore, ChatState, OutboxEntry, StateStore, StoreError,
+};
+
+struct CountingAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// Persistent storage stand-in which keeps nothing in memory, so only the cache is measured.
+struct DiscardStore;
+
+impl StateStore for DiscardStore {
+    fn load_chat(&mut self, _chat_id: i64) -> Result<ChatState, StoreError> {
+        Ok(ChatState::default())
+    }
+
+    fn save_chat(&mut self, _chat_id: i64, _state: &ChatState) -> Result<(), StoreError> {
+        Ok(())
+    }
+
+    fn chats(&mut self) -> Result<Vec<(i64, ChatState)>, StoreError> {
+        Ok(vec![])
+    }
+
+    fn last_update_id(&self) -> Result<Option<u64>, StoreError> {
+        Ok(None)
+    }
+
+    fn set_last_update_id(&mut self, _update_id: u64) -> Result<(), StoreError> {
+        Ok(())
+    }
+
+    fn is_processed(&self, _update_id: u64) -> Result<bool, StoreError> {
+        Ok(false)
+    }
+
+    fn mark_processed(&mut self, _update_id: u64) -> Result<(), StoreError> {
+        Ok(())
+    }
+
+    fn enqueue(
+        &mut self,
+        _key: &str,
+        _chat_id: i64,
+        _payload: &serde_json::Value,
+    ) -> Result<bool, StoreError> {
+        Ok(true)
+    }
+
+    fn undelivered(&self) -> Result<Vec<OutboxEntry>, StoreError> {
+        Ok(vec![])
+    }
+
+    fn failed_deliveries(&self) -> Result<Vec<OutboxEntry>, StoreError> {
+        Ok(vec![])
+    }
+
+    fn save_outbox_entry(&mut self, _entry: &OutboxEntry) -> Result<(), StoreError> {
+        Ok(())
+    }
+
+    fn value(&self, _key: &str) -> Result<Option<serde_json::Value>, StoreError> {
+        Ok(None)
+    }
+
+    fn values(&self) -> Result<BTreeMap<String, serde_json::Value>, StoreError> {
+        Ok(BTreeMap::new())
+    }
+
+    fn set_value(
+        &mut self,
+        _key: &str,
+        _value: Option<&serde_json::Value>,
+    ) -> Result<(), StoreError> {
+        Ok(())
+    }
+
+    fn append_audit(&mut self, _entry: &AuditEntry) -> Result<(), StoreError> {
+        Ok(())
+    }
+
+    fn audit_entries(&self) -> Result<Vec<AuditEntry>, StoreError> {
+        Ok(vec![])
+    }
+}
+
+const CHATS: i64 = 1_000_000;
+const CAPACITY: usize = 10_000;
+/// Upper bound of an average update, an order of magnitude above the expected time.
+const MAX_MICROS_PER_UPDATE: f64 = 20.0;
+
+fn main() {
+    let config = CacheConfig {
+        capacity: CAPACITY,
+        idle_ttl: 3600,
+        flush_interval: 5,
+    };
+    let mut store = CachedStore::new(Box::new(DiscardStore), config);
+    let started = Instant::now();
+    let mut full_cache_bytes = None;
+
+    for chat_id in 0..CHATS {
+        let mut chat = store.load_chat(chat_id).unwrap();
+        chat.repeat_number = Some((chat_id % 5 + 1) as u8);
+        store.save_chat(chat_id, &chat).unwrap();
+        store.set_last_update_id(chat_id as u64).unwrap();
+
+        // every tenth update comes from one of the recent chats
+        if chat_id % 10 == 0 {
+            store.load_chat((chat_id - 100).max(0)).unwrap();
+        }
+
+        if (chat_id + 1) % 100_000 == 0 {
+            let allocated = ALLOCATED.load(Ordering::Relaxed);
+            full_cache_bytes.get_or_insert(allocated);
+
+            println!(
+                "{:>9} chats: {:>8} KiB allocated, {}",
+                chat_id + 1,
+                allocated / 1024,
+                store.cache_metrics().unwrap()
+            );
+        }
+    }
+
+    let elapsed = started.elapsed();
+    let micros_per_update = elapsed.as_secs_f64() * 1_000_000.0 / CHATS as f64;
+    let first = full_cache_bytes.unwrap();
+    let last = ALLOCATED.load(Ordering::Relaxed);
+
+    println!(
+        "{} chats in {:.2?} ({:.2} µs per update), memory after first 100 000 chats {} KiB, at the end {} KiB",
+        CHATS,
+        elapsed,
+        micros_per_update,
+        first / 1024,
+        last / 1024
+    );
+
+    assert!(
+        last <= first + first / 10,
+        "memory grows with number of chats: {} -> {} bytes",
+        first,
+        last
+    );
+    assert!(
+        micros_per_update <= MAX_MICROS_PER_UPDATE,
+        "an update takes {:.2} µs, more than {} µs",
+        micros_per_update,
+        MAX_MICROS_PER_UPDATE
+    );
+}
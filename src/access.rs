@@ -0,0 +1,204 @@
+use std::{collections::BTreeSet, fmt::Display};
+
+use serde::{Deserialize, Serialize};
+
+use crate::storage::{StateStore, StoreError};
+
+/// Key of the lists edited by admin commands among bot-wide values.
+const ACCESS_KEY: &str = "access";
+
+/// Who the bot answers. Denylists win over allowlists, an empty allowlist allows everyone.
+/// Chat ids of private chats are user ids, groups have negative ids.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct AccessConfig {
+    #[serde(default)]
+    pub allow_users: Vec<u64>,
+    #[serde(default)]
+    pub allow_chats: Vec<i64>,
+    /// Telegram chat types: private, group, supergroup or channel.
+    #[serde(default)]
+    pub allow_chat_types: Vec<String>,
+    #[serde(default)]
+    pub deny_users: Vec<u64>,
+    #[serde(default)]
+    pub deny_chats: Vec<i64>,
+    #[serde(default)]
+    pub deny_chat_types: Vec<String>,
+    /// Sent to denied users, they are ignored silently if not set.
+    pub denied_reply: Option<String>,
+}
+
+/// Users and chats added by `/ban` and `/allow`, kept in the state store on top of config.
+/// Positive ids are users, negative ones are group chats.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct AccessLists {
+    pub allowed: BTreeSet<i64>,
+    pub denied: BTreeSet<i64>,
+}
+
+impl AccessLists {
+    pub fn load(store: &dyn StateStore) -> Result<Self, StoreError> {
+        Ok(store
+            .value(ACCESS_KEY)?
+            .map(serde_json::from_value)
+            .transpose()?
+            .unwrap_or_default())
+    }
+
+    pub fn save(&self, store: &mut dyn StateStore) -> Result<(), StoreError> {
+        store.set_value(ACCESS_KEY, Some(&serde_json::to_value(self)?))
+    }
+}
+
+/// Why an update is not answered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Denial {
+    User(u64),
+    Chat(i64),
+    ChatType(String),
+    /// Allowlist is set and the update matches none of its entries.
+    NotAllowed,
+}
+
+impl Display for Denial {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Denial::User(user_id) => write!(f, "user {} is denied", user_id),
+            Denial::Chat(chat_id) => write!(f, "chat {} is denied", chat_id),
+            Denial::ChatType(kind) => write!(f, "chat type {} is denied", kind),
+            Denial::NotAllowed => write!(f, "user and chat are not in allowlist"),
+        }
+    }
+}
+
+/// Where an update came from.
+pub struct Sender<'a> {
+    pub user_id: Option<u64>,
+    pub chat_id: i64,
+    pub chat_type: &'a str,
+}
+
+impl AccessConfig {
+    /// With allowlists the user or the chat must be listed, and the chat type too if chat types
+    /// are listed. Admins are never denied, so they can always fix the lists.
+    pub fn check(
+        &self,
+        lists: &AccessLists,
+        admins: &[u64],
+        sender: &Sender,
+    ) -> Result<(), Denial> {
+        let user_id = sender.user_id;
+        let is_user = |ids: &[u64], runtime: &BTreeSet<i64>| {
+            user_id.is_some_and(|id| ids.contains(&id) || runtime.contains(&(id as i64)))
+        };
+        let is_chat = |ids: &[i64], runtime: &BTreeSet<i64>| {
+            ids.contains(&sender.chat_id) || runtime.contains(&sender.chat_id)
+        };
+
+        if user_id.is_some_and(|id| admins.contains(&id)) {
+            return Ok(());
+        }
+
+        if is_user(&self.deny_users, &lists.denied) {
+            return Err(Denial::User(user_id.unwrap_or_default()));
+        }
+        if is_chat(&self.deny_chats, &lists.denied) {
+            return Err(Denial::Chat(sender.chat_id));
+        }
+        if self
+            .deny_chat_types
+            .iter()
+            .any(|kind| kind == sender.chat_type)
+        {
+            return Err(Denial::ChatType(sender.chat_type.to_string()));
+        }
+
+        // runtime entries extend allowlists from config, they never restrict an open bot
+        let is_restricted = !self.allow_users.is_empty() || !self.allow_chats.is_empty();
+        let sender_passes = !is_restricted
+            || is_user(&self.allow_users, &lists.allowed)
+            || is_chat(&self.allow_chats, &lists.allowed);
+        let type_passes = self.allow_chat_types.is_empty()
+            || self
+                .allow_chat_types
+                .iter()
+                .any(|kind| kind == sender.chat_type);
+
+        if sender_passes && type_passes {
+            Ok(())
+        } else {
+            Err(Denial::NotAllowed)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sender(user_id: u64, chat_id: i64, chat_type: &str) -> Sender<'_> {
+        Sender {
+            user_id: Some(user_id),
+            chat_id,
+            chat_type,
+        }
+    }
+
+    #[test]
+    fn should_allow_everyone_without_lists() {
+        let config = AccessConfig::default();
+
+        assert_eq!(
+            config.check(&AccessLists::default(), &[], &sender(1, 1, "private")),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn should_deny_by_config_and_runtime_lists() {
+        let config = AccessConfig {
+            deny_users: vec![1],
+            deny_chat_types: vec!["channel".to_string()],
+            ..Default::default()
+        };
+        let lists = AccessLists {
+            denied: BTreeSet::from([2, -10]),
+            ..Default::default()
+        };
+
+        let check = |sender| config.check(&lists, &[], &sender);
+
+        assert_eq!(check(sender(1, 1, "private")), Err(Denial::User(1)));
+        assert_eq!(check(sender(2, -5, "group")), Err(Denial::User(2)));
+        assert_eq!(check(sender(3, -10, "group")), Err(Denial::Chat(-10)));
+        assert_eq!(
+            check(sender(3, -20, "channel")),
+            Err(Denial::ChatType("channel".to_string()))
+        );
+        assert_eq!(check(sender(3, 3, "private")), Ok(()));
+        assert_eq!(config.check(&lists, &[1], &sender(1, 1, "private")), Ok(()));
+    }
+
+    #[test]
+    fn should_answer_only_allowed_users_chats_and_chat_types() {
+        let config = AccessConfig {
+            allow_users: vec![1],
+            allow_chats: vec![-10],
+            allow_chat_types: vec!["private".to_string(), "group".to_string()],
+            ..Default::default()
+        };
+        let lists = AccessLists {
+            allowed: BTreeSet::from([2]),
+            ..Default::default()
+        };
+
+        let check = |sender| config.check(&lists, &[], &sender);
+
+        assert_eq!(check(sender(1, 1, "private")), Ok(()));
+        assert_eq!(check(sender(2, 2, "private")), Ok(()));
+        assert_eq!(check(sender(3, 3, "private")), Err(Denial::NotAllowed));
+        assert_eq!(check(sender(3, -10, "group")), Ok(()));
+        assert_eq!(check(sender(3, -20, "group")), Err(Denial::NotAllowed));
+        assert_eq!(check(sender(1, -30, "channel")), Err(Denial::NotAllowed));
+    }
+}
@@ -0,0 +1,188 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::storage::{StateStore, StoreError};
+
+/// Key of per-chat limits set by admins among bot-wide values.
+const OVERRIDES_KEY: &str = "budgets";
+
+/// Usage of chats is forgotten when more chats than this are tracked and their minute is over.
+const TRACKED_LIMIT: usize = 10_000;
+
+const WINDOW_SECS: i64 = 60;
+
+/// Notice of chats which have an override but no global budget is configured.
+pub const DEFAULT_NOTICE: &str =
+    "Too many messages for this chat, repeats are skipped for a minute";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BudgetLimits {
+    pub messages_per_minute: u32,
+    pub chars_per_minute: u32,
+}
+
+/// Outgoing messages a chat can get per minute, so one message with a big repeat number
+/// does not turn the bot into an amplifier.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BudgetConfig {
+    #[serde(flatten)]
+    pub limits: BudgetLimits,
+    /// Sent once a minute to a chat which is over the budget.
+    #[serde(default = "default_notice")]
+    pub notice: String,
+}
+
+fn default_notice() -> String {
+    DEFAULT_NOTICE.to_string()
+}
+
+/// Limits of single chats which replace the global ones, set by `/budget`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct BudgetOverrides(pub BTreeMap<i64, BudgetLimits>);
+
+impl BudgetOverrides {
+    pub fn load(store: &dyn StateStore) -> Result<Self, StoreError> {
+        Ok(store
+            .value(OVERRIDES_KEY)?
+            .map(serde_json::from_value)
+            .transpose()?
+            .unwrap_or_default())
+    }
+
+    pub fn save(&self, store: &mut dyn StateStore) -> Result<(), StoreError> {
+        store.set_value(OVERRIDES_KEY, Some(&serde_json::to_value(self)?))
+    }
+
+    /// Limits of the chat: its override or the global ones, `None` if there are no limits.
+    pub fn limits(&self, chat_id: i64, global: Option<&BudgetConfig>) -> Option<BudgetLimits> {
+        self.0
+            .get(&chat_id)
+            .copied()
+            .or(global.map(|budget| budget.limits))
+    }
+}
+
+#[derive(Debug, Default)]
+struct Usage {
+    window_started_at: i64,
+    messages: u32,
+    chars: usize,
+    noticed: bool,
+}
+
+/// What is left of the budget of a chat in the current minute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Remaining {
+    pub messages: u32,
+    pub chars: usize,
+}
+
+/// Outgoing usage of chats in the current minute, kept in memory.
+#[derive(Debug, Default)]
+pub struct OutgoingBudget {
+    usage: HashMap<i64, Usage>,
+}
+
+impl OutgoingBudget {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn usage(&mut self, chat_id: i64, now: i64) -> &mut Usage {
+        if self.usage.len() > TRACKED_LIMIT {
+            self.usage
+                .retain(|_, usage| now - usage.window_started_at < WINDOW_SECS);
+        }
+
+        let usage = self.usage.entry(chat_id).or_default();
+        if now - usage.window_started_at >= WINDOW_SECS {
+            *usage = Usage {
+                window_started_at: now,
+                ..Default::default()
+            };
+        }
+
+        usage
+    }
+
+    pub fn remaining(&mut self, chat_id: i64, limits: &BudgetLimits, now: i64) -> Remaining {
+        let usage = self.usage(chat_id, now);
+
+        Remaining {
+            messages: limits.messages_per_minute.saturating_sub(usage.messages),
+            chars: (limits.chars_per_minute as usize).saturating_sub(usage.chars),
+        }
+    }
+
+    pub fn spend(&mut self, chat_id: i64, messages: u32, chars: usize, now: i64) {
+        let usage = self.usage(chat_id, now);
+
+        usage.messages += messages;
+        usage.chars += chars;
+    }
+
+    /// True only for the first call in the current minute of the chat.
+    pub fn take_notice(&mut self, chat_id: i64, now: i64) -> bool {
+        let usage = self.usage(chat_id, now);
+
+        !std::mem::replace(&mut usage.noticed, true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: BudgetLimits = BudgetLimits {
+        messages_per_minute: 3,
+        chars_per_minute: 10,
+    };
+
+    #[test]
+    fn should_restore_budget_and_notice_after_a_minute() {
+        let mut budget = OutgoingBudget::new();
+
+        budget.spend(1, 2, 8, 0);
+        assert_eq!(
+            budget.remaining(1, &LIMITS, 30),
+            Remaining {
+                messages: 1,
+                chars: 2
+            }
+        );
+        assert_eq!(budget.remaining(2, &LIMITS, 30).messages, 3);
+        assert!(budget.take_notice(1, 30));
+        assert!(!budget.take_notice(1, 59));
+
+        budget.spend(1, 5, 50, 59);
+        assert_eq!(
+            budget.remaining(1, &LIMITS, 59),
+            Remaining {
+                messages: 0,
+                chars: 0
+            }
+        );
+
+        assert_eq!(budget.remaining(1, &LIMITS, 60).messages, 3);
+        assert!(budget.take_notice(1, 60));
+    }
+
+    #[test]
+    fn should_prefer_chat_override_to_global_budget() {
+        let global = BudgetConfig {
+            limits: LIMITS,
+            notice: default_notice(),
+        };
+        let limits = BudgetLimits {
+            messages_per_minute: 100,
+            chars_per_minute: 1000,
+        };
+        let overrides = BudgetOverrides(BTreeMap::from([(-10, limits)]));
+
+        assert_eq!(overrides.limits(-10, Some(&global)), Some(limits));
+        assert_eq!(overrides.limits(-10, None), Some(limits));
+        assert_eq!(overrides.limits(1, Some(&global)), Some(LIMITS));
+        assert_eq!(overrides.limits(1, None), None);
+    }
+}
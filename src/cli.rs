@@ -0,0 +1,837 @@
+use std::{fs, io::Write};
+
+use chrono::{TimeZone, Utc};
+
+use crate::{
+    config::{self, Config, ConfigBuilder},
+    maintenance::Maintenance,
+    storage::{
+        self,
+        dump::{self, ImportStrategy},
+        StorageConfig,
+    },
+    BotError,
+};
+
+pub const USAGE: &str = "Usage: echo_bot [command] [--config <path>] [--set <key>=<value>]...
+
+Commands:
+    run                              start the bot (default)
+    outbox                           list messages waiting for delivery or failed to deliver
+    chats                            list chats the bot is or was in
+    export                           print all persisted state as JSON
+    import <dump.json> [--replace]   load exported state, merged with current one by default
+    validate <dump.json>             check exported state without loading it
+    check-config                     check the config and print every problem in it
+    config print                     print the merged config with secrets redacted
+    maintenance [on|off]             show or switch maintenance mode
+    audit                            print the audit log as JSON
+
+Options:
+    --config <path>                  config file (.yaml, .yml, .json or .toml), may be given as
+                                     the last argument instead; by default $ECHO_BOT_CONFIG,
+                                     then config.* in the current directory,
+                                     $XDG_CONFIG_HOME/echo_bot and /etc/echo_bot
+    --set <key>=<value>              override a config key, nested keys are separated by dots";
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Subcommand {
+    Run,
+    Outbox,
+    Chats,
+    Export,
+    Import {
+        dump: String,
+        strategy: ImportStrategy,
+    },
+    Validate {
+        dump: String,
+    },
+    /// Validate the config without starting the bot.
+    CheckConfig,
+    /// Print the config merged from all sources.
+    ConfigPrint,
+    /// Show maintenance state, or switch it on or off.
+    Maintenance {
+        enabled: Option<bool>,
+    },
+    /// Print the audit log.
+    Audit,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Cli {
+    pub command: Subcommand,
+    pub config_path: Option<String>,
+    /// `key=value` pairs of `--set`, applied on top of the config file and the environment.
+    pub overrides: Vec<String>,
+}
+
+fn usage_error(message: &str) -> String {
+    format!("{}\n\n{}", message, USAGE)
+}
+
+impl Cli {
+    /// Parse arguments without the program name. A lone argument which is not
+    /// a command is a config path, so `echo_bot config.yaml` keeps working.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Cli, String> {
+        let mut replace = false;
+        let mut overrides = vec![];
+        let mut config_path = None;
+        let mut positional = vec![];
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--replace" => replace = true,
+                "--set" => match args.next() {
+                    Some(item) if item.contains('=') => overrides.push(item),
+                    _ => return Err(usage_error("Option --set expects <key>=<value>")),
+                },
+                "--config" => match args.next() {
+                    Some(path) if config_path.is_none() => config_path = Some(path),
+                    Some(_) => return Err(usage_error("Option --config is given twice")),
+                    None => return Err(usage_error("Option --config expects <path>")),
+                },
+                option if option.starts_with("--") => {
+                    return Err(usage_error(&format!("Unknown option: {}", option)))
+                }
+                _ => positional.push(arg),
+            }
+        }
+
+        let dump = |index: usize| {
+            positional
+                .get(index)
+                .cloned()
+                .ok_or_else(|| usage_error("Missing path to dump file"))
+        };
+
+        let (command, rest) = match positional.first().map(String::as_str) {
+            Some("run") => (Subcommand::Run, &positional[1..]),
+            Some("outbox") => (Subcommand::Outbox, &positional[1..]),
+            Some("chats") => (Subcommand::Chats, &positional[1..]),
+            Some("export") => (Subcommand::Export, &positional[1..]),
+            Some("audit") => (Subcommand::Audit, &positional[1..]),
+            Some("import") => {
+                let strategy = match replace {
+                    true => ImportStrategy::Replace,
+                    false => ImportStrategy::Merge,
+                };
+                (
+                    Subcommand::Import {
+                        dump: dump(1)?,
+                        strategy,
+                    },
+                    &positional[2..],
+                )
+            }
+            Some("validate") => (Subcommand::Validate { dump: dump(1)? }, &positional[2..]),
+            Some("check-config") => (Subcommand::CheckConfig, &positional[1..]),
+            Some("config") => match positional.get(1).map(String::as_str) {
+                Some("print") => (Subcommand::ConfigPrint, &positional[2..]),
+                _ => return Err(usage_error("Command config expects print")),
+            },
+            Some("maintenance") => match positional.get(1).map(String::as_str) {
+                Some("on") => (
+                    Subcommand::Maintenance {
+                        enabled: Some(true),
+                    },
+                    &positional[2..],
+                ),
+                Some("off") => (
+                    Subcommand::Maintenance {
+                        enabled: Some(false),
+                    },
+                    &positional[2..],
+                ),
+                _ => (Subcommand::Maintenance { enabled: None }, &positional[1..]),
+            },
+            _ => (Subcommand::Run, &positional[..]),
+        };
+
+        if replace && !matches!(command, Subcommand::Import { .. }) {
+            return Err(usage_error("Option --replace is only supported by import"));
+        }
+
+        // validation does not touch the storage, so it needs no config
+        let max_rest = match command {
+            Subcommand::Validate { .. } => 0,
+            _ => 1,
+        };
+
+        if let Some(extra) = rest.get(max_rest) {
+            return Err(usage_error(&format!("Unexpected argument: {}", extra)));
+        }
+
+        let config_path = match (config_path, rest.first()) {
+            (Some(_), Some(_)) => return Err(usage_error("Config path is given twice")),
+            (Some(path), _) => Some(path),
+            (None, path) => path.cloned(),
+        };
+
+        Ok(Cli {
+            command,
+            config_path,
+            overrides,
+        })
+    }
+}
+
+/// Print the outbox of the configured storage.
+pub fn print_outbox(config: &Config, out: &mut dyn Write) -> Result<(), BotError> {
+    let store = storage::open(&config.storage).map_err(BotError::Storage)?;
+    let mut entries = store.undelivered().map_err(BotError::Storage)?;
+    entries.extend(store.failed_deliveries().map_err(BotError::Storage)?);
+    entries.sort_by_key(|entry| entry.id);
+
+    if entries.is_empty() {
+        writeln!(out, "Outbox is empty").map_err(BotError::Console)?;
+        return Ok(());
+    }
+
+    for entry in &entries {
+        let next_attempt = Utc
+            .timestamp_opt(entry.next_attempt_at, 0)
+            .single()
+            .map_or("-".to_string(), |time| time.to_rfc3339());
+
+        writeln!(
+            out,
+            "#{} key {} chat {} {} attempts {} next attempt {}",
+            entry.id, entry.key, entry.chat_id, entry.status, entry.attempts, next_attempt
+        )
+        .and_then(|_| writeln!(out, "    payload: {}", entry.payload))
+        .and_then(|_| match &entry.last_error {
+            Some(error) => writeln!(out, "    last error: {}", error),
+            None => Ok(()),
+        })
+        .map_err(BotError::Console)?;
+    }
+
+    Ok(())
+}
+
+/// Print the chat registry of the configured storage.
+pub fn print_chats(config: &Config, out: &mut dyn Write) -> Result<(), BotError> {
+    let mut store = storage::open(&config.storage).map_err(BotError::Storage)?;
+    let chats = store.chats().map_err(BotError::Storage)?;
+    let known: Vec<_> = chats
+        .iter()
+        .filter_map(|(chat_id, chat)| chat.info.as_ref().map(|info| info.line(*chat_id)))
+        .collect();
+
+    if known.is_empty() {
+        writeln!(out, "No known chats").map_err(BotError::Console)?;
+        return Ok(());
+    }
+
+    for line in known {
+        writeln!(out, "{}", line).map_err(BotError::Console)?;
+    }
+
+    Ok(())
+}
+
+/// Show or switch maintenance in the configured storage. Only sqlite storage is shared
+/// with a running bot, which sees the switch at the next update; other storages are kept
+/// in memory by the bot and would lose the switch, so they are refused. False if refused.
+pub fn maintenance(
+    config: &Config,
+    enabled: Option<bool>,
+    out: &mut dyn Write,
+) -> Result<bool, BotError> {
+    if !matches!(config.storage, StorageConfig::Sqlite { .. }) {
+        writeln!(
+            out,
+            "Maintenance can be switched here only with sqlite storage, a running bot keeps \
+             other storages in memory. Send SIGUSR1 (on) or SIGUSR2 (off) to the bot or use \
+             /maintenance instead"
+        )
+        .map_err(BotError::Console)?;
+        return Ok(false);
+    }
+    let mut store = storage::open(&config.storage).map_err(BotError::Storage)?;
+
+    if let Some(enabled) = enabled {
+        Maintenance::switch_outside(store.as_mut(), enabled, Utc::now().timestamp())
+            .map_err(BotError::Storage)?;
+    }
+
+    let maintenance = Maintenance::load(store.as_ref()).map_err(BotError::Storage)?;
+    writeln!(out, "{}", maintenance.describe())
+        .map(|_| true)
+        .map_err(BotError::Console)
+}
+
+/// Print all persisted state as a versioned JSON document.
+pub fn export(config: &Config, out: &mut dyn Write) -> Result<(), BotError> {
+    let mut store = storage::open(&config.storage).map_err(BotError::Storage)?;
+    let dump = dump::export(store.as_mut(), Utc::now().timestamp()).map_err(BotError::Storage)?;
+
+    serde_json::to_writer_pretty(&mut *out, &dump).map_err(|e| BotError::Console(e.into()))?;
+    writeln!(out).map_err(BotError::Console)
+}
+
+/// Print the audit log of the configured storage as a JSON array, oldest first.
+pub fn audit(config: &Config, out: &mut dyn Write) -> Result<(), BotError> {
+    let store = storage::open(&config.storage).map_err(BotError::Storage)?;
+    let entries = store.audit_entries().map_err(BotError::Storage)?;
+
+    serde_json::to_writer_pretty(&mut *out, &entries).map_err(|e| BotError::Console(e.into()))?;
+    writeln!(out).map_err(BotError::Console)
+}
+
+/// Check the config, printing every problem. False if the bot cannot start with it.
+pub fn check_config(
+    path: Option<String>,
+    overrides: Vec<String>,
+    out: &mut dyn Write,
+) -> Result<bool, BotError> {
+    let builder = ConfigBuilder::new()
+        .path(path)
+        .overrides(overrides)
+        .extract_config_body();
+    let path = builder
+        .as_ref()
+        .map_or_else(|_| String::new(), ConfigBuilder::file_path);
+
+    match builder.and_then(ConfigBuilder::build) {
+        Ok(_) => writeln!(out, "Config {} is valid", path).map(|_| true),
+        Err(error) => writeln!(out, "{}", error).map(|_| false),
+    }
+    .map_err(BotError::Console)
+}
+
+/// Print the config document merged from files, the environment and overrides, with secrets
+/// redacted. Keys missing from it take their defaults.
+pub fn print_config(document: &serde_yaml::Value, out: &mut dyn Write) -> Result<(), BotError> {
+    let printed = serde_yaml::to_string(&config::redacted(document))
+        .map_err(|e| BotError::Console(std::io::Error::other(e)))?;
+
+    write!(out, "{}", printed).map_err(BotError::Console)
+}
+
+/// Read and migrate a dump, printing every problem. None if the dump cannot be used.
+fn read_dump(path: &str, out: &mut dyn Write) -> Result<Option<dump::Dump>, BotError> {
+    let content = fs::read_to_string(path).map_err(BotError::Console)?;
+
+    let (dump, version) = match dump::parse(&content) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            writeln!(out, "Invalid dump {}: {}", path, e).map_err(BotError::Console)?;
+            return Ok(None);
+        }
+    };
+
+    if version < dump.version {
+        writeln!(
+            out,
+            "Dump migrated from version {} to {}",
+            version, dump.version
+        )
+        .map_err(BotError::Console)?;
+    }
+
+    let problems = dump::validate(&dump);
+    if !problems.is_empty() {
+        writeln!(out, "Invalid dump {}:", path).map_err(BotError::Console)?;
+        for problem in problems {
+            writeln!(out, "    {}", problem).map_err(BotError::Console)?;
+        }
+        return Ok(None);
+    }
+
+    Ok(Some(dump))
+}
+
+/// Check a dump without applying it, false if it is invalid.
+pub fn validate(path: &str, out: &mut dyn Write) -> Result<bool, BotError> {
+    let Some(dump) = read_dump(path, out)? else {
+        return Ok(false);
+    };
+
+    writeln!(
+        out,
+        "Dump is valid: {} chats, last update id {}",
+        dump.chats.len(),
+        dump.last_update_id
+            .map_or("-".to_string(), |id| id.to_string())
+    )
+    .map_err(BotError::Console)?;
+
+    Ok(true)
+}
+
+/// Load a dump into the configured storage, false if the dump is invalid.
+pub fn import(
+    config: &Config,
+    path: &str,
+    strategy: ImportStrategy,
+    out: &mut dyn Write,
+) -> Result<bool, BotError> {
+    let Some(dump) = read_dump(path, out)? else {
+        return Ok(false);
+    };
+
+    let mut store = storage::open(&config.storage).map_err(BotError::Storage)?;
+    let report = dump::import(store.as_mut(), &dump, strategy).map_err(BotError::Storage)?;
+
+    writeln!(
+        out,
+        "Imported {} chats, removed {} chats, imported {} values, {} audit entries and {} queued messages, dropped {} queued messages",
+        report.imported,
+        report.removed,
+        report.values,
+        report.audit,
+        report.outbox,
+        report.dropped
+    )
+    .map_err(BotError::Console)?;
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use crate::{
+        config::{BotMode, ConfigBuilder},
+        storage::{AuditEntry, BotStatus, ChatInfo, ChatState},
+    };
+
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn should_parse_config_path_without_command() {
+        assert_eq!(
+            Cli::parse(args(&["dir/config.yaml"])),
+            Ok(Cli {
+                command: Subcommand::Run,
+                config_path: Some("dir/config.yaml".to_string()),
+                overrides: vec![]
+            })
+        );
+        assert_eq!(
+            Cli::parse(args(&[])),
+            Ok(Cli {
+                command: Subcommand::Run,
+                config_path: None,
+                overrides: vec![]
+            })
+        );
+    }
+
+    #[test]
+    fn should_parse_outbox_command() {
+        assert_eq!(
+            Cli::parse(args(&["outbox", "config.yaml"])),
+            Ok(Cli {
+                command: Subcommand::Outbox,
+                config_path: Some("config.yaml".to_string()),
+                overrides: vec![]
+            })
+        );
+    }
+
+    #[test]
+    fn should_fail_on_extra_arguments() {
+        assert!(Cli::parse(args(&["outbox", "config.yaml", "more"])).is_err());
+    }
+
+    #[test]
+    fn should_collect_config_overrides() {
+        assert_eq!(
+            Cli::parse(args(&[
+                "--set",
+                "log_level=Debug",
+                "config.yaml",
+                "--set",
+                "storage.path=bot.db"
+            ])),
+            Ok(Cli {
+                command: Subcommand::Run,
+                config_path: Some("config.yaml".to_string()),
+                overrides: vec![
+                    "log_level=Debug".to_string(),
+                    "storage.path=bot.db".to_string()
+                ]
+            })
+        );
+        assert!(Cli::parse(args(&["--set", "log_level"])).is_err());
+        assert!(Cli::parse(args(&["--set"])).is_err());
+    }
+
+    #[test]
+    fn should_print_merged_config_with_secrets_redacted() {
+        assert_eq!(
+            Cli::parse(args(&["config", "print", "--config", "prod.toml"])),
+            Ok(Cli {
+                command: Subcommand::ConfigPrint,
+                config_path: Some("prod.toml".to_string()),
+                overrides: vec![]
+            })
+        );
+        assert!(Cli::parse(args(&["config"])).is_err());
+        assert!(Cli::parse(args(&["--config", "a.yaml", "b.yaml"])).is_err());
+
+        let path = env::temp_dir().join(format!("echo_bot_{}_print.json", process::id()));
+        fs::write(
+            &path,
+            r#"{"mode": "telegram", "help_msg": "help", "repeat_msg": "repeat",
+                "default_repeat_number": 1, "log_level": "Info",
+                "bot_token": "123456789:AAEhBP0av28xHmDIx5XnKpFqbOVq1Bp3kVw"}"#,
+        )
+        .unwrap();
+        let (_, document) = ConfigBuilder::new()
+            .path(Some(path.to_str().unwrap().to_string()))
+            .env(vec![])
+            .overrides(vec!["storage.type=memory".to_string()])
+            .extract_config_body()
+            .and_then(ConfigBuilder::build_with_document)
+            .unwrap();
+
+        let mut out = vec![];
+        assert!(print_config(&document, &mut out).is_ok());
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "bot_token: <redacted>\n\
+             default_repeat_number: 1\n\
+             help_msg: help\n\
+             log_level: Info\n\
+             mode: telegram\n\
+             repeat_msg: repeat\n\
+             storage:\n  type: memory\n"
+        );
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn should_print_outbox_entries() {
+        let path = env::temp_dir().join(format!("echo_bot_{}_outbox.json", process::id()));
+        let mut config = ConfigBuilder::build_default(BotMode::Telegram);
+        config.storage = StorageConfig::Json {
+            path: path.to_str().unwrap().to_string(),
+        };
+
+        let mut store = storage::open(&config.storage).unwrap();
+        let payload = serde_json::json!({"type": "text", "text": "hi"});
+        store.enqueue("7:0", 42, &payload).unwrap();
+        drop(store);
+
+        let mut out = vec![];
+        assert!(print_outbox(&config, &mut out).is_ok());
+        let printed = String::from_utf8(out).unwrap();
+
+        assert!(printed.starts_with("#1 key 7:0 chat 42 pending attempts 0"));
+        assert!(printed.contains(r#"payload: {"text":"hi","type":"text"}"#));
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn should_print_known_chats() {
+        let path = env::temp_dir().join(format!("echo_bot_{}_chats.json", process::id()));
+        let mut config = ConfigBuilder::build_default(BotMode::Telegram);
+        config.storage = StorageConfig::Json {
+            path: path.to_str().unwrap().to_string(),
+        };
+
+        let mut out = vec![];
+        assert!(print_chats(&config, &mut out).is_ok());
+        assert_eq!(String::from_utf8(out).unwrap(), "No known chats\n");
+
+        let mut store = storage::open(&config.storage).unwrap();
+        let mut info = ChatInfo::new("group".to_string(), Some("Team".to_string()));
+        info.set_status(BotStatus::Admin, 0);
+        let chat = ChatState {
+            info: Some(info),
+            ..Default::default()
+        };
+        store.save_chat(-5, &chat).unwrap();
+        store
+            .save_chat(
+                7,
+                &ChatState {
+                    repeat_number: Some(2),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        drop(store);
+
+        let mut out = vec![];
+        assert!(print_chats(&config, &mut out).is_ok());
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "-5 group Team admin, joined 1970-01-01T00:00:00+00:00, last activity -\n"
+        );
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn should_check_config_and_print_every_problem() {
+        assert_eq!(
+            Cli::parse(args(&["check-config", "config.yaml"])),
+            Ok(Cli {
+                command: Subcommand::CheckConfig,
+                config_path: Some("config.yaml".to_string()),
+                overrides: vec![]
+            })
+        );
+
+        let mut out = vec![];
+        assert!(matches!(
+            check_config(Some("config.example.yaml".to_string()), vec![], &mut out),
+            Ok(true)
+        ));
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "Config config.example.yaml is valid\n"
+        );
+
+        let path = env::temp_dir().join(format!("echo_bot_{}_check.yaml", process::id()));
+        fs::write(
+            &path,
+            "mode: telegram\nhelp_msg: \"\"\nrepeat_msg: repeat\n",
+        )
+        .unwrap();
+        let mut out = vec![];
+        assert!(matches!(
+            check_config(Some(path.to_str().unwrap().to_string()), vec![], &mut out),
+            Ok(false)
+        ));
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!(
+                "Invalid config {}:\n  \
+                 line 1, column 1: missing field `default_repeat_number`\n  \
+                 line 2, column 11: help_msg cannot be empty\n  \
+                 bot_token is required in telegram mode\n",
+                path.display()
+            )
+        );
+
+        let mut out = vec![];
+        assert!(matches!(
+            check_config(Some("missing.yaml".to_string()), vec![], &mut out),
+            Ok(false)
+        ));
+        assert!(String::from_utf8(out)
+            .unwrap()
+            .starts_with("Cannot read config missing.yaml: "));
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn should_parse_dump_commands() {
+        assert_eq!(
+            Cli::parse(args(&["import", "dump.json", "--replace", "config.yaml"])),
+            Ok(Cli {
+                command: Subcommand::Import {
+                    dump: "dump.json".to_string(),
+                    strategy: ImportStrategy::Replace
+                },
+                config_path: Some("config.yaml".to_string()),
+                overrides: vec![]
+            })
+        );
+        assert_eq!(
+            Cli::parse(args(&["validate", "dump.json"])),
+            Ok(Cli {
+                command: Subcommand::Validate {
+                    dump: "dump.json".to_string()
+                },
+                config_path: None,
+                overrides: vec![]
+            })
+        );
+        assert!(Cli::parse(args(&["import"])).is_err());
+        assert!(Cli::parse(args(&["export", "--replace"])).is_err());
+        assert!(Cli::parse(args(&["validate", "dump.json", "config.yaml"])).is_err());
+    }
+
+    #[test]
+    fn should_switch_maintenance_in_storage() {
+        assert_eq!(
+            Cli::parse(args(&["maintenance", "on", "config.yaml"])),
+            Ok(Cli {
+                command: Subcommand::Maintenance {
+                    enabled: Some(true)
+                },
+                config_path: Some("config.yaml".to_string()),
+                overrides: vec![]
+            })
+        );
+        assert_eq!(
+            Cli::parse(args(&["maintenance", "config.yaml"])),
+            Ok(Cli {
+                command: Subcommand::Maintenance { enabled: None },
+                config_path: Some("config.yaml".to_string()),
+                overrides: vec![]
+            })
+        );
+
+        let path = env::temp_dir().join(format!("echo_bot_{}_maintenance.sqlite", process::id()));
+        let _ = fs::remove_file(&path);
+        let mut config = ConfigBuilder::build_default(BotMode::Telegram);
+        config.storage = StorageConfig::Sqlite {
+            path: path.to_str().unwrap().to_string(),
+        };
+
+        let mut out = vec![];
+        assert!(matches!(
+            maintenance(&config, Some(true), &mut out),
+            Ok(true)
+        ));
+        assert!(String::from_utf8(out)
+            .unwrap()
+            .starts_with("Maintenance is on since "));
+
+        let mut out = vec![];
+        assert!(matches!(
+            maintenance(&config, Some(false), &mut out),
+            Ok(true)
+        ));
+        assert!(matches!(maintenance(&config, None, &mut out), Ok(true)));
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "Maintenance is off\nMaintenance is off\n"
+        );
+
+        // a running bot would never see the switch in its own memory or overwrite it
+        let json = env::temp_dir().join(format!("echo_bot_{}_maintenance.json", process::id()));
+        let _ = fs::remove_file(&json);
+        for storage in [
+            StorageConfig::Memory,
+            StorageConfig::Json {
+                path: json.to_str().unwrap().to_string(),
+            },
+        ] {
+            let mut refused = ConfigBuilder::build_default(BotMode::Telegram);
+            refused.storage = storage;
+            let mut out = vec![];
+            assert!(matches!(
+                maintenance(&refused, Some(true), &mut out),
+                Ok(false)
+            ));
+            assert!(String::from_utf8(out).unwrap().contains("SIGUSR1"));
+        }
+        assert!(!json.exists());
+
+        assert_eq!(
+            Cli::parse(args(&["audit", "config.yaml"])).map(|cli| cli.command),
+            Ok(Subcommand::Audit)
+        );
+        let mut out = vec![];
+        assert!(audit(&config, &mut out).is_ok());
+        let entries: Vec<AuditEntry> = serde_json::from_slice(&out).unwrap();
+        assert_eq!(
+            entries
+                .iter()
+                .map(|entry| (entry.action.as_str(), entry.actor, entry.new.clone()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("maintenance", None, true.into()),
+                ("maintenance", None, false.into())
+            ]
+        );
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn should_move_state_between_stores() {
+        let source = env::temp_dir().join(format!("echo_bot_{}_source.json", process::id()));
+        let target = env::temp_dir().join(format!("echo_bot_{}_target.sqlite", process::id()));
+        let dump_path = env::temp_dir().join(format!("echo_bot_{}_dump.json", process::id()));
+        let _ = fs::remove_file(&target);
+
+        let mut source_config = ConfigBuilder::build_default(BotMode::Telegram);
+        source_config.storage = StorageConfig::Json {
+            path: source.to_str().unwrap().to_string(),
+        };
+        let mut target_config = ConfigBuilder::build_default(BotMode::Telegram);
+        target_config.storage = StorageConfig::Sqlite {
+            path: target.to_str().unwrap().to_string(),
+        };
+
+        let mut store = storage::open(&source_config.storage).unwrap();
+        store
+            .save_chat(
+                1,
+                &ChatState {
+                    repeat_number: Some(3),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        store.set_last_update_id(9).unwrap();
+        drop(store);
+
+        let mut exported = vec![];
+        assert!(export(&source_config, &mut exported).is_ok());
+        fs::write(&dump_path, exported).unwrap();
+
+        let mut out = vec![];
+        let dump = dump_path.to_str().unwrap();
+        assert!(matches!(validate(dump, &mut out), Ok(true)));
+        assert!(matches!(
+            import(&target_config, dump, ImportStrategy::Merge, &mut out),
+            Ok(true)
+        ));
+
+        let mut store = storage::open(&target_config.storage).unwrap();
+        assert_eq!(store.load_chat(1).unwrap().repeat_number, Some(3));
+        assert_eq!(store.last_update_id().unwrap(), Some(9));
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "Dump is valid: 1 chats, last update id 9\nImported 1 chats, removed 0 chats, imported 0 values, 0 audit entries and 0 queued messages, dropped 0 queued messages\n"
+        );
+
+        [source, target, dump_path]
+            .iter()
+            .for_each(|path| fs::remove_file(path).unwrap());
+    }
+
+    #[test]
+    fn should_not_import_invalid_dump() {
+        let dump_path = env::temp_dir().join(format!("echo_bot_{}_invalid.json", process::id()));
+        fs::write(
+            &dump_path,
+            r#"{"version": 1, "exported_at": 0, "last_update_id": 1, "chats": {
+                "5": {"repeat_number": 0, "conversation": null}
+            }}"#,
+        )
+        .unwrap();
+        let config = ConfigBuilder::build_default(BotMode::Telegram);
+
+        let mut out = vec![];
+        let result = import(
+            &config,
+            dump_path.to_str().unwrap(),
+            ImportStrategy::Merge,
+            &mut out,
+        );
+
+        assert!(matches!(result, Ok(false)));
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!(
+                "Dump migrated from version 1 to 2\nInvalid dump {}:\n    chat 5: repeat number must be greater than 0\n",
+                dump_path.to_str().unwrap()
+            )
+        );
+
+        fs::remove_file(dump_path).unwrap();
+    }
+}
@@ -0,0 +1,1179 @@
+use chrono::Utc;
+use serde_json::{json, Value};
+
+use crate::{
+    access::AccessLists,
+    budget::{BudgetLimits, BudgetOverrides},
+    conversation::{self, Conversation, Dialog, Step, Transition},
+    custom::StaticReply,
+    maintenance::Maintenance,
+    overrides::{ConfigKey, ConfigOverrides},
+    reload::ConfigReloader,
+    storage::{AuditFilter, Broadcast},
+};
+
+use super::{
+    registry::{CommandAction, CommandContext, CommandSpec, Frontend, Reply, Session},
+    ArgumentError, BudgetChange, Command, ConfigCommand, MAX_TEXT_CHARS,
+};
+
+pub fn commands() -> Vec<CommandSpec> {
+    vec![
+        CommandSpec {
+            name: "help".to_string(),
+            aliases: vec![],
+            description: "print help message, or help for one command".to_string(),
+            usage: "[command]".to_string(),
+            frontends: vec![Frontend::Console, Frontend::Telegram],
+            admin_only: false,
+            action: CommandAction::Builtin {
+                parse: parse_help,
+                handler: help,
+            },
+        },
+        CommandSpec {
+            name: "repeat".to_string(),
+            aliases: vec![],
+            description: "show or set how many times each message is repeated".to_string(),
+            usage: "[number]".to_string(),
+            frontends: vec![Frontend::Console, Frontend::Telegram],
+            admin_only: false,
+            action: CommandAction::Builtin {
+                parse: parse_repeat,
+                handler: repeat,
+            },
+        },
+        CommandSpec {
+            name: "cancel".to_string(),
+            aliases: vec![],
+            description: "cancel current dialog".to_string(),
+            usage: "".to_string(),
+            frontends: vec![Frontend::Console, Frontend::Telegram],
+            admin_only: false,
+            action: CommandAction::Builtin {
+                parse: parse_cancel,
+                handler: cancel,
+            },
+        },
+        CommandSpec {
+            name: "exit".to_string(),
+            aliases: vec!["quit".to_string()],
+            description: "stop the bot".to_string(),
+            usage: "".to_string(),
+            frontends: vec![Frontend::Console],
+            admin_only: false,
+            action: CommandAction::Builtin {
+                parse: parse_exit,
+                handler: exit,
+            },
+        },
+        CommandSpec {
+            name: "start".to_string(),
+            aliases: vec![],
+            description: "show welcome message".to_string(),
+            usage: "".to_string(),
+            frontends: vec![Frontend::Telegram],
+            admin_only: false,
+            action: CommandAction::Builtin {
+                parse: parse_start,
+                handler: start,
+            },
+        },
+        CommandSpec {
+            name: "chats".to_string(),
+            aliases: vec![],
+            description: "list chats the bot is in".to_string(),
+            usage: "".to_string(),
+            frontends: vec![Frontend::Console, Frontend::Telegram],
+            admin_only: true,
+            action: CommandAction::Builtin {
+                parse: parse_chats,
+                handler: chats,
+            },
+        },
+        CommandSpec {
+            name: "status".to_string(),
+            aliases: vec![],
+            description: "show state of the bot".to_string(),
+            usage: "".to_string(),
+            frontends: vec![Frontend::Console, Frontend::Telegram],
+            admin_only: true,
+            action: CommandAction::Builtin {
+                parse: parse_status,
+                handler: status,
+            },
+        },
+        CommandSpec {
+            name: "broadcast".to_string(),
+            aliases: vec![],
+            description: "send a message to all chats".to_string(),
+            usage: "".to_string(),
+            frontends: vec![Frontend::Telegram],
+            admin_only: true,
+            action: CommandAction::Builtin {
+                parse: parse_broadcast,
+                handler: broadcast,
+            },
+        },
+        CommandSpec {
+            name: "ban".to_string(),
+            aliases: vec![],
+            description: "stop answering a user or a chat".to_string(),
+            usage: "<user_id|chat_id>".to_string(),
+            frontends: vec![Frontend::Console, Frontend::Telegram],
+            admin_only: true,
+            action: CommandAction::Builtin {
+                parse: parse_ban,
+                handler: access,
+            },
+        },
+        CommandSpec {
+            name: "unban".to_string(),
+            aliases: vec![],
+            description: "answer a banned user or chat again".to_string(),
+            usage: "<user_id|chat_id>".to_string(),
+            frontends: vec![Frontend::Console, Frontend::Telegram],
+            admin_only: true,
+            action: CommandAction::Builtin {
+                parse: parse_unban,
+                handler: access,
+            },
+        },
+        CommandSpec {
+            name: "allow".to_string(),
+            aliases: vec![],
+            description: "add a user or a chat to the allowlist".to_string(),
+            usage: "<user_id|chat_id>".to_string(),
+            frontends: vec![Frontend::Console, Frontend::Telegram],
+            admin_only: true,
+            action: CommandAction::Builtin {
+                parse: parse_allow,
+                handler: access,
+            },
+        },
+        CommandSpec {
+            name: "budget".to_string(),
+            aliases: vec![],
+            description: "show or set outgoing messages per minute of a chat".to_string(),
+            usage: "[chat_id] [<messages> <characters>|reset]".to_string(),
+            frontends: vec![Frontend::Console, Frontend::Telegram],
+            admin_only: true,
+            action: CommandAction::Builtin {
+                parse: parse_budget,
+                handler: budget,
+            },
+        },
+        CommandSpec {
+            name: "maintenance".to_string(),
+            aliases: vec![],
+            description: "show or switch maintenance mode, users get a fixed answer in it"
+                .to_string(),
+            usage: "[on|off]".to_string(),
+            frontends: vec![Frontend::Console, Frontend::Telegram],
+            admin_only: true,
+            action: CommandAction::Builtin {
+                parse: parse_maintenance,
+                handler: maintenance,
+            },
+        },
+        CommandSpec {
+            name: "config".to_string(),
+            aliases: vec![],
+            description: "show or change help_msg, repeat_msg and default_repeat_number"
+                .to_string(),
+            usage: "get [key] | set <key> <value> | diff | reset [key]".to_string(),
+            frontends: vec![Frontend::Console, Frontend::Telegram],
+            admin_only: true,
+            action: CommandAction::Builtin {
+                parse: parse_config,
+                handler: config,
+            },
+        },
+        CommandSpec {
+            name: "audit".to_string(),
+            aliases: vec![],
+            description: "show who changed settings, newest first".to_string(),
+            usage: "[chat=<id>] [user=<id>] [action=<prefix>] [limit=<n>]".to_string(),
+            frontends: vec![Frontend::Console, Frontend::Telegram],
+            admin_only: true,
+            action: CommandAction::Builtin {
+                parse: parse_audit,
+                handler: audit,
+            },
+        },
+        CommandSpec {
+            name: "reload".to_string(),
+            aliases: vec![],
+            description: "reload the config file, changes are logged".to_string(),
+            usage: "".to_string(),
+            frontends: vec![Frontend::Console, Frontend::Telegram],
+            admin_only: true,
+            action: CommandAction::Builtin {
+                parse: parse_reload,
+                handler: reload,
+            },
+        },
+    ]
+}
+
+pub fn dialogs() -> Vec<Dialog> {
+    vec![
+        Dialog {
+            name: "repeat",
+            steps: vec![Step {
+                name: "number",
+                prompt: repeat_prompt,
+                handle: repeat_number_input,
+            }],
+        },
+        Dialog {
+            name: "broadcast",
+            steps: vec![
+                Step {
+                    name: "text",
+                    prompt: broadcast_text_prompt,
+                    handle: broadcast_text_input,
+                },
+                Step {
+                    name: "confirm",
+                    prompt: broadcast_preview,
+                    handle: broadcast_confirm,
+                },
+            ],
+        },
+    ]
+}
+
+fn parse_help(args: &str) -> Result<Command, ArgumentError> {
+    if args.is_empty() {
+        return Ok(Command::Help(None));
+    }
+
+    if args.contains(char::is_whitespace) {
+        return Err(ArgumentError::Unexpected);
+    }
+
+    Ok(Command::Help(Some(args.to_string())))
+}
+
+fn parse_repeat(args: &str) -> Result<Command, ArgumentError> {
+    if args.is_empty() {
+        return Ok(Command::Repeat(None));
+    }
+
+    match args.parse::<u8>() {
+        Ok(0) | Err(_) => Err(ArgumentError::Invalid(format!(
+            "expected a number from 1 to {}, got {}",
+            u8::MAX,
+            args
+        ))),
+        Ok(number) => Ok(Command::Repeat(Some(number))),
+    }
+}
+
+fn parse_start(args: &str) -> Result<Command, ArgumentError> {
+    // deep link payload is up to 64 chars of `A-Za-z0-9_-`, anything else is typed by hand
+    let is_payload = (1..=64).contains(&args.len())
+        && args
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+
+    Ok(Command::Start(is_payload.then(|| args.to_string())))
+}
+
+fn parse_cancel(args: &str) -> Result<Command, ArgumentError> {
+    if args.is_empty() {
+        Ok(Command::Cancel)
+    } else {
+        Err(ArgumentError::Unexpected)
+    }
+}
+
+fn parse_exit(args: &str) -> Result<Command, ArgumentError> {
+    if args.is_empty() {
+        Ok(Command::Exit)
+    } else {
+        Err(ArgumentError::Unexpected)
+    }
+}
+
+fn parse_chats(args: &str) -> Result<Command, ArgumentError> {
+    if args.is_empty() {
+        Ok(Command::Chats)
+    } else {
+        Err(ArgumentError::Unexpected)
+    }
+}
+
+fn parse_status(args: &str) -> Result<Command, ArgumentError> {
+    if args.is_empty() {
+        Ok(Command::Status)
+    } else {
+        Err(ArgumentError::Unexpected)
+    }
+}
+
+fn parse_broadcast(args: &str) -> Result<Command, ArgumentError> {
+    if args.is_empty() {
+        Ok(Command::Broadcast)
+    } else {
+        Err(ArgumentError::Unexpected)
+    }
+}
+
+/// Telegram id: positive for users, negative for group chats.
+fn parse_access_id(args: &str) -> Result<i64, ArgumentError> {
+    match args.parse::<i64>() {
+        Ok(0) | Err(_) => Err(ArgumentError::Invalid(format!(
+            "expected a user id or a chat id, got {}",
+            args
+        ))),
+        Ok(id) => Ok(id),
+    }
+}
+
+fn parse_ban(args: &str) -> Result<Command, ArgumentError> {
+    parse_access_id(args).map(Command::Ban)
+}
+
+fn parse_unban(args: &str) -> Result<Command, ArgumentError> {
+    parse_access_id(args).map(Command::Unban)
+}
+
+fn parse_allow(args: &str) -> Result<Command, ArgumentError> {
+    parse_access_id(args).map(Command::Allow)
+}
+
+/// Chat id goes first and is told from limits by the number of words.
+fn parse_budget(args: &str) -> Result<Command, ArgumentError> {
+    let mut words: Vec<&str> = args.split_whitespace().collect();
+    let has_chat_id = match words.as_slice() {
+        [word] => *word != "reset",
+        [_, word] => *word == "reset",
+        [_, _, _] => true,
+        _ => false,
+    };
+    let chat_id = match has_chat_id {
+        true => Some(parse_access_id(words.remove(0))?),
+        false => None,
+    };
+    let limit = |word: &str| match word.parse::<u32>() {
+        Ok(0) | Err(_) => Err(ArgumentError::Invalid(format!(
+            "expected a number greater than 0, got {}",
+            word
+        ))),
+        Ok(limit) => Ok(limit),
+    };
+
+    let change = match words.as_slice() {
+        [] => None,
+        ["reset"] => Some(BudgetChange::Reset),
+        [messages, chars] => Some(BudgetChange::Set(BudgetLimits {
+            messages_per_minute: limit(messages)?,
+            chars_per_minute: limit(chars)?,
+        })),
+        _ => return Err(ArgumentError::Unexpected),
+    };
+
+    Ok(Command::Budget { chat_id, change })
+}
+
+fn parse_maintenance(args: &str) -> Result<Command, ArgumentError> {
+    match args {
+        "" => Ok(Command::Maintenance(None)),
+        "on" => Ok(Command::Maintenance(Some(true))),
+        "off" => Ok(Command::Maintenance(Some(false))),
+        _ => Err(ArgumentError::Invalid(format!(
+            "expected on or off, got {}",
+            args
+        ))),
+    }
+}
+
+fn parse_config_key(name: &str) -> Result<ConfigKey, ArgumentError> {
+    ConfigKey::parse(name).ok_or_else(|| {
+        ArgumentError::Invalid(format!(
+            "{} cannot be changed at runtime, keys: {}",
+            name,
+            ConfigKey::ALL.map(|key| key.name()).join(", ")
+        ))
+    })
+}
+
+fn parse_config(args: &str) -> Result<Command, ArgumentError> {
+    let (action, rest) = args
+        .split_once(char::is_whitespace)
+        .map_or((args, ""), |(action, rest)| (action, rest.trim()));
+    let key = |name: &str| match name {
+        "" => Ok(None),
+        name if name.contains(char::is_whitespace) => Err(ArgumentError::Unexpected),
+        name => parse_config_key(name).map(Some),
+    };
+
+    let command = match action {
+        "get" => ConfigCommand::Get(key(rest)?),
+        "diff" if rest.is_empty() => ConfigCommand::Diff,
+        "diff" => return Err(ArgumentError::Unexpected),
+        "reset" => ConfigCommand::Reset(key(rest)?),
+        "set" => {
+            let (name, value) = rest
+                .split_once(char::is_whitespace)
+                .map_or((rest, ""), |(name, value)| (name, value.trim()));
+            let key = parse_config_key(name)?;
+            ConfigCommand::Set(key, key.parse_value(value).map_err(ArgumentError::Invalid)?)
+        }
+        _ => {
+            return Err(ArgumentError::Invalid(format!(
+                "expected get, set, diff or reset, got {}",
+                action
+            )))
+        }
+    };
+
+    Ok(Command::Config(command))
+}
+
+fn parse_reload(args: &str) -> Result<Command, ArgumentError> {
+    if args.is_empty() {
+        Ok(Command::Reload)
+    } else {
+        Err(ArgumentError::Unexpected)
+    }
+}
+
+fn parse_audit(args: &str) -> Result<Command, ArgumentError> {
+    let mut filter = AuditFilter::default();
+
+    for arg in args.split_whitespace() {
+        let invalid = || ArgumentError::Invalid(format!("cannot use {}", arg));
+        match arg.split_once('=').ok_or_else(invalid)? {
+            ("chat", id) => filter.chat_id = Some(id.parse().map_err(|_| invalid())?),
+            ("user", id) => filter.actor = Some(id.parse().map_err(|_| invalid())?),
+            ("action", prefix) if !prefix.is_empty() => filter.action = Some(prefix.to_string()),
+            ("limit", limit) => match limit.parse() {
+                Ok(limit @ 1..=AuditFilter::MAX_LIMIT) => filter.limit = limit,
+                _ => {
+                    return Err(ArgumentError::Invalid(format!(
+                        "limit must be a number from 1 to {}",
+                        AuditFilter::MAX_LIMIT
+                    )))
+                }
+            },
+            _ => return Err(invalid()),
+        }
+    }
+
+    Ok(Command::Audit(filter))
+}
+
+fn help(ctx: &CommandContext, _session: &mut dyn Session, command: Command) -> Reply {
+    match command {
+        Command::Help(Some(name)) => match ctx
+            .registry
+            .find(&name, ctx.frontend)
+            .filter(|spec| spec.is_visible(ctx.frontend, ctx.role()))
+        {
+            Some(spec) if spec.aliases.is_empty() => Reply::Text(spec.help_line()),
+            Some(spec) => Reply::Text(format!(
+                "{}\nAliases: {}",
+                spec.help_line(),
+                spec.aliases
+                    .iter()
+                    .map(|alias| format!("/{}", alias))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
+            None => ctx.registry.unknown_command(
+                &format!("/{}", name.trim_start_matches('/')),
+                ctx.frontend,
+                ctx.role(),
+            ),
+        },
+        _ => Reply::Text(format!(
+            "{}\n\n{}",
+            ctx.config.help_msg,
+            ctx.registry.help_lines(ctx.frontend, ctx.role()).join("\n")
+        )),
+    }
+}
+
+fn repeat(ctx: &CommandContext, session: &mut dyn Session, command: Command) -> Reply {
+    match command {
+        Command::Repeat(Some(number)) => {
+            ctx.set_repeat_number(session, number);
+            Reply::RepeatChanged(number)
+        }
+        _ => conversation::start(ctx, session, "repeat"),
+    }
+}
+
+fn repeat_prompt(ctx: &CommandContext, session: &dyn Session, _: &Conversation) -> Reply {
+    Reply::RepeatPrompt {
+        current: session
+            .repeat_number()
+            .unwrap_or(ctx.config.default_repeat_number),
+    }
+}
+
+fn repeat_number_input(
+    ctx: &CommandContext,
+    session: &mut dyn Session,
+    _: &mut Conversation,
+    input: &str,
+) -> Transition {
+    let error = "Try again input number".to_string();
+
+    match input.trim().parse::<u8>() {
+        Ok(0) => {
+            ctx.logger.log_warn("input number can't be zero");
+            Transition::Retry(error)
+        }
+        Ok(number) => {
+            ctx.set_repeat_number(session, number);
+            Transition::Finish(Reply::RepeatChanged(number))
+        }
+        Err(e) => {
+            ctx.logger
+                .log_warn(format!("failed parsing: {:?}", e).as_str());
+            Transition::Retry(error)
+        }
+    }
+}
+
+fn cancel(_ctx: &CommandContext, session: &mut dyn Session, _command: Command) -> Reply {
+    if conversation::cancel(session) {
+        Reply::Text("Cancelled. Send me any message and I will repeat it".to_string())
+    } else {
+        Reply::Text("Nothing to cancel".to_string())
+    }
+}
+
+fn start(ctx: &CommandContext, session: &mut dyn Session, command: Command) -> Reply {
+    let welcome = match &ctx.config.start {
+        Some(start) => start.welcome.clone(),
+        None => StaticReply {
+            text: Some(ctx.config.help_msg.clone()),
+            media: None,
+            keyboard: vec![],
+        },
+    };
+
+    let Command::Start(Some(payload)) = command else {
+        return Reply::Static(welcome);
+    };
+
+    ctx.logger
+        .log_info(format!("start with deep link payload: {}", payload).as_str());
+
+    let configured = ctx
+        .config
+        .start
+        .iter()
+        .flat_map(|start| start.payloads.iter())
+        .find(|p| p.payload == payload);
+
+    if let Some(configured) = configured {
+        if let Some(number) = configured.repeat_number.filter(|n| *n > 0) {
+            ctx.set_repeat_number(session, number);
+        }
+        return Reply::Static(configured.reply.clone());
+    }
+
+    // `repeat_<number>` presets repeat number without any config
+    let preset = payload
+        .strip_prefix("repeat_")
+        .and_then(|number| number.parse::<u8>().ok())
+        .filter(|number| *number > 0);
+
+    match preset {
+        Some(number) => ctx.set_repeat_number(session, number),
+        None => ctx
+            .logger
+            .log_warn(format!("unknown deep link payload: {}", payload).as_str()),
+    }
+
+    Reply::Static(welcome)
+}
+
+fn exit(_ctx: &CommandContext, _session: &mut dyn Session, _command: Command) -> Reply {
+    Reply::Exit
+}
+
+/// At most this many chats are listed, the most recently active first.
+const CHATS_LIST_LIMIT: usize = 50;
+
+fn chats(ctx: &CommandContext, _session: &mut dyn Session, _command: Command) -> Reply {
+    let chats = match ctx.store.borrow_mut().chats() {
+        Ok(chats) => chats,
+        Err(e) => {
+            ctx.logger
+                .log_error(format!("failed to read chats: {}", e).as_str());
+            return Reply::Text("Failed to read chats, see the log".to_string());
+        }
+    };
+
+    let mut known: Vec<_> = chats
+        .iter()
+        .filter_map(|(chat_id, chat)| chat.info.as_ref().map(|info| (*chat_id, info)))
+        .collect();
+    known.sort_by_key(|(_, info)| std::cmp::Reverse(info.last_activity_at));
+
+    if known.is_empty() {
+        return Reply::Text("No known chats".to_string());
+    }
+
+    let present = known
+        .iter()
+        .filter(|(_, info)| info.status.is_present())
+        .count();
+    let mut lines = vec![format!(
+        "Known chats: {}, bot is in {}",
+        known.len(),
+        present
+    )];
+    lines.extend(
+        known
+            .iter()
+            .take(CHATS_LIST_LIMIT)
+            .map(|(chat_id, info)| info.line(*chat_id)),
+    );
+    let hidden = known.len().saturating_sub(CHATS_LIST_LIMIT);
+
+    Reply::Text(fit_lines(lines, hidden, |more| {
+        format!("and {} more", more)
+    }))
+}
+
+/// Join lines into one text which fits into a message, lines which do not fit are dropped
+/// from the end and counted, together with `hidden` ones, in the last line made by `more`.
+fn fit_lines(lines: Vec<String>, hidden: usize, more: impl Fn(usize) -> String) -> String {
+    let joined = lines.join("\n");
+    if hidden == 0 && joined.chars().count() <= MAX_TEXT_CHARS {
+        return joined;
+    }
+
+    let reserved = more(lines.len() + hidden).chars().count() + 1;
+    let mut length = 0;
+    let mut kept = 0;
+    for line in &lines {
+        let added = line.chars().count() + usize::from(kept > 0);
+        if length + added + reserved > MAX_TEXT_CHARS {
+            break;
+        }
+        length += added;
+        kept += 1;
+    }
+
+    let mut fitted = lines[..kept].to_vec();
+    fitted.push(more(lines.len() - kept + hidden));
+    fitted.join("\n")
+}
+
+fn status(ctx: &CommandContext, _session: &mut dyn Session, _command: Command) -> Reply {
+    let mut store = ctx.store.borrow_mut();
+    let state = store.chats().and_then(|chats| {
+        let outbox = (store.undelivered()?.len(), store.failed_deliveries()?.len());
+        Ok((chats, outbox, store.last_update_id()?))
+    });
+    let (chats, (pending, failed), last_update_id) = match state {
+        Ok(state) => state,
+        Err(e) => {
+            ctx.logger
+                .log_error(format!("failed to read state: {}", e).as_str());
+            return Reply::Text("Failed to read state, see the log".to_string());
+        }
+    };
+
+    let known = chats.iter().filter(|(_, chat)| chat.info.is_some()).count();
+    let present = chats
+        .iter()
+        .filter_map(|(_, chat)| chat.info.as_ref())
+        .filter(|info| info.status.is_present())
+        .count();
+
+    let mut lines = vec![
+        format!("Chats: {} known, bot is in {}", known, present),
+        format!("Outbox: {} pending, {} failed", pending, failed),
+        format!(
+            "Last update: {}",
+            last_update_id.map_or("-".to_string(), |id| id.to_string())
+        ),
+    ];
+    if let Some(metrics) = store.cache_metrics() {
+        lines.push(format!("Cache: {}", metrics));
+    }
+    if let Ok(Some(broadcast)) = Broadcast::load(*store) {
+        lines.push(broadcast.progress());
+    }
+    match Maintenance::load(*store) {
+        Ok(maintenance) if maintenance.enabled => lines.push(maintenance.describe()),
+        _ => {}
+    }
+
+    Reply::Text(lines.join("\n"))
+}
+
+/// Answers of the broadcast confirmation.
+const BROADCAST_SEND: &str = "Send";
+const BROADCAST_CANCEL: &str = "Cancel";
+
+/// Running broadcast, if any. Errors are logged and treated as no broadcast.
+fn running_broadcast(ctx: &CommandContext) -> Option<Broadcast> {
+    match Broadcast::load(*ctx.store.borrow()) {
+        Ok(broadcast) => broadcast.filter(|broadcast| !broadcast.is_finished()),
+        Err(e) => {
+            ctx.logger
+                .log_error(format!("failed to read broadcast: {}", e).as_str());
+            None
+        }
+    }
+}
+
+fn broadcast(ctx: &CommandContext, session: &mut dyn Session, _command: Command) -> Reply {
+    match running_broadcast(ctx) {
+        Some(running) => Reply::Text(format!(
+            "Another broadcast is running\n{}",
+            running.progress()
+        )),
+        None => conversation::start(ctx, session, "broadcast"),
+    }
+}
+
+fn broadcast_text_prompt(_ctx: &CommandContext, _session: &dyn Session, _: &Conversation) -> Reply {
+    Reply::Text("Send the message for all chats, or /cancel".to_string())
+}
+
+fn broadcast_text_input(
+    _ctx: &CommandContext,
+    _session: &mut dyn Session,
+    conversation: &mut Conversation,
+    input: &str,
+) -> Transition {
+    if input.trim().is_empty() {
+        return Transition::Retry("Message is empty, send the text to broadcast".to_string());
+    }
+
+    conversation
+        .data
+        .insert("text".to_string(), input.to_string());
+
+    Transition::Next("confirm")
+}
+
+fn broadcast_preview(
+    ctx: &CommandContext,
+    _session: &dyn Session,
+    conversation: &Conversation,
+) -> Reply {
+    let recipients = match Broadcast::recipients(*ctx.store.borrow_mut()) {
+        Ok(recipients) => recipients,
+        Err(e) => {
+            ctx.logger
+                .log_error(format!("failed to read chats: {}", e).as_str());
+            return Reply::Text("Failed to read chats, see the log".to_string());
+        }
+    };
+
+    Reply::Choice {
+        text: format!(
+            "Send this message to {} chats?\n\n{}",
+            recipients.len(),
+            conversation.data.get("text").map_or("", String::as_str)
+        ),
+        options: vec![BROADCAST_SEND.to_string(), BROADCAST_CANCEL.to_string()],
+    }
+}
+
+fn broadcast_confirm(
+    ctx: &CommandContext,
+    _session: &mut dyn Session,
+    conversation: &mut Conversation,
+    input: &str,
+) -> Transition {
+    let answer = input.trim();
+
+    if answer.eq_ignore_ascii_case(BROADCAST_CANCEL) {
+        return Transition::Finish(Reply::Text("Broadcast is cancelled".to_string()));
+    }
+    if !answer.eq_ignore_ascii_case(BROADCAST_SEND) {
+        return Transition::Retry(format!("Answer {} or {}", BROADCAST_SEND, BROADCAST_CANCEL));
+    }
+
+    if let Some(running) = running_broadcast(ctx) {
+        return Transition::Finish(Reply::Text(format!(
+            "Another broadcast is running\n{}",
+            running.progress()
+        )));
+    }
+
+    let text = conversation.data.get("text").cloned().unwrap_or_default();
+    let mut store = ctx.store.borrow_mut();
+    let started = Broadcast::recipients(*store).and_then(|recipients| {
+        let broadcast = Broadcast::new(text, ctx.chat_id, recipients, Utc::now().timestamp());
+        broadcast.save(*store)?;
+        Ok(broadcast)
+    });
+    drop(store);
+
+    match started {
+        Ok(broadcast) => {
+            ctx.logger
+                .log_info(format!("start broadcast to {} chats", broadcast.chats.len()).as_str());
+            ctx.audit(
+                "broadcast",
+                Value::Null,
+                json!({ "text": broadcast.text, "chats": broadcast.chats.len() }),
+            );
+            Transition::Finish(Reply::Text(format!(
+                "Broadcast to {} chats is started",
+                broadcast.chats.len()
+            )))
+        }
+        Err(e) => {
+            ctx.logger
+                .log_error(format!("failed to start broadcast: {}", e).as_str());
+            Transition::Retry("Failed to start broadcast, see the log".to_string())
+        }
+    }
+}
+
+fn describe_access_id(id: i64) -> String {
+    match id > 0 {
+        true => format!("User {}", id),
+        false => format!("Chat {}", id),
+    }
+}
+
+/// Runtime list the id is in, as recorded in the audit log.
+fn access_list_of(lists: &AccessLists, id: i64) -> Value {
+    if lists.denied.contains(&id) {
+        Value::from("denied")
+    } else if lists.allowed.contains(&id) {
+        Value::from("allowed")
+    } else {
+        Value::Null
+    }
+}
+
+fn access(ctx: &CommandContext, _session: &mut dyn Session, command: Command) -> Reply {
+    let config = &ctx.config.access;
+    let mut store = ctx.store.borrow_mut();
+    let mut lists = match AccessLists::load(*store) {
+        Ok(lists) => lists,
+        Err(e) => {
+            ctx.logger
+                .log_error(format!("failed to read access lists: {}", e).as_str());
+            return Reply::Text("Failed to read access lists, see the log".to_string());
+        }
+    };
+    let in_config = |users: &[u64], chats: &[i64], id: i64| {
+        chats.contains(&id) || (id > 0 && users.contains(&(id as u64)))
+    };
+    let id = match command {
+        Command::Ban(id) | Command::Unban(id) | Command::Allow(id) => id,
+        _ => return Reply::Text("Unexpected command".to_string()),
+    };
+    let old = access_list_of(&lists, id);
+
+    let text = match command {
+        Command::Ban(id) if id > 0 && ctx.config.admins.contains(&(id as u64)) => {
+            return Reply::Text(format!("{} is an admin, admins cannot be banned", id));
+        }
+        Command::Ban(id) => {
+            lists.allowed.remove(&id);
+            lists.denied.insert(id);
+            format!("{} is banned", describe_access_id(id))
+        }
+        Command::Unban(id) if !lists.denied.contains(&id) => {
+            return match in_config(&config.deny_users, &config.deny_chats, id) {
+                true => Reply::Text(format!(
+                    "{} is denied in config, remove it there",
+                    describe_access_id(id)
+                )),
+                false => Reply::Text(format!("{} is not banned", describe_access_id(id))),
+            };
+        }
+        Command::Unban(id) => {
+            lists.denied.remove(&id);
+            format!("{} is unbanned", describe_access_id(id))
+        }
+        Command::Allow(id) => {
+            lists.denied.remove(&id);
+            lists.allowed.insert(id);
+
+            let mut text = format!("{} is allowed", describe_access_id(id));
+            if config.allow_users.is_empty() && config.allow_chats.is_empty() {
+                text.push_str(", allowlist is not configured, so everyone is allowed anyway");
+            }
+            if in_config(&config.deny_users, &config.deny_chats, id) {
+                text.push_str(", but it is denied in config");
+            }
+            text
+        }
+        _ => return Reply::Text("Unexpected command".to_string()),
+    };
+
+    if let Err(e) = lists.save(*store) {
+        ctx.logger
+            .log_error(format!("failed to save access lists: {}", e).as_str());
+        return Reply::Text("Failed to save access lists, see the log".to_string());
+    }
+    drop(store);
+    ctx.audit(&format!("access.{}", id), old, access_list_of(&lists, id));
+    ctx.logger.log_info(&text);
+
+    Reply::Text(text)
+}
+
+fn describe_budget(limits: Option<BudgetLimits>) -> String {
+    match limits {
+        Some(limits) => format!(
+            "{} messages and {} characters per minute",
+            limits.messages_per_minute, limits.chars_per_minute
+        ),
+        None => "not limited".to_string(),
+    }
+}
+
+fn budget(ctx: &CommandContext, _session: &mut dyn Session, command: Command) -> Reply {
+    let Command::Budget { chat_id, change } = command else {
+        return Reply::Text("Unexpected command".to_string());
+    };
+    let chat_id = chat_id.unwrap_or(ctx.chat_id);
+    let global = ctx.config.outgoing_budget.as_ref();
+    let mut store = ctx.store.borrow_mut();
+    let mut overrides = match BudgetOverrides::load(*store) {
+        Ok(overrides) => overrides,
+        Err(e) => {
+            ctx.logger
+                .log_error(format!("failed to read budgets: {}", e).as_str());
+            return Reply::Text("Failed to read budgets, see the log".to_string());
+        }
+    };
+    let old = json!(overrides.0.get(&chat_id));
+
+    let text = match change {
+        None => {
+            let source = match overrides.0.contains_key(&chat_id) {
+                true => "set for this chat",
+                false => "from config",
+            };
+            return Reply::Text(format!(
+                "Outgoing budget of chat {}: {}, {}",
+                chat_id,
+                describe_budget(overrides.limits(chat_id, global)),
+                source
+            ));
+        }
+        Some(BudgetChange::Reset) if overrides.0.remove(&chat_id).is_none() => {
+            return Reply::Text(format!("Chat {} has no own outgoing budget", chat_id));
+        }
+        Some(BudgetChange::Reset) => format!(
+            "Outgoing budget of chat {} is reset to config: {}",
+            chat_id,
+            describe_budget(overrides.limits(chat_id, global))
+        ),
+        Some(BudgetChange::Set(limits)) => {
+            overrides.0.insert(chat_id, limits);
+            format!(
+                "Outgoing budget of chat {} is set to {}",
+                chat_id,
+                describe_budget(Some(limits))
+            )
+        }
+    };
+
+    if let Err(e) = overrides.save(*store) {
+        ctx.logger
+            .log_error(format!("failed to save budgets: {}", e).as_str());
+        return Reply::Text("Failed to save budgets, see the log".to_string());
+    }
+    drop(store);
+    ctx.audit(
+        &format!("budget.{}", chat_id),
+        old,
+        json!(overrides.0.get(&chat_id)),
+    );
+    ctx.logger.log_info(&text);
+
+    Reply::Text(text)
+}
+
+fn maintenance(ctx: &CommandContext, _session: &mut dyn Session, command: Command) -> Reply {
+    let Command::Maintenance(enabled) = command else {
+        return Reply::Text("Unexpected command".to_string());
+    };
+    let mut store = ctx.store.borrow_mut();
+
+    let result =
+        match enabled {
+            None => Maintenance::load(*store).map(|maintenance| (false, maintenance.describe())),
+            Some(enabled) => Maintenance::switch(*store, enabled, Utc::now().timestamp()).map(
+                |switched| match (switched, enabled) {
+                    (false, _) => (
+                        false,
+                        format!(
+                            "Maintenance is already {}",
+                            if enabled { "on" } else { "off" }
+                        ),
+                    ),
+                    (true, true) => (
+                        true,
+                        format!(
+                            "Maintenance is on, users get: {}",
+                            ctx.config.maintenance_msg
+                        ),
+                    ),
+                    (true, false) => (true, "Maintenance is off".to_string()),
+                },
+            ),
+        };
+    drop(store);
+
+    match result {
+        Ok((switched, text)) => {
+            if let (true, Some(enabled)) = (switched, enabled) {
+                ctx.audit("maintenance", Value::from(!enabled), Value::from(enabled));
+            }
+            if enabled.is_some() {
+                ctx.logger.log_info(&text);
+            }
+            Reply::Text(text)
+        }
+        Err(e) => {
+            ctx.logger
+                .log_error(format!("failed to switch maintenance: {}", e).as_str());
+            Reply::Text("Failed to switch maintenance, see the log".to_string())
+        }
+    }
+}
+
+fn config(ctx: &CommandContext, _session: &mut dyn Session, command: Command) -> Reply {
+    let Command::Config(command) = command else {
+        return Reply::Text("Unexpected command".to_string());
+    };
+    let file = ctx.file_config;
+    let loaded = ConfigOverrides::load(*ctx.store.borrow());
+    let mut overrides = match loaded {
+        Ok(overrides) => overrides,
+        Err(e) => {
+            ctx.logger
+                .log_error(format!("failed to read config overrides: {}", e).as_str());
+            return Reply::Text("Failed to read config overrides, see the log".to_string());
+        }
+    };
+    let line = |key: ConfigKey, overrides: &ConfigOverrides| match overrides.get(key) {
+        Some(value) => format!("{} = {} (file: {})", key.name(), value, key.value(file)),
+        None => format!("{} = {}", key.name(), key.value(file)),
+    };
+
+    let (changes, text) = match command {
+        ConfigCommand::Get(key) => {
+            let keys = key.map_or(ConfigKey::ALL.to_vec(), |key| vec![key]);
+            let lines: Vec<_> = keys.into_iter().map(|key| line(key, &overrides)).collect();
+            return Reply::Text(lines.join("\n"));
+        }
+        ConfigCommand::Diff => {
+            let lines: Vec<_> = ConfigKey::ALL
+                .into_iter()
+                .filter_map(|key| {
+                    overrides
+                        .get(key)
+                        .map(|value| format!("{}: {} -> {}", key.name(), key.value(file), value))
+                })
+                .collect();
+            return Reply::Text(match lines.is_empty() {
+                true => "No overrides, config file is used as is".to_string(),
+                false => lines.join("\n"),
+            });
+        }
+        ConfigCommand::Set(key, value) => {
+            let old = overrides.value(key, file);
+            overrides.set(key, value.clone());
+            (
+                vec![(key, old, value.clone())],
+                format!("{} is set to {}", key.name(), value),
+            )
+        }
+        ConfigCommand::Reset(key) => {
+            let keys = key.map_or(ConfigKey::ALL.to_vec(), |key| vec![key]);
+            let changes: Vec<_> = keys
+                .into_iter()
+                .filter_map(|key| overrides.reset(key).map(|old| (key, old, key.value(file))))
+                .collect();
+            let text = match (key, changes.is_empty()) {
+                (Some(key), true) => {
+                    return Reply::Text(format!("{} is not overridden", key.name()))
+                }
+                (None, true) => return Reply::Text("No overrides to reset".to_string()),
+                (_, false) => changes
+                    .iter()
+                    .map(|(key, _, value)| format!("{} is reset to {}", key.name(), value))
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            };
+            (changes, text)
+        }
+    };
+
+    let saved = overrides.save(*ctx.store.borrow_mut());
+    if let Err(e) = saved {
+        ctx.logger
+            .log_error(format!("failed to save config overrides: {}", e).as_str());
+        return Reply::Text("Failed to save config overrides, see the log".to_string());
+    }
+    for (key, old, new) in changes {
+        ctx.audit(&format!("config.{}", key.name()), old, new);
+    }
+    ctx.logger.log_info(&text);
+
+    Reply::Text(text)
+}
+
+fn audit(ctx: &CommandContext, _session: &mut dyn Session, command: Command) -> Reply {
+    let Command::Audit(filter) = command else {
+        return Reply::Text("Unexpected command".to_string());
+    };
+    let loaded = ctx.store.borrow().audit_entries();
+
+    match loaded {
+        Ok(entries) => {
+            let lines: Vec<_> = filter
+                .select(entries)
+                .iter()
+                .map(|entry| entry.line())
+                .collect();
+            Reply::Text(match lines.is_empty() {
+                true => "No audit entries".to_string(),
+                false => fit_lines(lines, 0, |more| {
+                    format!("…{} more, narrow the filter", more)
+                }),
+            })
+        }
+        Err(e) => {
+            ctx.logger
+                .log_error(format!("failed to read audit log: {}", e).as_str());
+            Reply::Text("Failed to read audit log, see the log".to_string())
+        }
+    }
+}
+
+fn reload(ctx: &CommandContext, _session: &mut dyn Session, _command: Command) -> Reply {
+    let requested = ConfigReloader::request(*ctx.store.borrow_mut());
+
+    match requested {
+        Ok(()) => {
+            ctx.logger.log_info("config reload is requested");
+            Reply::Text(
+                "Config is reloaded before the next update, changed keys are logged \
+                 and kept in /audit"
+                    .to_string(),
+            )
+        }
+        Err(e) => {
+            ctx.logger
+                .log_error(format!("failed to request config reload: {}", e).as_str());
+            Reply::Text("Failed to request config reload, see the log".to_string())
+        }
+    }
+}
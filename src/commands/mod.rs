@@ -0,0 +1,113 @@
+mod builtin;
+pub mod registry;
+mod suggest;
+
+use std::fmt::Display;
+
+use crate::{budget::BudgetLimits, overrides::ConfigKey, storage::AuditFilter};
+
+pub use registry::{
+    dispatch, CommandContext, CommandRegistry, CommandSpec, Frontend, Reply, Role, Session,
+};
+
+/// Longest text of one reply, telegram does not send longer ones.
+pub const MAX_TEXT_CHARS: usize = 4096;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Cancel,
+    Exit,
+    Help(Option<String>),
+    Repeat(Option<u8>),
+    /// `/start` with optional deep link payload.
+    Start(Option<String>),
+    /// List of chats the bot is in.
+    Chats,
+    Status,
+    /// Send a message to every chat, composed in a dialog.
+    Broadcast,
+    /// Add a user (positive id) or a chat (negative id) to the runtime denylist.
+    Ban(i64),
+    Unban(i64),
+    /// Add a user or a chat to the runtime allowlist.
+    Allow(i64),
+    /// Show or change the outgoing budget of a chat, the current one if no chat is given.
+    Budget {
+        chat_id: Option<i64>,
+        change: Option<BudgetChange>,
+    },
+    /// Show maintenance state, or switch it on or off.
+    Maintenance(Option<bool>),
+    /// Read or change config keys which can be overridden at runtime.
+    Config(ConfigCommand),
+    /// Show recorded changes of settings, newest first.
+    Audit(AuditFilter),
+    /// Reload the config file before the next update.
+    Reload,
+    /// Command defined in config, holds its name.
+    Custom(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BudgetChange {
+    Set(BudgetLimits),
+    /// Remove the override, the chat is back to the global budget.
+    Reset,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigCommand {
+    /// One key, or all of them.
+    Get(Option<ConfigKey>),
+    /// The value is already checked against the type of the key.
+    Set(ConfigKey, serde_json::Value),
+    /// Overridden keys with values from the file and in effect.
+    Diff,
+    /// Back to the file value of one key, or of all of them.
+    Reset(Option<ConfigKey>),
+}
+
+/// Error returned by argument parsers of a single command, the registry
+/// attaches command usage to it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArgumentError {
+    Unexpected,
+    Invalid(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandError {
+    Unknown(String),
+    UnexpectedArgument { usage: String },
+    InvalidArgument { usage: String, reason: String },
+}
+
+impl Display for CommandError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CommandError::Unknown(name) => write!(f, "Unknown command: {}", name),
+            CommandError::UnexpectedArgument { usage } => {
+                write!(f, "Too many arguments. Usage: {}", usage)
+            }
+            CommandError::InvalidArgument { usage, reason } => {
+                write!(f, "Invalid argument: {}. Usage: {}", reason, usage)
+            }
+        }
+    }
+}
+
+pub trait IsCommand {
+    fn is_command(&self) -> bool;
+}
+
+impl IsCommand for String {
+    fn is_command(&self) -> bool {
+        self.starts_with('/')
+    }
+}
+
+impl IsCommand for str {
+    fn is_command(&self) -> bool {
+        self.starts_with('/')
+    }
+}
@@ -0,0 +1,381 @@
+use std::cell::RefCell;
+
+use chrono::Utc;
+
+use crate::{
+    config::Config,
+    conversation::{Conversation, Dialog},
+    custom::StaticReply,
+    logger::Logger,
+    storage::{AuditEntry, StateStore},
+};
+
+use super::{builtin, suggest::typo_distance, ArgumentError, Command, CommandError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Frontend {
+    Console,
+    Telegram,
+}
+
+/// Permissions of the sender of a command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    User,
+    /// Operator of the bot, can run admin-only commands.
+    Admin,
+}
+
+/// Per-chat settings a command handler is allowed to read and change.
+pub trait Session {
+    fn repeat_number(&self) -> Option<u8>;
+    fn set_repeat_number(&mut self, number: u8);
+    fn conversation(&self) -> Option<Conversation>;
+    fn set_conversation(&mut self, conversation: Option<Conversation>);
+}
+
+/// Result of a command, every frontend renders it in its own way.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reply {
+    Text(String),
+    /// `suggestions` are command names with leading slash, closest first.
+    UnknownCommand {
+        text: String,
+        suggestions: Vec<String>,
+    },
+    Static(StaticReply),
+    RepeatPrompt {
+        current: u8,
+    },
+    RepeatChanged(u8),
+    /// Question with a fixed set of answers, the chosen one comes back as dialog input.
+    Choice {
+        text: String,
+        options: Vec<String>,
+    },
+    Exit,
+}
+
+pub struct CommandContext<'a> {
+    /// Config with runtime overrides applied.
+    pub config: &'a Config,
+    /// Config as it was loaded from the file.
+    pub file_config: &'a Config,
+    pub registry: &'a CommandRegistry,
+    pub frontend: Frontend,
+    /// Chat the command came from.
+    pub chat_id: i64,
+    /// Telegram user who sent the command, `None` in the console.
+    pub user_id: Option<u64>,
+    pub logger: &'a dyn Logger,
+    /// Store of all chats, for commands which look beyond the current one.
+    /// The current chat is the session, it is saved by the frontend.
+    pub store: RefCell<&'a mut dyn StateStore>,
+    /// Resolves role of the sender, it may ask telegram, so it is called only when needed.
+    pub role: &'a dyn Fn() -> Role,
+}
+
+impl CommandContext<'_> {
+    pub fn role(&self) -> Role {
+        (self.role)()
+    }
+
+    /// Record a change made by the sender in the audit log, a failure is only logged.
+    pub fn audit(&self, action: &str, old: serde_json::Value, new: serde_json::Value) {
+        let entry = AuditEntry::new(
+            Utc::now().timestamp(),
+            self.user_id,
+            self.chat_id,
+            action,
+            old,
+            new,
+        );
+
+        if let Err(e) = self.store.borrow_mut().append_audit(&entry) {
+            self.logger
+                .log_error(format!("failed to write audit log: {}", e).as_str());
+        }
+    }
+
+    /// Change repeat number of the current chat, `null` is the old value of a chat which
+    /// used the default one.
+    pub fn set_repeat_number(&self, session: &mut dyn Session, number: u8) {
+        let old = session.repeat_number();
+
+        session.set_repeat_number(number);
+        self.logger
+            .log_info(format!("set repeat number: {}", number).as_str());
+        self.audit("repeat", old.into(), number.into());
+    }
+}
+
+pub type ArgumentParser = fn(&str) -> Result<Command, ArgumentError>;
+pub type CommandHandler = fn(&CommandContext, &mut dyn Session, Command) -> Reply;
+
+pub enum CommandAction {
+    Builtin {
+        parse: ArgumentParser,
+        handler: CommandHandler,
+    },
+    /// Command from config which always answers with the same message, arguments are ignored.
+    Static(StaticReply),
+}
+
+pub struct CommandSpec {
+    /// Name without leading slash, e.g. `repeat`.
+    pub name: String,
+    pub aliases: Vec<String>,
+    pub description: String,
+    /// Arguments part of usage, e.g. `[number]`.
+    pub usage: String,
+    pub frontends: Vec<Frontend>,
+    /// Only admins can run the command, regular users do not see it.
+    pub admin_only: bool,
+    pub action: CommandAction,
+}
+
+impl CommandSpec {
+    pub fn usage_line(&self) -> String {
+        if self.usage.is_empty() {
+            format!("/{}", self.name)
+        } else {
+            format!("/{} {}", self.name, self.usage)
+        }
+    }
+
+    pub fn help_line(&self) -> String {
+        format!("{} - {}", self.usage_line(), self.description)
+    }
+
+    pub fn is_available(&self, frontend: Frontend) -> bool {
+        self.frontends.contains(&frontend)
+    }
+
+    /// Available and allowed for the role, so it can be shown in help and suggestions.
+    pub fn is_visible(&self, frontend: Frontend, role: Role) -> bool {
+        self.is_available(frontend) && (!self.admin_only || role == Role::Admin)
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        self.name == name || self.aliases.iter().any(|alias| alias == name)
+    }
+}
+
+pub struct CommandRegistry {
+    commands: Vec<CommandSpec>,
+    dialogs: Vec<Dialog>,
+}
+
+impl CommandRegistry {
+    /// Registry with all built-in commands and dialogs.
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        let mut registry = CommandRegistry {
+            commands: vec![],
+            dialogs: builtin::dialogs(),
+        };
+
+        builtin::commands()
+            .into_iter()
+            .for_each(|spec| registry.register(spec));
+
+        registry
+    }
+
+    /// Registry with built-in commands and custom commands from config. Custom commands
+    /// with the name of a built-in one are rejected by config validation and skipped here,
+    /// so a config cannot replace `/help` or an admin command.
+    pub fn from_config(config: &Config) -> Self {
+        let mut registry = CommandRegistry::new();
+
+        config
+            .custom_commands
+            .iter()
+            .filter(|custom| !CommandRegistry::is_builtin(&custom.name))
+            .for_each(|custom| {
+                registry.register(CommandSpec {
+                    name: custom.name.clone(),
+                    aliases: vec![],
+                    description: custom.description.clone(),
+                    usage: "".to_string(),
+                    frontends: vec![Frontend::Console, Frontend::Telegram],
+                    admin_only: false,
+                    action: CommandAction::Static(custom.reply.clone()),
+                })
+            });
+
+        registry
+    }
+
+    /// Name or alias of a built-in command in any frontend.
+    pub fn is_builtin(name: &str) -> bool {
+        builtin::commands().iter().any(|spec| spec.matches(name))
+    }
+
+    /// Add command, a command with the same name is replaced.
+    pub fn register(&mut self, spec: CommandSpec) {
+        match self.commands.iter_mut().find(|c| c.name == spec.name) {
+            Some(existing) => *existing = spec,
+            None => self.commands.push(spec),
+        }
+    }
+
+    pub fn dialog(&self, name: &str) -> Option<&Dialog> {
+        self.dialogs.iter().find(|dialog| dialog.name == name)
+    }
+
+    pub fn available(&self, frontend: Frontend) -> impl Iterator<Item = &CommandSpec> {
+        self.commands
+            .iter()
+            .filter(move |spec| spec.is_available(frontend))
+    }
+
+    pub fn visible(&self, frontend: Frontend, role: Role) -> impl Iterator<Item = &CommandSpec> {
+        self.commands
+            .iter()
+            .filter(move |spec| spec.is_visible(frontend, role))
+    }
+
+    /// Find command by name or alias, leading slash is optional.
+    pub fn find(&self, name: &str, frontend: Frontend) -> Option<&CommandSpec> {
+        let name = name.trim_start_matches('/');
+
+        self.available(frontend).find(|spec| spec.matches(name))
+    }
+
+    /// Commands whose name or alias starts with `prefix`.
+    pub fn complete(&self, prefix: &str, frontend: Frontend) -> Vec<&CommandSpec> {
+        let prefix = prefix.trim_start_matches('/');
+
+        self.available(frontend)
+            .filter(|spec| {
+                spec.name.starts_with(prefix)
+                    || spec.aliases.iter().any(|alias| alias.starts_with(prefix))
+            })
+            .collect()
+    }
+
+    pub fn parse(
+        &self,
+        input: &str,
+        frontend: Frontend,
+    ) -> Result<(&CommandSpec, Command), CommandError> {
+        let (name, args) = match input.trim().split_once(char::is_whitespace) {
+            Some((name, args)) => (name, args.trim()),
+            None => (input.trim(), ""),
+        };
+        // in group chats telegram sends commands as `/help@bot_name`
+        let name = name.split('@').next().unwrap_or(name);
+
+        let spec = self
+            .find(name, frontend)
+            .ok_or_else(|| CommandError::Unknown(name.to_string()))?;
+
+        let parse = match &spec.action {
+            CommandAction::Builtin { parse, .. } => parse,
+            CommandAction::Static(_) => return Ok((spec, Command::Custom(spec.name.clone()))),
+        };
+
+        let command = parse(args).map_err(|error| match error {
+            ArgumentError::Unexpected => CommandError::UnexpectedArgument {
+                usage: spec.usage_line(),
+            },
+            ArgumentError::Invalid(reason) => CommandError::InvalidArgument {
+                usage: spec.usage_line(),
+                reason,
+            },
+        })?;
+
+        Ok((spec, command))
+    }
+
+    /// Registered names and aliases closest to a mistyped `name`, at most three.
+    pub fn suggest(&self, name: &str, frontend: Frontend, role: Role) -> Vec<String> {
+        let name = name.trim_start_matches('/');
+        let mut candidates: Vec<(usize, &String)> = self
+            .visible(frontend, role)
+            .filter_map(|spec| {
+                std::iter::once(&spec.name)
+                    .chain(spec.aliases.iter())
+                    .filter_map(|known| typo_distance(name, known).map(|d| (d, known)))
+                    .min_by_key(|(distance, _)| *distance)
+            })
+            .collect();
+
+        // stable sort keeps registration order for equal distances
+        candidates.sort_by_key(|(distance, _)| *distance);
+
+        candidates
+            .into_iter()
+            .take(3)
+            .map(|(_, known)| format!("/{}", known))
+            .collect()
+    }
+
+    pub fn unknown_command(&self, name: &str, frontend: Frontend, role: Role) -> Reply {
+        let suggestions = self.suggest(name, frontend, role);
+
+        let text = match suggestions.split_last() {
+            None => {
+                let supported = self
+                    .visible(frontend, role)
+                    .map(|spec| format!("/{}", spec.name))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+
+                format!(
+                    "Unknown command {}. Supported commands: {}",
+                    name, supported
+                )
+            }
+            Some((last, [])) => format!("Unknown command {}. Did you mean {}?", name, last),
+            Some((last, rest)) => format!(
+                "Unknown command {}. Did you mean {} or {}?",
+                name,
+                rest.join(", "),
+                last
+            ),
+        };
+
+        Reply::UnknownCommand { text, suggestions }
+    }
+
+    /// Lines for `/help`, one per command.
+    pub fn help_lines(&self, frontend: Frontend, role: Role) -> Vec<String> {
+        self.visible(frontend, role)
+            .map(|spec| spec.help_line())
+            .collect()
+    }
+}
+
+/// Parse `input` as a command and run its handler. This is the only place where
+/// permissions are checked: admin-only commands look unknown to regular users.
+pub fn dispatch(ctx: &CommandContext, session: &mut dyn Session, input: &str) -> Reply {
+    match ctx.registry.parse(input, ctx.frontend) {
+        Ok((spec, _)) if spec.admin_only && ctx.role() != Role::Admin => {
+            ctx.logger
+                .log_warn(format!("deny admin command /{} to regular user", spec.name).as_str());
+            ctx.registry
+                .unknown_command(&format!("/{}", spec.name), ctx.frontend, Role::User)
+        }
+        Ok((spec, command)) => {
+            ctx.logger
+                .log_debug(format!("handle command /{}: {:?}", spec.name, command).as_str());
+            match &spec.action {
+                CommandAction::Builtin { handler, .. } => handler(ctx, session, command),
+                CommandAction::Static(reply) => Reply::Static(reply.clone()),
+            }
+        }
+        Err(CommandError::Unknown(name)) => {
+            ctx.logger
+                .log_warn(format!("get unknown command: {}", name).as_str());
+            ctx.registry
+                .unknown_command(&name, ctx.frontend, ctx.role())
+        }
+        Err(error) => {
+            let msg = format!("{}", error);
+            ctx.logger.log_warn(&msg);
+            Reply::Text(msg)
+        }
+    }
+}
@@ -0,0 +1,62 @@
+/// Levenshtein distance between two strings, counted in chars.
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+/// Maximum distance at which a name is still considered a typo of the input.
+fn max_distance(input: &str) -> usize {
+    if input.chars().count() <= 3 {
+        1
+    } else {
+        2
+    }
+}
+
+/// Distance between typed name and a known one, `None` if they are too far apart.
+/// Typed prefix of a known name is always a match.
+pub fn typo_distance(input: &str, known: &str) -> Option<usize> {
+    let distance = edit_distance(input, known);
+
+    if distance <= max_distance(input) || (input.chars().count() >= 2 && known.starts_with(input))
+    {
+        Some(distance)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_count_edit_distance() {
+        assert_eq!(edit_distance("repeat", "repeat"), 0);
+        assert_eq!(edit_distance("repet", "repeat"), 1);
+        assert_eq!(edit_distance("rpeaet", "repeat"), 2);
+        assert_eq!(edit_distance("", "help"), 4);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn should_match_only_close_names() {
+        assert_eq!(typo_distance("hlep", "help"), Some(2));
+        assert_eq!(typo_distance("rep", "repeat"), Some(3));
+        assert_eq!(typo_distance("x", "exit"), None);
+        assert_eq!(typo_distance("status", "repeat"), None);
+    }
+}
@@ -0,0 +1,343 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde_yaml::{Mapping, Value};
+
+use super::{layers, ConfigError, ConfigProblem};
+
+/// Files looked for in every directory of the search, in this order.
+const FILE_NAMES: [&str; 4] = ["config.yaml", "config.yml", "config.json", "config.toml"];
+
+/// Format of a config file, chosen by its extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Yaml,
+    Json,
+    Toml,
+}
+
+impl Format {
+    /// YAML for `.yaml`, `.yml` and files without an extension, None for other extensions.
+    pub fn of(path: &str) -> Option<Format> {
+        match Path::new(path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+        {
+            None | Some("yaml") | Some("yml") => Some(Format::Yaml),
+            Some("json") => Some(Format::Json),
+            Some("toml") => Some(Format::Toml),
+            Some(_) => None,
+        }
+    }
+
+    /// Parse interpolated text into a document, an empty YAML file is an empty mapping.
+    pub fn parse(self, content: &str) -> Result<Value, ConfigProblem> {
+        let value = match self {
+            Format::Yaml => {
+                serde_yaml::from_str(content).map_err(|e| ConfigProblem::from_yaml(&e))?
+            }
+            Format::Json => {
+                let value: serde_json::Value = serde_json::from_str(content).map_err(|e| {
+                    ConfigProblem::located(e.to_string(), Some((e.line(), e.column())))
+                })?;
+                serde_yaml::to_value(value).unwrap_or_default()
+            }
+            Format::Toml => {
+                let value: toml::Value = toml::from_str(content).map_err(|e| {
+                    // toml counts lines and columns from 0
+                    let position = e.line_col().map(|(line, column)| (line + 1, column + 1));
+                    ConfigProblem::located(e.to_string(), position)
+                })?;
+                serde_yaml::to_value(value).unwrap_or_default()
+            }
+        };
+
+        match value {
+            Value::Null => Ok(Value::Mapping(Mapping::new())),
+            value => Ok(value),
+        }
+    }
+}
+
+/// Config files read for one config and merged into one document.
+#[derive(Debug)]
+pub struct Layered {
+    pub document: Value,
+    /// Interpolated text of the main file if it is YAML, positions of problems are
+    /// looked for in it.
+    pub content: Option<String>,
+    /// Top-level keys set by files the main one includes, so their values are not the
+    /// ones at their positions in the main file.
+    pub included: Vec<String>,
+    /// Every file which was read, the main one first.
+    pub files: Vec<String>,
+}
+
+/// Path of the config: the given one, `$ECHO_BOT_CONFIG`, then the first file found by
+/// [`candidates`]. A given path is used even if it does not exist, so it is reported.
+pub fn locate(path: Option<String>, env: &[(String, String)]) -> Result<String, ConfigError> {
+    if let Some(path) = path.or_else(|| var(env, "ECHO_BOT_CONFIG")) {
+        return Ok(path);
+    }
+
+    let searched = candidates(env);
+    searched
+        .iter()
+        .find(|path| Path::new(path).is_file())
+        .cloned()
+        .ok_or(ConfigError::NotFound { searched })
+}
+
+/// Files looked for when no path is given: in the current directory,
+/// `$XDG_CONFIG_HOME/echo_bot` (`~/.config/echo_bot` by default) and `/etc/echo_bot`.
+pub fn candidates(env: &[(String, String)]) -> Vec<String> {
+    let config_home = var(env, "XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| var(env, "HOME").map(|home| Path::new(&home).join(".config")));
+    let directories = [
+        None,
+        config_home.map(|home| home.join("echo_bot")),
+        Some(PathBuf::from("/etc/echo_bot")),
+    ];
+
+    directories
+        .iter()
+        .flat_map(|directory| {
+            FILE_NAMES.iter().map(move |name| match directory {
+                Some(directory) => directory.join(name).display().to_string(),
+                None => name.to_string(),
+            })
+        })
+        .collect()
+}
+
+fn var(env: &[(String, String)], name: &str) -> Option<String> {
+    env.iter()
+        .find(|(key, value)| key == name && !value.is_empty())
+        .map(|(_, value)| value.clone())
+}
+
+/// Read the file with its `extends:` and `include:` files. A file is layered on top of the
+/// files it extends, and the files it includes are layered on top of it, so a base file
+/// can be extended by a profile or include one. Paths are relative to the file they are in.
+pub fn read(path: &str, env: &[(String, String)]) -> Result<Layered, ConfigError> {
+    let mut files = vec![];
+    let layer = read_layer(path, env, &mut vec![], &mut files)?;
+
+    Ok(Layered {
+        document: layer.document,
+        content: layer.content,
+        included: layer.included,
+        files,
+    })
+}
+
+struct Layer {
+    document: Value,
+    content: Option<String>,
+    included: Vec<String>,
+}
+
+fn read_layer(
+    path: &str,
+    env: &[(String, String)],
+    chain: &mut Vec<PathBuf>,
+    files: &mut Vec<String>,
+) -> Result<Layer, ConfigError> {
+    let invalid = |message: String| ConfigError::Invalid {
+        path: path.to_string(),
+        problems: vec![ConfigProblem::new(None, message)],
+    };
+
+    let format = Format::of(path).ok_or_else(|| {
+        invalid("unsupported config format, use .yaml, .yml, .json or .toml".to_string())
+    })?;
+    let content = fs::read_to_string(path).map_err(|error| ConfigError::Read {
+        path: path.to_string(),
+        error,
+    })?;
+
+    let canonical = fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+    if chain.contains(&canonical) {
+        return Err(invalid("config extends or includes itself".to_string()));
+    }
+    files.push(path.to_string());
+
+    let content = layers::interpolate(&content, env).map_err(|problems| ConfigError::Invalid {
+        path: path.to_string(),
+        problems,
+    })?;
+    let mut document = format
+        .parse(&content)
+        .map_err(|problem| ConfigError::Invalid {
+            path: path.to_string(),
+            problems: vec![problem],
+        })?;
+    let extends = take_paths(&mut document, "extends", path).map_err(invalid)?;
+    let includes = take_paths(&mut document, "include", path).map_err(invalid)?;
+
+    chain.push(canonical);
+    let mut merged = Value::Mapping(Mapping::new());
+    for base in extends {
+        merge(&mut merged, read_layer(&base, env, chain, files)?.document);
+    }
+    merge(&mut merged, document);
+    let mut included = vec![];
+    for include in includes {
+        let layer = read_layer(&include, env, chain, files)?.document;
+        included.extend(keys(&layer));
+        merge(&mut merged, layer);
+    }
+    chain.pop();
+
+    Ok(Layer {
+        document: merged,
+        content: (format == Format::Yaml).then_some(content),
+        included,
+    })
+}
+
+/// Remove a key with a path or a list of paths, resolved from the directory of the file.
+fn take_paths(document: &mut Value, key: &str, path: &str) -> Result<Vec<String>, String> {
+    let Some(value) = document
+        .as_mapping_mut()
+        .and_then(|mapping| mapping.remove(key))
+    else {
+        return Ok(vec![]);
+    };
+    let paths = match &value {
+        Value::String(path) => vec![path.as_str()],
+        Value::Sequence(items) => items
+            .iter()
+            .map(Value::as_str)
+            .collect::<Option<_>>()
+            .ok_or_else(|| format!("{} must be a path or a list of paths", key))?,
+        _ => return Err(format!("{} must be a path or a list of paths", key)),
+    };
+    let directory = Path::new(path).parent().unwrap_or(Path::new(""));
+
+    Ok(paths
+        .into_iter()
+        .map(|item| directory.join(item).display().to_string())
+        .collect())
+}
+
+fn keys(document: &Value) -> Vec<String> {
+    document
+        .as_mapping()
+        .into_iter()
+        .flat_map(|mapping| mapping.keys())
+        .filter_map(|key| key.as_str().map(str::to_string))
+        .collect()
+}
+
+/// Layer `top` over `base`: mappings are merged key by key, other values are replaced.
+fn merge(base: &mut Value, top: Value) {
+    match (base, top) {
+        (Value::Mapping(base), Value::Mapping(top)) => {
+            // the token and its file are alternatives, the upper file wins
+            for (key, other) in [
+                ("bot_token", "bot_token_file"),
+                ("bot_token_file", "bot_token"),
+            ] {
+                if top.contains_key(key) {
+                    base.remove(other);
+                }
+            }
+            for (key, value) in top {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, top) => *base = top,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+
+    #[test]
+    fn should_layer_profiles_in_any_format() {
+        let dir = env::temp_dir().join(format!("echo_bot_{}_layers", process::id()));
+        fs::create_dir_all(dir.join("profiles")).unwrap();
+        fs::write(
+            dir.join("base.yaml"),
+            "mode: console\nhelp_msg: help\nbot_token: base\nstorage:\n  type: json\n  path: base.json\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("profiles/prod.toml"),
+            "extends = \"../base.yaml\"\nbot_token_file = \"/run/token\"\n\n[storage]\npath = \"prod.json\"\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("config.json"),
+            r#"{"extends": "profiles/prod.toml", "include": ["local.yaml"], "help_msg": "prod help"}"#,
+        )
+        .unwrap();
+        fs::write(dir.join("local.yaml"), "log_level: ${LEVEL:-Info}\n").unwrap();
+
+        let path = dir.join("config.json").display().to_string();
+        let layered = read(&path, &[("LEVEL".to_string(), "Debug".to_string())]).unwrap();
+
+        assert_eq!(
+            layered.document,
+            serde_yaml::from_str::<Value>(
+                "mode: console\nhelp_msg: prod help\nstorage: {type: json, path: prod.json}\n\
+                 bot_token_file: /run/token\nlog_level: Debug"
+            )
+            .unwrap()
+        );
+        assert_eq!(layered.content, None);
+        assert_eq!(layered.included, vec!["log_level"]);
+        assert_eq!(layered.files.len(), 4);
+
+        fs::write(dir.join("base.yaml"), "extends: config.json\n").unwrap();
+        assert!(matches!(
+            read(&path, &[]),
+            Err(ConfigError::Invalid { path, .. }) if path.ends_with("config.json")
+        ));
+        fs::write(dir.join("base.yaml"), "mode: [console\n").unwrap();
+        assert!(matches!(
+            read(&path, &[]),
+            Err(ConfigError::Invalid { path, problems })
+                if path.ends_with("base.yaml") && problems[0].position == Some((2, 1))
+        ));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn should_search_standard_locations_in_order() {
+        let vars = |vars: &[(&str, &str)]| -> Vec<(String, String)> {
+            vars.iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect()
+        };
+
+        let searched = candidates(&vars(&[("HOME", "/home/bot")]));
+        assert_eq!(searched[0], "config.yaml");
+        assert_eq!(searched[4], "/home/bot/.config/echo_bot/config.yaml");
+        assert_eq!(searched[11], "/etc/echo_bot/config.toml");
+
+        let env = vars(&[("XDG_CONFIG_HOME", "/xdg"), ("ECHO_BOT_CONFIG", "env.toml")]);
+        assert_eq!(candidates(&env)[7], "/xdg/echo_bot/config.toml");
+        assert_eq!(locate(None, &env).unwrap(), "env.toml");
+        assert_eq!(
+            locate(Some("flag.json".to_string()), &env).unwrap(),
+            "flag.json"
+        );
+
+        assert_eq!(Format::of("config"), Some(Format::Yaml));
+        assert_eq!(Format::of("config.ini"), None);
+    }
+}
@@ -0,0 +1,268 @@
+use std::fs;
+
+use regex::{Captures, Regex};
+use serde_yaml::{Mapping, Value};
+
+use super::ConfigProblem;
+
+/// Prefix of environment variables which override config keys.
+pub const ENV_PREFIX: &str = "ECHO_BOT_";
+
+/// Environment variables with the prefix which are not config keys.
+const RESERVED_ENV: [&str; 1] = ["ECHO_BOT_CONFIG"];
+
+/// Replace `${VAR}` and `${VAR:-default}` in the text of the file, line by line, so
+/// positions of the rest of the file are kept. The default is used when the variable is
+/// not set or empty, every variable without a value and a default is a problem.
+/// YAML and TOML comments are left as they are.
+pub fn interpolate(content: &str, env: &[(String, String)]) -> Result<String, Vec<ConfigProblem>> {
+    let pattern = Regex::new(r"\$\{([A-Za-z_][A-Za-z0-9_]*)(:-([^}]*))?\}")
+        .expect("valid interpolation regex");
+    let mut problems = vec![];
+
+    let lines: Vec<_> = content
+        .split('\n')
+        .enumerate()
+        .map(|(index, line)| {
+            let (text, comment) = line.split_at(comment_start(line).unwrap_or(line.len()));
+            let text = pattern.replace_all(text, |captures: &Captures| {
+                let name = &captures[1];
+                let value = env
+                    .iter()
+                    .find(|(key, value)| key == name && !value.is_empty())
+                    .map(|(_, value)| value.as_str());
+
+                match (value, captures.get(3)) {
+                    (Some(value), _) => value.to_string(),
+                    (None, Some(default)) => default.as_str().to_string(),
+                    (None, None) => {
+                        let column = captures.get(0).map_or(0, |found| found.start()) + 1;
+                        problems.push(ConfigProblem::new(
+                            Some((index + 1, column)),
+                            format!("environment variable {} is not set", name),
+                        ));
+                        String::new()
+                    }
+                }
+            });
+
+            format!("{}{}", text, comment)
+        })
+        .collect();
+
+    match problems.is_empty() {
+        true => Ok(lines.join("\n")),
+        false => Err(problems),
+    }
+}
+
+/// Byte offset of a `#` comment: at the start of the line or after whitespace, outside of
+/// quoted strings. A quote starts a string only where a value can start, so apostrophes
+/// in plain YAML text do not hide comments.
+fn comment_start(line: &str) -> Option<usize> {
+    let mut quote = None;
+    let mut escaped = false;
+    let mut previous = None;
+
+    for (offset, c) in line.char_indices() {
+        match quote {
+            Some('"') if escaped => escaped = false,
+            Some('"') if c == '\\' => escaped = true,
+            Some(open) if c == open => quote = None,
+            Some(_) => {}
+            None if c == '#' && previous.is_none_or(char::is_whitespace) => return Some(offset),
+            None if (c == '"' || c == '\'')
+                && previous.is_none_or(|p: char| p.is_whitespace() || "[{,:=".contains(p)) =>
+            {
+                quote = Some(c)
+            }
+            None => {}
+        }
+        previous = Some(c);
+    }
+
+    None
+}
+
+/// Apply `ECHO_BOT_<KEY>` variables, nested keys are separated by `__`, e.g.
+/// `ECHO_BOT_STORAGE__PATH`. Returns top-level keys which were changed.
+pub fn apply_env(value: &mut Value, env: &[(String, String)]) -> Vec<String> {
+    env.iter()
+        .filter(|(name, _)| !RESERVED_ENV.contains(&name.as_str()))
+        .filter_map(|(name, raw)| {
+            let key = name.strip_prefix(ENV_PREFIX)?.to_lowercase();
+            let path: Vec<_> = key.split("__").collect();
+            set_key(value, &path, raw);
+            Some(path[0].to_string())
+        })
+        .collect()
+}
+
+/// Apply `--set key=value` flags, nested keys are separated by dots, e.g.
+/// `--set storage.path=bot.db`. Returns top-level keys which were changed.
+pub fn apply_overrides(value: &mut Value, overrides: &[String]) -> Vec<String> {
+    overrides
+        .iter()
+        .filter_map(|item| {
+            let (key, raw) = item.split_once('=')?;
+            let path: Vec<_> = key.trim().split('.').collect();
+            set_key(value, &path, raw);
+            Some(path[0].to_string())
+        })
+        .collect()
+}
+
+/// Set a value given as text: numbers, booleans and `[lists]` keep their type, anything
+/// which would be read as a mapping stays a string, so messages may contain colons.
+fn set_key(value: &mut Value, path: &[&str], raw: &str) {
+    let parsed = match serde_yaml::from_str::<Value>(raw) {
+        Ok(Value::Mapping(_)) | Err(_) => Value::String(raw.to_string()),
+        Ok(parsed) => parsed,
+    };
+
+    // the token and its file are alternatives, the later source wins
+    match path {
+        ["bot_token"] => remove_key(value, "bot_token_file"),
+        ["bot_token_file"] => remove_key(value, "bot_token"),
+        _ => {}
+    }
+
+    let mut target = value;
+    for key in path {
+        if !target.is_mapping() {
+            *target = Value::Mapping(Mapping::new());
+        }
+        target = target
+            .as_mapping_mut()
+            .expect("replaced by a mapping above")
+            .entry(Value::from(*key))
+            .or_insert(Value::Null);
+    }
+
+    *target = parsed;
+}
+
+fn remove_key(value: &mut Value, key: &str) {
+    if let Some(mapping) = value.as_mapping_mut() {
+        mapping.remove(key);
+    }
+}
+
+/// Replace `bot_token_file` with `bot_token` read from it, trailing whitespace of the file
+/// is dropped.
+pub fn read_token_file(value: &mut Value) -> Result<(), ConfigProblem> {
+    let Some(path) = value.get("bot_token_file").cloned() else {
+        return Ok(());
+    };
+    let problem = |message: String| ConfigProblem::new(None, message);
+
+    if value.get("bot_token").is_some() {
+        return Err(problem(
+            "bot_token and bot_token_file are both set, keep one of them".to_string(),
+        ));
+    }
+    let Some(path) = path.as_str() else {
+        return Err(problem("bot_token_file must be a path".to_string()));
+    };
+
+    let token = fs::read_to_string(path)
+        .map_err(|e| problem(format!("cannot read bot_token_file {}: {}", path, e)))?;
+    remove_key(value, "bot_token_file");
+    if let Some(mapping) = value.as_mapping_mut() {
+        mapping.insert(
+            Value::from("bot_token"),
+            Value::from(token.trim_end().to_string()),
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn should_interpolate_variables_with_defaults() {
+        let vars = env(&[("TOKEN", "1:abc"), ("EMPTY", "")]);
+
+        assert_eq!(
+            interpolate(
+                "bot_token: ${TOKEN}\nlog_level: ${LEVEL:-Info}\nhelp_msg: ${EMPTY:-help}",
+                &vars
+            ),
+            Ok("bot_token: 1:abc\nlog_level: Info\nhelp_msg: help".to_string())
+        );
+        assert_eq!(
+            interpolate("mode: console\nhelp_msg: ${HELP} ${EMPTY}", &vars),
+            Err(vec![
+                ConfigProblem::new(
+                    Some((2, 11)),
+                    "environment variable HELP is not set".to_string()
+                ),
+                ConfigProblem::new(
+                    Some((2, 19)),
+                    "environment variable EMPTY is not set".to_string()
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    fn should_not_interpolate_comments() {
+        let vars = env(&[("TOKEN", "1:abc")]);
+
+        assert_eq!(
+            interpolate(
+                "# token is ${NOT_SET}\nbot_token: ${TOKEN} # or ${NOT_SET}\n\
+                 help_msg: \"# ${TOKEN}\" # ${NOT_SET}\nrepeat_msg: don't ${TOKEN} # ${NOT_SET}",
+                &vars
+            ),
+            Ok("# token is ${NOT_SET}\nbot_token: 1:abc # or ${NOT_SET}\n\
+                help_msg: \"# 1:abc\" # ${NOT_SET}\nrepeat_msg: don't 1:abc # ${NOT_SET}"
+                .to_string())
+        );
+        assert_eq!(
+            interpolate("bot_token = '${NOT_SET}' # ${TOKEN}", &vars).unwrap_err()[0].position,
+            Some((1, 14))
+        );
+    }
+
+    #[test]
+    fn should_set_nested_keys_keeping_types() {
+        let mut value: Value =
+            serde_yaml::from_str("bot_token_file: /run/secrets/token\nstorage:\n  type: memory")
+                .unwrap();
+
+        let changed = apply_env(
+            &mut value,
+            &env(&[
+                ("ECHO_BOT_STORAGE__TYPE", "json"),
+                ("ECHO_BOT_STORAGE__PATH", "bot.json"),
+                ("ECHO_BOT_ADMINS", "[1, 2]"),
+                ("ECHO_BOT_BOT_TOKEN", "1:abc"),
+                ("ECHO_BOT_CONFIG", "other.yaml"),
+                ("HOME", "/root"),
+            ]),
+        );
+        apply_overrides(&mut value, &["help_msg=Hi: it is a bot".to_string()]);
+
+        assert_eq!(changed, vec!["storage", "storage", "admins", "bot_token"]);
+        assert_eq!(
+            value,
+            serde_yaml::from_str::<Value>(
+                "storage: {type: json, path: bot.json}\n\
+                 admins: [1, 2]\n\
+                 bot_token: '1:abc'\n\
+                 help_msg: 'Hi: it is a bot'"
+            )
+            .unwrap()
+        );
+    }
+}
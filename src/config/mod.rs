@@ -0,0 +1,758 @@
+mod files;
+mod layers;
+
+use crate::access::AccessConfig;
+use crate::budget::BudgetConfig;
+use crate::commands::CommandRegistry;
+use crate::custom::{AutoReply, CustomCommand, StartConfig};
+use crate::flood::FloodConfig;
+use crate::logger::LogLevel;
+use crate::storage::{CacheConfig, StorageConfig};
+use regex::Regex;
+use serde::Deserialize;
+use serde_yaml::Value;
+use std::{env, fmt::Display};
+
+use files::Format;
+pub use layers::ENV_PREFIX;
+
+/// Keyboard of `/repeat` offers numbers from 1 to this one.
+pub const MAX_KEYBOARD_REPEAT_NUMBER: u8 = 5;
+
+pub struct ConfigBuilder {
+    file_path: Option<String>,
+    layered: Option<files::Layered>,
+    env: Vec<(String, String)>,
+    overrides: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub enum BotMode {
+    #[serde(rename = "telegram")]
+    Telegram,
+    #[serde(rename = "console")]
+    Console,
+}
+
+/// When the telegram offset is committed relative to handling of an update.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DeliveryGuarantee {
+    /// Offset is committed before the update is handled: after a crash the update is lost.
+    #[serde(rename = "at_most_once")]
+    AtMostOnce,
+    /// Offset is committed after the update is handled: after a crash the update is handled again.
+    #[default]
+    #[serde(rename = "at_least_once")]
+    AtLeastOnce,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Config {
+    pub mode: BotMode,
+    pub help_msg: String,
+    pub repeat_msg: String,
+    pub default_repeat_number: u8,
+    /// Answer of users while maintenance is on.
+    #[serde(default = "default_maintenance_msg")]
+    pub maintenance_msg: String,
+    pub log_level: LogLevel,
+    /// Required in telegram mode.
+    pub bot_token: Option<String>,
+    /// Seconds of silence after which an unfinished dialog is dropped.
+    #[serde(default = "default_conversation_timeout")]
+    pub conversation_timeout: u64,
+    #[serde(default)]
+    pub storage: StorageConfig,
+    /// Bounded cache of chat states in front of the storage, no cache if not set.
+    pub cache: Option<CacheConfig>,
+    #[serde(default)]
+    pub delivery_guarantee: DeliveryGuarantee,
+    /// Telegram user ids which can run admin commands in any chat.
+    #[serde(default)]
+    pub admins: Vec<u64>,
+    /// Administrators of a group can run admin commands in that group.
+    #[serde(default)]
+    pub chat_admins: bool,
+    /// Allowlists and denylists of users and chats, extended at runtime by admin commands.
+    #[serde(default)]
+    pub access: AccessConfig,
+    /// Limits of incoming messages per user and per chat, no limits if not set.
+    pub flood_control: Option<FloodConfig>,
+    /// Limits of outgoing messages per chat, admins can override them for single chats.
+    pub outgoing_budget: Option<BudgetConfig>,
+    /// Messages per second sent by a broadcast.
+    #[serde(default = "default_broadcast_rate")]
+    pub broadcast_rate: u32,
+    /// Address of the HTTP health endpoint, e.g. `127.0.0.1:8080`, no endpoint if not set.
+    pub health_addr: Option<String>,
+    pub start: Option<StartConfig>,
+    #[serde(default)]
+    pub custom_commands: Vec<CustomCommand>,
+    #[serde(default)]
+    pub auto_replies: Vec<AutoReply>,
+}
+
+/// Problem found in the config, with its position in the file if it is known.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigProblem {
+    /// Line and column, both start from 1.
+    pub position: Option<(usize, usize)>,
+    pub message: String,
+}
+
+impl ConfigProblem {
+    fn new(position: Option<(usize, usize)>, message: String) -> Self {
+        ConfigProblem { position, message }
+    }
+
+    fn from_yaml(error: &serde_yaml::Error) -> Self {
+        ConfigProblem::located(
+            error.to_string(),
+            error
+                .location()
+                .map(|location| (location.line(), location.column())),
+        )
+    }
+
+    /// Problem of a parser which puts the position at the end of its message.
+    fn located(message: String, position: Option<(usize, usize)>) -> Self {
+        // the position is kept apart, so it is cut from the message
+        let message = match message.rfind(" at line ") {
+            Some(index) if position.is_some() => message[..index].to_string(),
+            _ => message,
+        };
+
+        ConfigProblem::new(position, message)
+    }
+}
+
+impl Display for ConfigProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.position {
+            Some((line, column)) => write!(f, "line {}, column {}: {}", line, column, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read {
+        path: String,
+        error: std::io::Error,
+    },
+    /// Every problem found in the file, not only the first one.
+    Invalid {
+        path: String,
+        problems: Vec<ConfigProblem>,
+    },
+    /// No path was given and no file was found in standard locations.
+    NotFound {
+        searched: Vec<String>,
+    },
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Read { path, error } => {
+                write!(f, "Cannot read config {}: {}", path, error)
+            }
+            ConfigError::Invalid { path, problems } => {
+                write!(f, "Invalid config {}:", path)?;
+                for problem in problems {
+                    write!(f, "\n  {}", problem)?;
+                }
+                Ok(())
+            }
+            ConfigError::NotFound { searched } => write!(
+                f,
+                "Config is not found, give it with --config or put it in one of: {}",
+                searched.join(", ")
+            ),
+        }
+    }
+}
+
+impl ConfigBuilder {
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        ConfigBuilder {
+            file_path: None,
+            layered: None,
+            env: env::vars().collect(),
+            overrides: vec![],
+        }
+    }
+
+    /// Path of the config, known after [`ConfigBuilder::extract_config_body`] if it was
+    /// not given.
+    pub fn file_path(&self) -> String {
+        self.file_path.clone().unwrap_or_default()
+    }
+
+    /// Every file the config was read from, the main one first.
+    pub fn files(&self) -> Vec<String> {
+        self.layered
+            .as_ref()
+            .map_or_else(Vec::new, |layered| layered.files.clone())
+    }
+
+    /// Path of the config file, it is looked for in standard locations if not set.
+    pub fn path(mut self, path: Option<String>) -> Self {
+        if let Some(file_path) = path {
+            self.file_path = Some(file_path);
+        }
+
+        self
+    }
+
+    /// Variables for `${VAR}` and `ECHO_BOT_*` keys, the environment of the process by default.
+    pub fn env(mut self, vars: Vec<(String, String)>) -> Self {
+        self.env = vars;
+
+        self
+    }
+
+    /// `key=value` pairs of `--set` flags, they win over the file and the environment.
+    pub fn overrides(mut self, overrides: Vec<String>) -> Self {
+        self.overrides = overrides;
+
+        self
+    }
+
+    /// Find and read the file with the files it extends and includes, a relative path is
+    /// resolved from the current directory.
+    pub fn extract_config_body(mut self) -> Result<Self, ConfigError> {
+        let path = files::locate(self.file_path.take(), &self.env)?;
+
+        self.layered = Some(files::read(&path, &self.env)?);
+        self.file_path = Some(path);
+
+        Ok(self)
+    }
+
+    pub fn build(self) -> Result<Config, ConfigError> {
+        self.build_with_document().map(|(config, _)| config)
+    }
+
+    /// Config and the document it was read from, with all sources applied.
+    pub fn build_with_document(self) -> Result<(Config, Value), ConfigError> {
+        let result = match self.layered {
+            Some(layered) => apply_sources(
+                layered.document,
+                layered.content.as_deref(),
+                &layered.included,
+                &self.env,
+                &self.overrides,
+            ),
+            None => parse_document("", &self.env, &self.overrides),
+        };
+
+        result.map_err(|problems| ConfigError::Invalid {
+            path: self.file_path.unwrap_or_default(),
+            problems,
+        })
+    }
+
+    pub fn build_default(mode: BotMode) -> Config {
+        Config {
+            mode,
+            help_msg: "help msg".to_string(),
+            repeat_msg: "repeat msg".to_string(),
+            default_repeat_number: 1,
+            bot_token: Some("test".to_string()),
+            maintenance_msg: default_maintenance_msg(),
+            log_level: LogLevel::Debug,
+            conversation_timeout: default_conversation_timeout(),
+            storage: StorageConfig::Memory,
+            cache: None,
+            delivery_guarantee: DeliveryGuarantee::AtLeastOnce,
+            admins: vec![],
+            chat_admins: false,
+            access: AccessConfig::default(),
+            flood_control: None,
+            outgoing_budget: None,
+            broadcast_rate: default_broadcast_rate(),
+            health_addr: None,
+            start: None,
+            custom_commands: vec![],
+            auto_replies: vec![],
+        }
+    }
+}
+
+fn default_conversation_timeout() -> u64 {
+    300
+}
+
+/// Telegram allows about 30 messages per second to different chats.
+fn default_broadcast_rate() -> u32 {
+    20
+}
+
+fn default_maintenance_msg() -> String {
+    "The bot is under maintenance, we'll be right back".to_string()
+}
+
+/// Parse and validate YAML config text, `extends:` and `include:` are left to the builder
+/// which reads files. Sources are applied in order of precedence: defaults of missing keys,
+/// the text, `ECHO_BOT_*` variables, then `--set` overrides.
+///
+/// Problems are sorted by position, a problem found by validation hides the parse error on
+/// the same line. Keys changed by the environment or overrides have no position.
+pub fn parse(
+    content: &str,
+    env: &[(String, String)],
+    overrides: &[String],
+) -> Result<Config, Vec<ConfigProblem>> {
+    parse_document(content, env, overrides).map(|(config, _)| config)
+}
+
+fn parse_document(
+    content: &str,
+    env: &[(String, String)],
+    overrides: &[String],
+) -> Result<(Config, Value), Vec<ConfigProblem>> {
+    let content = layers::interpolate(content, env)?;
+    let value = Format::Yaml
+        .parse(&content)
+        .map_err(|problem| vec![problem])?;
+
+    apply_sources(value, Some(&content), &[], env, overrides)
+}
+
+/// Apply the environment and overrides to the document of the files and validate it.
+/// Positions are looked for in `content`, the text of the main file if it is YAML, except
+/// for keys in `included` which come from other files.
+fn apply_sources(
+    mut value: Value,
+    content: Option<&str>,
+    included: &[String],
+    env: &[(String, String)],
+    overrides: &[String],
+) -> Result<(Config, Value), Vec<ConfigProblem>> {
+    let mut changed = included.to_vec();
+    changed.extend(layers::apply_env(&mut value, env));
+    changed.extend(layers::apply_overrides(&mut value, overrides));
+    let mut problems: Vec<_> = layers::read_token_file(&mut value)
+        .err()
+        .into_iter()
+        .collect();
+
+    let position = |key: &str, item: Option<usize>| match changed.iter().any(|c| c == key) {
+        true => None,
+        false => content.and_then(|content| {
+            item.and_then(|index| item_position(content, key, index))
+                .or_else(|| value_position(content, key))
+        }),
+    };
+    problems.extend(validate(&value, &position));
+
+    let merged = serde_yaml::to_string(&value).unwrap_or_default();
+    let config = match serde_yaml::from_str::<Config>(&merged) {
+        Ok(config) => Some(config),
+        Err(e) => {
+            // positions are known only for the file, the error is looked for there
+            let mut problem = ConfigProblem::from_yaml(&e);
+            problem.position = match content.map(serde_yaml::from_str::<Config>) {
+                Some(Err(e)) if ConfigProblem::from_yaml(&e).message == problem.message => {
+                    ConfigProblem::from_yaml(&e).position
+                }
+                _ => None,
+            };
+            let line = |problem: &ConfigProblem| problem.position.map(|(line, _)| line);
+            if line(&problem).is_none()
+                || problems.iter().all(|known| line(known) != line(&problem))
+            {
+                problems.push(problem);
+            }
+            None
+        }
+    };
+
+    match (config, problems.is_empty()) {
+        (Some(config), true) => Ok((config, value)),
+        _ => {
+            problems.sort_by_key(|problem| problem.position.unwrap_or((usize::MAX, 0)));
+            Err(problems)
+        }
+    }
+}
+
+/// Line and column in the text of the file, both counted from 1.
+type Position = (usize, usize);
+
+/// Rules which types cannot express. They are checked on the raw document, so they are
+/// reported together with a parse error of another key.
+fn validate(
+    value: &Value,
+    position: &dyn Fn(&str, Option<usize>) -> Option<Position>,
+) -> Vec<ConfigProblem> {
+    let mut problems = vec![];
+    let mut problem = |key: &str, item: Option<usize>, message: String| {
+        problems.push(ConfigProblem::new(position(key, item), message))
+    };
+
+    if value.get("mode").and_then(Value::as_str) == Some("telegram") {
+        match value.get("bot_token") {
+            None | Some(Value::Null) => problem(
+                "bot_token",
+                None,
+                "bot_token is required in telegram mode".to_string(),
+            ),
+            Some(Value::String(token)) if !is_bot_token(token) => problem(
+                "bot_token",
+                None,
+                "bot_token must look like 123456789:AAE..., the token given by @BotFather"
+                    .to_string(),
+            ),
+            _ => {}
+        }
+    }
+
+    if let Some(number) = value.get("default_repeat_number").and_then(Value::as_i64) {
+        if !(1..=i64::from(MAX_KEYBOARD_REPEAT_NUMBER)).contains(&number) {
+            problem(
+                "default_repeat_number",
+                None,
+                format!(
+                    "default_repeat_number must be from 1 to {}, the numbers of /repeat keyboard, got {}",
+                    MAX_KEYBOARD_REPEAT_NUMBER, number
+                ),
+            );
+        }
+    }
+
+    for key in ["help_msg", "repeat_msg", "maintenance_msg"] {
+        if value
+            .get(key)
+            .and_then(Value::as_str)
+            .is_some_and(|text| text.trim().is_empty())
+        {
+            problem(key, None, format!("{} cannot be empty", key));
+        }
+    }
+
+    let commands = value.get("custom_commands").and_then(Value::as_sequence);
+    for (index, command) in commands.into_iter().flatten().enumerate() {
+        let name = command.get("name").and_then(Value::as_str).unwrap_or("");
+        if CommandRegistry::is_builtin(name.trim_start_matches('/')) {
+            problem(
+                "custom_commands",
+                Some(index),
+                format!(
+                    "custom command /{} has the name of a built-in command",
+                    name.trim_start_matches('/')
+                ),
+            );
+        }
+    }
+
+    let payloads = value.get("start").and_then(|start| start.get("payloads"));
+    for payload in payloads.and_then(Value::as_sequence).into_iter().flatten() {
+        if payload.get("repeat_number").and_then(Value::as_i64) == Some(0) {
+            problem(
+                "start",
+                None,
+                format!(
+                    "repeat_number of start payload {} must be greater than 0",
+                    payload
+                        .get("payload")
+                        .and_then(Value::as_str)
+                        .unwrap_or("-")
+                ),
+            );
+        }
+    }
+
+    problems
+}
+
+/// Token of the bot API: numeric bot id, a colon and the secret.
+fn is_bot_token(token: &str) -> bool {
+    Regex::new(r"^[0-9]+:[A-Za-z0-9_-]{30,}$")
+        .expect("valid token regex")
+        .is_match(token)
+}
+
+/// Position of the value of a top-level key, found by text as parsed values have none.
+fn value_position(content: &str, key: &str) -> Option<(usize, usize)> {
+    content.lines().enumerate().find_map(|(index, line)| {
+        let rest = line.strip_prefix(key)?.strip_prefix(':')?;
+        let value = rest.trim_start();
+        let column = match value.is_empty() || value.starts_with('#') {
+            true => 1,
+            false => line.len() - value.len() + 1,
+        };
+        Some((index + 1, column))
+    })
+}
+
+/// Position of the value of an item of a top-level block sequence, e.g. of the second
+/// command in `custom_commands`. Items of nested sequences are deeper and not counted.
+fn item_position(content: &str, key: &str, index: usize) -> Option<(usize, usize)> {
+    let (key_line, _) = value_position(content, key)?;
+    let mut indent = None;
+    let mut found = 0;
+
+    for (line_index, line) in content.lines().enumerate().skip(key_line) {
+        let item = line.trim_start();
+        let current = line.len() - item.len();
+        if item.is_empty() || item.starts_with('#') {
+            continue;
+        }
+        // the next top-level key ends the sequence
+        if current == 0 && !item.starts_with('-') {
+            return None;
+        }
+        if !item.starts_with('-') || *indent.get_or_insert(current) != current {
+            continue;
+        }
+        if found == index {
+            let value = item[1..].trim_start();
+            return Some((line_index + 1, line.len() - value.len() + 1));
+        }
+        found += 1;
+    }
+
+    None
+}
+
+/// Shown instead of values of secret keys.
+const REDACTED: &str = "<redacted>";
+
+/// Document with values of secret keys, e.g. `bot_token`, replaced, so it can be shown.
+pub fn redacted(document: &Value) -> Value {
+    match document {
+        Value::Mapping(mapping) => Value::Mapping(
+            mapping
+                .iter()
+                .map(|(key, value)| {
+                    let secret = key.as_str().is_some_and(is_secret) && !value.is_null();
+                    match secret {
+                        true => (key.clone(), Value::from(REDACTED)),
+                        false => (key.clone(), redacted(value)),
+                    }
+                })
+                .collect(),
+        ),
+        Value::Sequence(items) => Value::Sequence(items.iter().map(redacted).collect()),
+        value => value.clone(),
+    }
+}
+
+fn is_secret(key: &str) -> bool {
+    ["token", "secret", "password"]
+        .iter()
+        .any(|word| key.contains(word))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn should_parse_example_config() {
+        let content = fs::read_to_string("config.example.yaml").unwrap();
+        let config = parse(&content, &[], &[]).unwrap();
+
+        assert!(matches!(config.mode, BotMode::Console));
+        assert_eq!(config.admins, vec![123456789]);
+        assert_eq!(config.custom_commands.len(), 2);
+    }
+
+    #[test]
+    fn should_list_every_problem_with_position() {
+        let content = "mode: telegram\n\
+                       help_msg: \" \"\n\
+                       repeat_msg: repeat\n\
+                       default_repeat_number: 9\n\
+                       log_level: Loud\n";
+
+        assert_eq!(
+            parse(content, &[], &[]).unwrap_err(),
+            vec![
+                ConfigProblem::new(Some((2, 11)), "help_msg cannot be empty".to_string()),
+                ConfigProblem::new(
+                    Some((4, 24)),
+                    "default_repeat_number must be from 1 to 5, the numbers of /repeat keyboard, got 9"
+                        .to_string()
+                ),
+                ConfigProblem::new(
+                    Some((5, 12)),
+                    "log_level: unknown variant `Loud`, expected one of `Debug`, `Info`, `Warn`, `Error`"
+                        .to_string()
+                ),
+                ConfigProblem::new(None, "bot_token is required in telegram mode".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn should_check_token_only_in_telegram_mode() {
+        let content =
+            "help_msg: help\nrepeat_msg: repeat\ndefault_repeat_number: 1\nlog_level: Info\n";
+
+        assert!(parse(&format!("mode: console\n{}", content), &[], &[]).is_ok());
+        assert_eq!(
+            parse(
+                &format!("mode: telegram\nbot_token: token\n{}", content),
+                &[],
+                &[]
+            )
+            .unwrap_err(),
+            vec![ConfigProblem::new(
+                Some((2, 12)),
+                "bot_token must look like 123456789:AAE..., the token given by @BotFather"
+                    .to_string()
+            )]
+        );
+        assert!(parse(
+            &format!(
+                "mode: telegram\nbot_token: 123456789:AAEhBP0av28xHmDIx5XnKpFqbOVq1Bp3kVw\n{}",
+                content
+            ),
+            &[],
+            &[]
+        )
+        .is_ok());
+
+        let error = ConfigError::Invalid {
+            path: "config.yaml".to_string(),
+            problems: parse("mode: telgram", &[], &[]).unwrap_err(),
+        };
+        assert_eq!(
+            error.to_string(),
+            "Invalid config config.yaml:\n  \
+             line 1, column 7: mode: unknown variant `telgram`, expected `telegram` or `console`"
+        );
+    }
+
+    #[test]
+    fn should_reject_custom_commands_named_as_builtin() {
+        let content = "mode: console\nhelp_msg: help\nrepeat_msg: repeat\n\
+                       default_repeat_number: 1\nlog_level: Info\n\
+                       custom_commands:\n\
+                       - {name: hours, description: hours, reply: 9-18}\n\
+                       - {name: /help, description: help, reply: hijacked}\n\
+                       - {name: ban, description: ban, reply: banned}\n";
+
+        assert_eq!(
+            parse(content, &[], &[]).unwrap_err(),
+            vec![
+                ConfigProblem::new(
+                    Some((8, 3)),
+                    "custom command /help has the name of a built-in command".to_string()
+                ),
+                ConfigProblem::new(
+                    Some((9, 3)),
+                    "custom command /ban has the name of a built-in command".to_string()
+                ),
+            ]
+        );
+
+        // nested sequences of an item are not items
+        let content = "mode: console\nhelp_msg: help\nrepeat_msg: repeat\n\
+                       default_repeat_number: 1\nlog_level: Info\n\
+                       custom_commands:\n\
+                       \x20 - name: hours\n\
+                       \x20   description: hours\n\
+                       \x20   reply: 9-18\n\
+                       \x20   keyboard:\n\
+                       \x20     - - text: Help\n\
+                       \x20         command: /help\n\
+                       \x20 - name: help\n\
+                       \x20   description: help\n\
+                       \x20   reply: hijacked\n";
+        assert_eq!(
+            parse(content, &[], &[]).unwrap_err(),
+            vec![ConfigProblem::new(
+                Some((13, 5)),
+                "custom command /help has the name of a built-in command".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn should_apply_sources_in_order_of_precedence() {
+        let content = "mode: console\n\
+                       help_msg: ${HELP:-file help}\n\
+                       repeat_msg: repeat\n\
+                       default_repeat_number: 2\n\
+                       log_level: Info\n\
+                       broadcast_rate: 10\n";
+        let env = |vars: &[(&str, &str)]| -> Vec<(String, String)> {
+            vars.iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect()
+        };
+        let overrides = vec!["default_repeat_number=4".to_string()];
+
+        let config = parse(content, &[], &[]).unwrap();
+        assert_eq!(config.conversation_timeout, 300);
+        assert_eq!(config.help_msg, "file help");
+        assert_eq!(config.broadcast_rate, 10);
+
+        let vars = env(&[
+            ("HELP", "env help"),
+            ("ECHO_BOT_BROADCAST_RATE", "15"),
+            ("ECHO_BOT_DEFAULT_REPEAT_NUMBER", "3"),
+            ("ECHO_BOT_CONVERSATION_TIMEOUT", "60"),
+        ]);
+        let config = parse(content, &vars, &overrides).unwrap();
+        assert_eq!(config.conversation_timeout, 60);
+        assert_eq!(config.help_msg, "env help");
+        assert_eq!(config.broadcast_rate, 15);
+        assert_eq!(config.default_repeat_number, 4);
+
+        assert_eq!(
+            parse(
+                content,
+                &env(&[("ECHO_BOT_DEFAULT_REPEAT_NUMBER", "7")]),
+                &[]
+            )
+            .unwrap_err(),
+            vec![ConfigProblem::new(
+                None,
+                "default_repeat_number must be from 1 to 5, the numbers of /repeat keyboard, got 7"
+                    .to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn should_read_token_from_file() {
+        let path = env::temp_dir().join(format!("echo_bot_{}_token", std::process::id()));
+        fs::write(&path, "123456789:AAEhBP0av28xHmDIx5XnKpFqbOVq1Bp3kVw\n").unwrap();
+        let content = format!(
+            "mode: telegram\nbot_token_file: {}\nhelp_msg: help\nrepeat_msg: repeat\n\
+             default_repeat_number: 1\nlog_level: Info\n",
+            path.display()
+        );
+
+        let config = parse(&content, &[], &[]).unwrap();
+        assert_eq!(
+            config.bot_token.as_deref(),
+            Some("123456789:AAEhBP0av28xHmDIx5XnKpFqbOVq1Bp3kVw")
+        );
+
+        let token = vec![("ECHO_BOT_BOT_TOKEN".to_string(), "1:short".to_string())];
+        assert_eq!(
+            parse(&content, &token, &[]).unwrap_err()[0].message,
+            "bot_token must look like 123456789:AAE..., the token given by @BotFather"
+        );
+        assert!(parse(&format!("bot_token: x\n{}", content), &[], &[])
+            .unwrap_err()
+            .iter()
+            .any(|problem| problem.message
+                == "bot_token and bot_token_file are both set, keep one of them"));
+
+        fs::remove_file(&path).unwrap();
+        assert!(parse(&content, &[], &[]).unwrap_err()[0]
+            .message
+            .starts_with("cannot read bot_token_file "));
+    }
+}
@@ -1,51 +1,144 @@
-use crate::commands::{Command, IsCommand};
+use std::cell::RefCell;
+
+use chrono::Utc;
+
+use crate::commands::{
+    dispatch, Command, CommandContext, CommandRegistry, Frontend, IsCommand, Reply, Role,
+};
 use crate::config::Config;
+use crate::conversation;
+use crate::custom::{find_auto_reply, Button, StaticReply};
+use crate::flood::{FloodControl, Verdict};
 use crate::logger::Logger;
+use crate::overrides::ConfigOverrides;
+use crate::reload::ConfigReloader;
+use crate::storage::{ChatState, StateStore};
+use crate::BotError;
 
-struct State {
-    pub repeat_number: Option<u8>,
-    pub is_await_repeat_number: bool,
-}
+/// Console has the only user, its state is stored as a chat with this id.
+const CONSOLE_CHAT_ID: i64 = 0;
+
+pub fn run_bot(
+    config: &Config,
+    store: &mut dyn StateStore,
+    reloader: &mut ConfigReloader,
+    logger: &dyn Logger,
+) -> Result<(), BotError> {
+    let mut config = config.clone();
+    let mut state = store
+        .load_chat(CONSOLE_CHAT_ID)
+        .map_err(BotError::Storage)?;
+    let registry = CommandRegistry::from_config(&config);
+    let mut flood = FloodControl::new();
+
+    loop {
+        reloader
+            .poll(&mut config, store, logger, Utc::now().timestamp())
+            .map_err(BotError::Storage)?;
+
+        let input = get_user_message().map_err(BotError::Console)?;
 
-impl State {
-    fn new() -> Self {
-        State {
-            repeat_number: None,
-            is_await_repeat_number: false,
+        // terminal sends a typed tab as is, so `/re<Tab><Enter>` asks for completion
+        if input.ends_with('\t') && input.is_command() {
+            println!("{}", complete_command(input.trim(), &registry));
+            continue;
         }
-    }
-}
 
-pub fn run_bot(config: &Config, logger: &dyn Logger) -> Result<(), std::io::Error> {
-    let mut state = State::new();
+        let input = input.trim().to_string();
 
-    loop {
-        let input = get_user_message()?;
+        if let Err(warning) = check_flood(&mut flood, &config, logger, Utc::now().timestamp()) {
+            if let Some(warning) = warning {
+                println!("{warning}");
+            }
+            continue;
+        }
 
-        let Some(response) = respond_user(input, &mut state, config, logger) else { break };
+        let previous = state.clone();
+        let response = respond_user(input, &mut state, &config, &registry, store, logger);
+
+        // there is no update to commit, so a change is written at once and Ctrl-C loses nothing
+        if state != previous {
+            store
+                .save_chat(CONSOLE_CHAT_ID, &state)
+                .and_then(|_| store.flush())
+                .map_err(BotError::Storage)?;
+        }
+
+        let Some(response) = response else {
+            break;
+        };
         println!("{response}");
-    };
-    
+    }
+
     Ok(())
 }
 
+/// Input is ignored while the console is muted, the error holds the warning of a new mute.
+fn check_flood(
+    flood: &mut FloodControl,
+    config: &Config,
+    logger: &dyn Logger,
+    now: i64,
+) -> Result<(), Option<String>> {
+    let Some(flood_config) = &config.flood_control else {
+        return Ok(());
+    };
+
+    match flood.check(flood_config, None, CONSOLE_CHAT_ID, now) {
+        Verdict::Allow => Ok(()),
+        Verdict::Mute { seconds } => {
+            logger.log_warn(
+                format!("mute console for {} seconds: too many messages", seconds).as_str(),
+            );
+            Err(Some(flood_config.warning(seconds)))
+        }
+        Verdict::Muted => Err(None),
+    }
+}
+
 fn respond_user(
     input: String,
-    state: &mut State,
+    state: &mut ChatState,
     config: &Config,
+    registry: &CommandRegistry,
+    store: &mut dyn StateStore,
     logger: &dyn Logger,
 ) -> Option<String> {
-    let command = input.parse::<Command>().unwrap();
-
-    if let Command::Exit = command {
-        return None;
-    }
+    let overrides = ConfigOverrides::load(store).unwrap_or_else(|e| {
+        logger.log_error(format!("failed to read config overrides: {}", e).as_str());
+        ConfigOverrides::default()
+    });
+    let file_config = config;
+    let config = &*overrides.apply(file_config);
+    let ctx = CommandContext {
+        config,
+        file_config,
+        registry,
+        frontend: Frontend::Console,
+        chat_id: CONSOLE_CHAT_ID,
+        user_id: None,
+        logger,
+        store: RefCell::new(store),
+        // whoever runs the console operates the bot
+        role: &|| Role::Admin,
+    };
 
-    if state.is_await_repeat_number {
-        return Some(extract_repeat_count(&input, state, logger));
+    if input.is_command() {
+        if let Ok((_, Command::Exit)) = registry.parse(&input, Frontend::Console) {
+            return None;
+        }
     }
 
     if !input.is_command() {
+        if let Some(reply) = conversation::handle_input(&ctx, state, &input) {
+            return render_reply(reply, config);
+        }
+
+        if let Some(reply) = find_auto_reply(&config.auto_replies, &input) {
+            logger.log_info(format!("auto reply to user input: {}", input).as_str());
+            return Some(render_static_reply(reply));
+        }
+
         return Some(construct_repeated_message(
             input.as_str(),
             state,
@@ -54,33 +147,61 @@ fn respond_user(
         ));
     }
 
-    logger.log_debug(format!("handle user command: {:?}", command).as_str());
+    let reply = dispatch(&ctx, state, &input);
+    render_reply(reply, config)
+}
 
-    match command {
-        Command::Help => Some(config.help_msg.clone()),
-        Command::Repeat => {
-            state.is_await_repeat_number = true;
-            Some(format!(
-                "Currently repeat set: {}. {}",
-                state.repeat_number.unwrap_or(config.default_repeat_number),
-                config.repeat_msg
-            ))
+fn render_reply(reply: Reply, config: &Config) -> Option<String> {
+    match reply {
+        Reply::Text(text) => Some(text),
+        Reply::UnknownCommand { text, .. } => Some(text),
+        Reply::Static(reply) => Some(render_static_reply(&reply)),
+        Reply::RepeatPrompt { current } => Some(format!(
+            "Currently repeat set: {}. {}",
+            current, config.repeat_msg
+        )),
+        Reply::RepeatChanged(count) => {
+            Some(format!("Repeat message count currently is: {}", count))
         }
-        Command::Unknown => {
-            let response = format!(
-                "Unknown command: {}. Supported commands: /help, /repeat, /exit",
-                input
-            );
-            logger.log_warn(&response);
-            Some(response)
-        }
-        Command::Exit => unreachable!(),
+        Reply::Choice { text, options } => Some(format!("{}\n{}", text, options.join(" | "))),
+        Reply::Exit => None,
+    }
+}
+
+fn render_static_reply(reply: &StaticReply) -> String {
+    let media = reply
+        .media
+        .iter()
+        .map(|media| format!("[{}: {}]", media.kind.name(), media.file_id));
+    let buttons = reply.keyboard.iter().flatten().map(|button| match button {
+        Button::Command { text, command } => format!("[{}] {}", text, command),
+        Button::Url { text, url } => format!("[{}] {}", text, url),
+    });
+
+    media
+        .chain(reply.text.clone())
+        .chain(buttons)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn complete_command(prefix: &str, registry: &CommandRegistry) -> String {
+    let matches = registry
+        .complete(prefix, Frontend::Console)
+        .into_iter()
+        .map(|spec| spec.usage_line())
+        .collect::<Vec<_>>();
+
+    if matches.is_empty() {
+        format!("No commands start with {}", prefix)
+    } else {
+        matches.join("\n")
     }
 }
 
 fn construct_repeated_message(
     input: &str,
-    state: &mut State,
+    state: &mut ChatState,
     config: &Config,
     logger: &dyn Logger,
 ) -> String {
@@ -89,9 +210,9 @@ fn construct_repeated_message(
     let mut result = input.to_string();
 
     for _ in 0..count - 1 {
-        result.push_str("\n");
+        result.push('\n');
         result.push_str(input);
-    } 
+    }
 
     result
 }
@@ -99,38 +220,17 @@ fn construct_repeated_message(
 fn get_user_message() -> Result<String, std::io::Error> {
     let mut input = String::new();
     std::io::stdin().read_line(&mut input)?;
-    input = input.trim().to_string();
+    input = input.trim_end_matches(['\r', '\n']).to_string();
 
     Ok(input)
 }
 
-fn extract_repeat_count(input: &str, state: &mut State, logger: &dyn Logger) -> String {
-    let error = "Try again input number".to_string();
-    let count: u8 = match input.parse() {
-        Ok(res) => {
-            if res == 0 {
-                logger.log_warn("input number can't be zero");
-                return error;
-            }
-            res
-        }
-        Err(e) => {
-            logger.log_warn(format!("failed parsing: {:?}", e).as_str());
-            return error;
-        }
-    };
-
-    state.repeat_number = Some(count);
-    state.is_await_repeat_number = false;
-
-    format!("Repeat message count currently is: {}", count)
-}
-
 #[cfg(test)]
 mod tests {
     use crate::{
         config::{BotMode, ConfigBuilder},
         logger::LogLevel,
+        storage::{ChatInfo, MemoryStore},
     };
 
     use super::*;
@@ -144,39 +244,65 @@ mod tests {
 
     #[test]
     fn should_success_return_help_msg() {
-        let mut state = State::new();
+        let mut state = ChatState::default();
         let logger = MockLogger::default();
         let input = "/help".to_string();
         let config = ConfigBuilder::build_default(BotMode::Console);
+        let registry = CommandRegistry::new();
+        let mut store = MemoryStore::new();
 
-        let response = respond_user(input, &mut state, &config, &logger);
-        assert_eq!(response, Some("help msg".to_string()));
+        let response = respond_user(input, &mut state, &config, &registry, &mut store, &logger);
+        assert_eq!(
+            response,
+            Some(
+                "help msg\n\n\
+                 /help [command] - print help message, or help for one command\n\
+                 /repeat [number] - show or set how many times each message is repeated\n\
+                 /cancel - cancel current dialog\n\
+                 /exit - stop the bot\n\
+                 /chats - list chats the bot is in\n\
+                 /status - show state of the bot\n\
+                 /ban <user_id|chat_id> - stop answering a user or a chat\n\
+                 /unban <user_id|chat_id> - answer a banned user or chat again\n\
+                 /allow <user_id|chat_id> - add a user or a chat to the allowlist\n\
+                 /budget [chat_id] [<messages> <characters>|reset] - show or set outgoing messages per minute of a chat\n\
+                 /maintenance [on|off] - show or switch maintenance mode, users get a fixed answer in it\n\
+                 /config get [key] | set <key> <value> | diff | reset [key] - show or change help_msg, repeat_msg and default_repeat_number\n\
+                 /audit [chat=<id>] [user=<id>] [action=<prefix>] [limit=<n>] - show who changed settings, newest first\n\
+                 /reload - reload the config file, changes are logged"
+                    .to_string()
+            )
+        );
     }
 
     #[test]
     fn should_return_none_if_provide_exit_command() {
-        let mut state = State::new();
+        let mut state = ChatState::default();
         let logger = MockLogger::default();
         let input = "/exit".to_string();
         let config = ConfigBuilder::build_default(BotMode::Console);
+        let registry = CommandRegistry::new();
+        let mut store = MemoryStore::new();
 
-        let response = respond_user(input, &mut state, &config, &logger);
+        let response = respond_user(input, &mut state, &config, &registry, &mut store, &logger);
         assert_eq!(response, None);
     }
 
     #[test]
     fn should_return_error_if_provided_unknown_command() {
-        let mut state = State::new();
+        let mut state = ChatState::default();
         let logger = MockLogger::default();
         let input = "/unknown".to_string();
         let input_clone = input.clone();
         let config = ConfigBuilder::build_default(BotMode::Console);
+        let registry = CommandRegistry::new();
+        let mut store = MemoryStore::new();
 
-        let response = respond_user(input, &mut state, &config, &logger);
+        let response = respond_user(input, &mut state, &config, &registry, &mut store, &logger);
         assert_eq!(
             response,
             Some(format!(
-                "Unknown command: {}. Supported commands: /help, /repeat, /exit",
+                "Unknown command {}. Supported commands: /help, /repeat, /cancel, /exit, /chats, /status, /ban, /unban, /allow, /budget, /maintenance, /config, /audit, /reload",
                 input_clone
             ))
         );
@@ -184,41 +310,61 @@ mod tests {
 
     #[test]
     fn should_success_repeat_message_with_default_repeat_count() {
-        let mut state = State::new();
+        let mut state = ChatState::default();
         let logger = MockLogger::default();
         let input = "test".to_string();
         let config = ConfigBuilder::build_default(BotMode::Console);
+        let registry = CommandRegistry::new();
+        let mut store = MemoryStore::new();
 
-        let response = respond_user(input, &mut state, &config, &logger);
+        let response = respond_user(input, &mut state, &config, &registry, &mut store, &logger);
         assert_eq!(response, Some("test".to_string()));
     }
 
     #[test]
     fn should_return_error_if_provided_invalid_number() {
-        let mut state = State::new();
+        let mut state = ChatState::default();
         let logger = MockLogger::default();
         let input = "/repeat".to_string();
         let config = ConfigBuilder::build_default(BotMode::Console);
-
-        respond_user(input, &mut state, &config, &logger);
-
-        let response1 = respond_user("0".to_string(), &mut state, &config, &logger);
+        let registry = CommandRegistry::new();
+        let mut store = MemoryStore::new();
+
+        respond_user(input, &mut state, &config, &registry, &mut store, &logger);
+
+        let response1 = respond_user(
+            "0".to_string(),
+            &mut state,
+            &config,
+            &registry,
+            &mut store,
+            &logger,
+        );
         assert_eq!(response1, Some("Try again input number".to_string()));
         assert_eq!(state.repeat_number, None);
 
-        let response2 = respond_user("txt".to_string(), &mut state, &config, &logger);
+        let response2 = respond_user(
+            "txt".to_string(),
+            &mut state,
+            &config,
+            &registry,
+            &mut store,
+            &logger,
+        );
         assert_eq!(response2, Some("Try again input number".to_string()));
         assert_eq!(state.repeat_number, None);
     }
 
     #[test]
     fn should_success_change_repeat_number() {
-        let mut state = State::new();
+        let mut state = ChatState::default();
         let logger = MockLogger::default();
         let input = "/repeat".to_string();
         let config = ConfigBuilder::build_default(BotMode::Console);
+        let registry = CommandRegistry::new();
+        let mut store = MemoryStore::new();
 
-        let response1 = respond_user(input, &mut state, &config, &logger);
+        let response1 = respond_user(input, &mut state, &config, &registry, &mut store, &logger);
         assert_eq!(
             response1,
             Some(format!(
@@ -227,7 +373,14 @@ mod tests {
             ))
         );
 
-        let response2 = respond_user("3".to_string(), &mut state, &config, &logger);
+        let response2 = respond_user(
+            "3".to_string(),
+            &mut state,
+            &config,
+            &registry,
+            &mut store,
+            &logger,
+        );
         assert_eq!(
             response2,
             Some("Repeat message count currently is: 3".to_string())
@@ -237,14 +390,526 @@ mod tests {
 
     #[test]
     fn should_success_repeat_message_after_change_number() {
-        let mut state = State::new();
+        let mut state = ChatState::default();
         let logger = MockLogger::default();
         let input = "/repeat".to_string();
         let config = ConfigBuilder::build_default(BotMode::Console);
-
-        respond_user(input, &mut state, &config, &logger);
-        respond_user("2".to_string(), &mut state, &config, &logger);
-        let response = respond_user("test".to_string(), &mut state, &config, &logger);
+        let registry = CommandRegistry::new();
+        let mut store = MemoryStore::new();
+
+        respond_user(input, &mut state, &config, &registry, &mut store, &logger);
+        respond_user(
+            "2".to_string(),
+            &mut state,
+            &config,
+            &registry,
+            &mut store,
+            &logger,
+        );
+        let response = respond_user(
+            "test".to_string(),
+            &mut state,
+            &config,
+            &registry,
+            &mut store,
+            &logger,
+        );
         assert_eq!(response, Some("test\ntest".to_string()));
     }
+
+    #[test]
+    fn should_success_change_repeat_number_via_argument() {
+        let mut state = ChatState::default();
+        let logger = MockLogger::default();
+        let config = ConfigBuilder::build_default(BotMode::Console);
+        let registry = CommandRegistry::new();
+        let mut store = MemoryStore::new();
+
+        let response = respond_user(
+            "/repeat 3".to_string(),
+            &mut state,
+            &config,
+            &registry,
+            &mut store,
+            &logger,
+        );
+        assert_eq!(
+            response,
+            Some("Repeat message count currently is: 3".to_string())
+        );
+        assert_eq!(state.repeat_number, Some(3));
+        assert_eq!(state.conversation, None);
+
+        let response = respond_user(
+            "test".to_string(),
+            &mut state,
+            &config,
+            &registry,
+            &mut store,
+            &logger,
+        );
+        assert_eq!(response, Some("test\ntest\ntest".to_string()));
+    }
+
+    #[test]
+    fn should_return_usage_if_provided_invalid_argument() {
+        let mut state = ChatState::default();
+        let logger = MockLogger::default();
+        let config = ConfigBuilder::build_default(BotMode::Console);
+        let registry = CommandRegistry::new();
+        let mut store = MemoryStore::new();
+
+        let response = respond_user(
+            "/repeat 0".to_string(),
+            &mut state,
+            &config,
+            &registry,
+            &mut store,
+            &logger,
+        );
+        assert_eq!(
+            response,
+            Some(
+                "Invalid argument: expected a number from 1 to 255, got 0. Usage: /repeat [number]"
+                    .to_string()
+            )
+        );
+        assert_eq!(state.repeat_number, None);
+
+        let response = respond_user(
+            "/exit now".to_string(),
+            &mut state,
+            &config,
+            &registry,
+            &mut store,
+            &logger,
+        );
+        assert_eq!(
+            response,
+            Some("Too many arguments. Usage: /exit".to_string())
+        );
+    }
+
+    #[test]
+    fn should_return_help_for_one_command() {
+        let mut state = ChatState::default();
+        let logger = MockLogger::default();
+        let config = ConfigBuilder::build_default(BotMode::Console);
+        let registry = CommandRegistry::new();
+        let mut store = MemoryStore::new();
+
+        let response = respond_user(
+            "/help repeat".to_string(),
+            &mut state,
+            &config,
+            &registry,
+            &mut store,
+            &logger,
+        );
+        assert_eq!(
+            response,
+            Some(
+                "/repeat [number] - show or set how many times each message is repeated"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn should_return_none_if_provide_exit_alias() {
+        let mut state = ChatState::default();
+        let logger = MockLogger::default();
+        let config = ConfigBuilder::build_default(BotMode::Console);
+        let registry = CommandRegistry::new();
+        let mut store = MemoryStore::new();
+
+        let response = respond_user(
+            "/quit".to_string(),
+            &mut state,
+            &config,
+            &registry,
+            &mut store,
+            &logger,
+        );
+        assert_eq!(response, None);
+    }
+
+    #[test]
+    fn should_complete_command_by_prefix() {
+        let registry = CommandRegistry::new();
+
+        assert_eq!(complete_command("/rep", &registry), "/repeat [number]");
+        assert_eq!(
+            complete_command("/re", &registry),
+            "/repeat [number]\n/reload"
+        );
+        assert_eq!(
+            complete_command("/x", &registry),
+            "No commands start with /x"
+        );
+    }
+
+    #[test]
+    fn should_suggest_closest_command_for_typo() {
+        let mut state = ChatState::default();
+        let logger = MockLogger::default();
+        let config = ConfigBuilder::build_default(BotMode::Console);
+        let registry = CommandRegistry::new();
+        let mut store = MemoryStore::new();
+
+        let response = respond_user(
+            "/repet".to_string(),
+            &mut state,
+            &config,
+            &registry,
+            &mut store,
+            &logger,
+        );
+        assert_eq!(
+            response,
+            Some("Unknown command /repet. Did you mean /repeat?".to_string())
+        );
+
+        let response = respond_user(
+            "/qiut".to_string(),
+            &mut state,
+            &config,
+            &registry,
+            &mut store,
+            &logger,
+        );
+        assert_eq!(
+            response,
+            Some("Unknown command /qiut. Did you mean /quit?".to_string())
+        );
+    }
+
+    #[test]
+    fn should_answer_custom_command_and_auto_reply_before_echo() {
+        let mut state = ChatState::default();
+        let logger = MockLogger::default();
+        let config: Config = serde_yaml::from_str(
+            r#"
+            mode: console
+            help_msg: help
+            repeat_msg: repeat
+            default_repeat_number: 2
+            log_level: Info
+            custom_commands:
+              - name: hours
+                description: opening hours
+                reply: "We are open 9-18"
+                keyboard:
+                  - - text: Help
+                      command: /help
+              - name: help
+                description: not a built-in
+                reply: hijacked
+            auto_replies:
+              - regex: "^(hi|hello)\\b"
+                reply: Hello there!
+            "#,
+        )
+        .unwrap();
+        let registry = CommandRegistry::from_config(&config);
+        let mut store = MemoryStore::new();
+
+        let response = respond_user(
+            "/hours".to_string(),
+            &mut state,
+            &config,
+            &registry,
+            &mut store,
+            &logger,
+        );
+        assert_eq!(response, Some("We are open 9-18\n[Help] /help".to_string()));
+
+        // a custom command cannot replace a built-in one
+        let response = respond_user(
+            "/help".to_string(),
+            &mut state,
+            &config,
+            &registry,
+            &mut store,
+            &logger,
+        );
+        assert!(response.is_some_and(|help| help.starts_with("help")));
+
+        let response = respond_user(
+            "Hello bot".to_string(),
+            &mut state,
+            &config,
+            &registry,
+            &mut store,
+            &logger,
+        );
+        assert_eq!(response, Some("Hello there!".to_string()));
+
+        let response = respond_user(
+            "hilarious".to_string(),
+            &mut state,
+            &config,
+            &registry,
+            &mut store,
+            &logger,
+        );
+        assert_eq!(response, Some("hilarious\nhilarious".to_string()));
+    }
+
+    #[test]
+    fn should_cancel_repeat_dialog() {
+        let mut state = ChatState::default();
+        let logger = MockLogger::default();
+        let config = ConfigBuilder::build_default(BotMode::Console);
+        let registry = CommandRegistry::new();
+        let mut store = MemoryStore::new();
+
+        respond_user(
+            "/repeat".to_string(),
+            &mut state,
+            &config,
+            &registry,
+            &mut store,
+            &logger,
+        );
+        assert!(state.conversation.is_some());
+
+        let response = respond_user(
+            "/cancel".to_string(),
+            &mut state,
+            &config,
+            &registry,
+            &mut store,
+            &logger,
+        );
+        assert_eq!(
+            response,
+            Some("Cancelled. Send me any message and I will repeat it".to_string())
+        );
+        assert_eq!(state.conversation, None);
+
+        let response = respond_user(
+            "3".to_string(),
+            &mut state,
+            &config,
+            &registry,
+            &mut store,
+            &logger,
+        );
+        assert_eq!(response, Some("3".to_string()));
+
+        let response = respond_user(
+            "/cancel".to_string(),
+            &mut state,
+            &config,
+            &registry,
+            &mut store,
+            &logger,
+        );
+        assert_eq!(response, Some("Nothing to cancel".to_string()));
+    }
+
+    #[test]
+    fn should_return_to_echo_mode_after_dialog_expired() {
+        let mut state = ChatState::default();
+        let logger = MockLogger::default();
+        let config = ConfigBuilder::build_default(BotMode::Console);
+        let registry = CommandRegistry::new();
+        let mut store = MemoryStore::new();
+
+        respond_user(
+            "/repeat".to_string(),
+            &mut state,
+            &config,
+            &registry,
+            &mut store,
+            &logger,
+        );
+        state.conversation.as_mut().unwrap().expires_at -= config.conversation_timeout as i64;
+
+        let response = respond_user(
+            "3".to_string(),
+            &mut state,
+            &config,
+            &registry,
+            &mut store,
+            &logger,
+        );
+        assert_eq!(response, Some("3".to_string()));
+        assert_eq!(state.conversation, None);
+        assert_eq!(state.repeat_number, None);
+    }
+
+    #[test]
+    fn should_list_known_chats() {
+        let mut state = ChatState::default();
+        let logger = MockLogger::default();
+        let config = ConfigBuilder::build_default(BotMode::Console);
+        let registry = CommandRegistry::new();
+        let mut store = MemoryStore::new();
+
+        let response = respond_user(
+            "/chats".to_string(),
+            &mut state,
+            &config,
+            &registry,
+            &mut store,
+            &logger,
+        );
+        assert_eq!(response, Some("No known chats".to_string()));
+
+        let chat = |kind: &str, last_activity_at| ChatState {
+            info: Some(ChatInfo {
+                last_activity_at: Some(last_activity_at),
+                ..ChatInfo::new(kind.to_string(), None)
+            }),
+            ..Default::default()
+        };
+        store.save_chat(1, &chat("private", 0)).unwrap();
+        store.save_chat(-2, &chat("group", 60)).unwrap();
+
+        let response = respond_user(
+            "/chats".to_string(),
+            &mut state,
+            &config,
+            &registry,
+            &mut store,
+            &logger,
+        );
+        assert_eq!(
+            response,
+            Some(
+                "Known chats: 2, bot is in 2\n\
+                 -2 group - member, joined -, last activity 1970-01-01T00:01:00+00:00\n\
+                 1 private - member, joined -, last activity 1970-01-01T00:00:00+00:00"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn should_ignore_input_while_muted_for_flood() {
+        let logger = MockLogger::default();
+        let mut config = ConfigBuilder::build_default(BotMode::Console);
+        config.flood_control =
+            Some(serde_yaml::from_str("chat: {capacity: 1, per_minute: 1}\nmute: 30").unwrap());
+        let mut flood = FloodControl::new();
+
+        assert_eq!(check_flood(&mut flood, &config, &logger, 0), Ok(()));
+        assert_eq!(
+            check_flood(&mut flood, &config, &logger, 0),
+            Err(Some(
+                "Too many messages, I will not answer for 30 seconds".to_string()
+            ))
+        );
+        assert_eq!(check_flood(&mut flood, &config, &logger, 10), Err(None));
+        assert_eq!(check_flood(&mut flood, &config, &logger, 90), Ok(()));
+    }
+
+    #[test]
+    fn should_override_config_keys_at_runtime_and_audit_changes() {
+        let mut state = ChatState::default();
+        let logger = MockLogger::default();
+        let config = ConfigBuilder::build_default(BotMode::Console);
+        let registry = CommandRegistry::new();
+        let mut store = MemoryStore::new();
+        let mut respond = |input: &str| {
+            respond_user(
+                input.to_string(),
+                &mut state,
+                &config,
+                &registry,
+                &mut store,
+                &logger,
+            )
+            .unwrap()
+        };
+
+        assert_eq!(
+            respond("/config set default_repeat_number 0"),
+            "Invalid argument: default_repeat_number must be a number from 1 to 5, got 0. \
+             Usage: /config get [key] | set <key> <value> | diff | reset [key]"
+        );
+        assert!(respond("/config set log_level Debug")
+            .starts_with("Invalid argument: log_level cannot be changed at runtime"));
+
+        assert_eq!(
+            respond("/config set default_repeat_number 2"),
+            "default_repeat_number is set to 2"
+        );
+        assert_eq!(
+            respond("/config set help_msg new help"),
+            "help_msg is set to \"new help\""
+        );
+        assert_eq!(respond("hi"), "hi\nhi");
+        assert!(respond("/help").starts_with("new help\n\n/help"));
+        assert_eq!(
+            respond("/config get help_msg"),
+            "help_msg = \"new help\" (file: \"help msg\")"
+        );
+        assert_eq!(
+            respond("/config diff"),
+            "help_msg: \"help msg\" -> \"new help\"\ndefault_repeat_number: 1 -> 2"
+        );
+
+        assert_eq!(
+            respond("/config reset"),
+            "help_msg is reset to \"help msg\"\ndefault_repeat_number is reset to 1"
+        );
+        assert_eq!(
+            respond("/config diff"),
+            "No overrides, config file is used as is"
+        );
+        assert_eq!(respond("hi"), "hi");
+
+        let audit: Vec<_> = store
+            .audit_entries()
+            .unwrap()
+            .into_iter()
+            .map(|entry| (entry.action, entry.old.to_string(), entry.new.to_string()))
+            .collect();
+        assert_eq!(
+            audit,
+            [
+                ("config.default_repeat_number", "1", "2"),
+                ("config.help_msg", "\"help msg\"", "\"new help\""),
+                ("config.help_msg", "\"new help\"", "\"help msg\""),
+                ("config.default_repeat_number", "2", "1"),
+            ]
+            .map(|(action, old, new)| (
+                action.to_string(),
+                old.to_string(),
+                new.to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn should_request_config_reload() {
+        let mut state = ChatState::default();
+        let logger = MockLogger::default();
+        let config = ConfigBuilder::build_default(BotMode::Console);
+        let registry = CommandRegistry::new();
+        let mut store = MemoryStore::new();
+
+        assert_eq!(
+            respond_user(
+                "/reload".to_string(),
+                &mut state,
+                &config,
+                &registry,
+                &mut store,
+                &logger,
+            ),
+            Some(
+                "Config is reloaded before the next update, changed keys are logged \
+                 and kept in /audit"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            store.value("reload").unwrap(),
+            Some(serde_json::Value::Bool(true))
+        );
+    }
 }
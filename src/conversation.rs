@@ -0,0 +1,151 @@
+use std::collections::BTreeMap;
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use crate::commands::{CommandContext, Reply, Session};
+
+/// Active dialog of one chat.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Conversation {
+    pub dialog: String,
+    pub step: String,
+    /// Unix timestamp in seconds, after it the chat returns to echo mode.
+    pub expires_at: i64,
+    /// Values collected by previous steps.
+    pub data: BTreeMap<String, String>,
+}
+
+impl Conversation {
+    fn new(dialog: &str, step: &str, timeout: u64) -> Self {
+        let mut conversation = Conversation {
+            dialog: dialog.to_string(),
+            step: step.to_string(),
+            expires_at: 0,
+            data: BTreeMap::new(),
+        };
+        conversation.prolong(timeout);
+
+        conversation
+    }
+
+    fn prolong(&mut self, timeout: u64) {
+        self.expires_at = Utc::now().timestamp() + timeout as i64;
+    }
+
+    pub fn is_expired(&self) -> bool {
+        Utc::now().timestamp() >= self.expires_at
+    }
+}
+
+pub enum Transition {
+    /// Go to the step with this name and send its prompt.
+    Next(&'static str),
+    /// Input is not valid, stay on the same step and send the message.
+    Retry(String),
+    Finish(Reply),
+}
+
+pub type StepPrompt = fn(&CommandContext, &dyn Session, &Conversation) -> Reply;
+pub type StepHandler = fn(&CommandContext, &mut dyn Session, &mut Conversation, &str) -> Transition;
+
+pub struct Step {
+    pub name: &'static str,
+    pub prompt: StepPrompt,
+    pub handle: StepHandler,
+}
+
+/// Finite state machine of a multi-step dialog, the first step is the initial one.
+pub struct Dialog {
+    pub name: &'static str,
+    pub steps: Vec<Step>,
+}
+
+impl Dialog {
+    pub fn step(&self, name: &str) -> Option<&Step> {
+        self.steps.iter().find(|step| step.name == name)
+    }
+}
+
+/// Start dialog in the chat, replaces any active one. Returns prompt of the first step.
+pub fn start(ctx: &CommandContext, session: &mut dyn Session, dialog: &str) -> Reply {
+    let dialog = ctx
+        .registry
+        .dialog(dialog)
+        .unwrap_or_else(|| panic!("dialog {} is not registered", dialog));
+    let step = &dialog.steps[0];
+    let conversation = Conversation::new(dialog.name, step.name, ctx.config.conversation_timeout);
+
+    ctx.logger
+        .log_debug(format!("start dialog: {}", dialog.name).as_str());
+
+    let reply = (step.prompt)(ctx, session, &conversation);
+    session.set_conversation(Some(conversation));
+
+    reply
+}
+
+/// Pass user input to the active dialog. `None` means there is no active dialog
+/// (or it is expired) and the input should be handled as usual.
+pub fn handle_input(ctx: &CommandContext, session: &mut dyn Session, input: &str) -> Option<Reply> {
+    let mut conversation = session.conversation()?;
+
+    if conversation.is_expired() {
+        ctx.logger.log_info(
+            format!(
+                "dialog {} expired, return to echo mode",
+                conversation.dialog
+            )
+            .as_str(),
+        );
+        session.set_conversation(None);
+        return None;
+    }
+
+    let Some(dialog) = ctx.registry.dialog(&conversation.dialog) else {
+        ctx.logger
+            .log_warn(format!("unknown dialog: {}", conversation.dialog).as_str());
+        session.set_conversation(None);
+        return None;
+    };
+    let Some(step) = dialog.step(&conversation.step) else {
+        ctx.logger
+            .log_warn(format!("unknown dialog step: {}", conversation.step).as_str());
+        session.set_conversation(None);
+        return None;
+    };
+
+    let reply = match (step.handle)(ctx, session, &mut conversation, input) {
+        Transition::Next(name) => {
+            let next = dialog
+                .step(name)
+                .unwrap_or_else(|| panic!("dialog {} has no step {}", dialog.name, name));
+            conversation.step = name.to_string();
+            conversation.prolong(ctx.config.conversation_timeout);
+            let reply = (next.prompt)(ctx, session, &conversation);
+            session.set_conversation(Some(conversation));
+            reply
+        }
+        Transition::Retry(msg) => {
+            conversation.prolong(ctx.config.conversation_timeout);
+            session.set_conversation(Some(conversation));
+            Reply::Text(msg)
+        }
+        Transition::Finish(reply) => {
+            session.set_conversation(None);
+            reply
+        }
+    };
+
+    Some(reply)
+}
+
+/// Drop active dialog, returns `false` if there was none.
+pub fn cancel(session: &mut dyn Session) -> bool {
+    let is_active = session
+        .conversation()
+        .is_some_and(|conversation| !conversation.is_expired());
+    session.set_conversation(None);
+
+    is_active
+}
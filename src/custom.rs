@@ -0,0 +1,163 @@
+use regex::Regex;
+use serde::{Deserialize, Deserializer, Serialize};
+
+/// Message defined in config: text, media or both (then text is a caption).
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct StaticReply {
+    #[serde(rename = "reply")]
+    pub text: Option<String>,
+    pub media: Option<Media>,
+    #[serde(default)]
+    pub keyboard: Vec<Vec<Button>>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Media {
+    #[serde(rename = "type")]
+    pub kind: MediaKind,
+    pub file_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaKind {
+    #[serde(rename = "photo")]
+    Photo,
+    #[serde(rename = "video")]
+    Video,
+    #[serde(rename = "document")]
+    Document,
+}
+
+impl MediaKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            MediaKind::Photo => "photo",
+            MediaKind::Video => "video",
+            MediaKind::Document => "document",
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum Button {
+    /// Runs the command when pressed.
+    Command {
+        text: String,
+        command: String,
+    },
+    Url {
+        text: String,
+        url: String,
+    },
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct CustomCommand {
+    #[serde(deserialize_with = "deserialize_command_name")]
+    pub name: String,
+    pub description: String,
+    #[serde(flatten)]
+    pub reply: StaticReply,
+}
+
+/// Welcome flow of `/start`, the flattened reply is the welcome message.
+#[derive(Deserialize, Debug, Clone)]
+pub struct StartConfig {
+    #[serde(flatten)]
+    pub welcome: StaticReply,
+    #[serde(default)]
+    pub payloads: Vec<StartPayload>,
+}
+
+/// Reply for a deep link `t.me/<bot>?start=<payload>`.
+#[derive(Deserialize, Debug, Clone)]
+pub struct StartPayload {
+    pub payload: String,
+    /// Preset of the chat repeat number.
+    pub repeat_number: Option<u8>,
+    #[serde(flatten)]
+    pub reply: StaticReply,
+}
+
+/// Reply sent instead of echo when a text message matches any keyword or the regex.
+#[derive(Deserialize, Debug, Clone)]
+pub struct AutoReply {
+    #[serde(default)]
+    pub keywords: Vec<String>,
+    #[serde(default, deserialize_with = "deserialize_regex")]
+    pub regex: Option<Regex>,
+    #[serde(flatten)]
+    pub reply: StaticReply,
+}
+
+impl AutoReply {
+    pub fn matches(&self, text: &str) -> bool {
+        let text = text.to_lowercase();
+
+        self.keywords
+            .iter()
+            .any(|keyword| contains_word(&text, &keyword.to_lowercase()))
+            || self
+                .regex
+                .as_ref()
+                .is_some_and(|regex| regex.is_match(&text))
+    }
+}
+
+/// First auto reply in config order which matches `text`.
+pub fn find_auto_reply<'a>(auto_replies: &'a [AutoReply], text: &str) -> Option<&'a StaticReply> {
+    auto_replies
+        .iter()
+        .find(|auto_reply| auto_reply.matches(text))
+        .map(|auto_reply| &auto_reply.reply)
+}
+
+/// Case is expected to be normalized by caller.
+fn contains_word(text: &str, word: &str) -> bool {
+    if word.is_empty() {
+        return false;
+    }
+
+    text.match_indices(word).any(|(start, _)| {
+        let before = text[..start].chars().next_back();
+        let after = text[start + word.len()..].chars().next();
+
+        !before.is_some_and(char::is_alphanumeric) && !after.is_some_and(char::is_alphanumeric)
+    })
+}
+
+fn deserialize_regex<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Regex>, D::Error> {
+    let Some(pattern) = Option::<String>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+
+    // auto replies are matched against lowercased text
+    Regex::new(&format!("(?i){}", pattern))
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+fn deserialize_command_name<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<String, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    let name = name.trim_start_matches('/').to_string();
+
+    // the same rules as telegram applies in setMyCommands
+    let is_valid = (1..=32).contains(&name.len())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+
+    if is_valid {
+        Ok(name)
+    } else {
+        Err(serde::de::Error::custom(format!(
+            "invalid command name `{}`: use 1-32 lowercase letters, digits or underscores",
+            name
+        )))
+    }
+}
@@ -0,0 +1,293 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+/// Buckets are pruned when more senders than this are tracked, full buckets of senders
+/// without recent offences are dropped first.
+const TRACKED_LIMIT: usize = 10_000;
+
+/// Token bucket: a burst of `capacity` messages, then `per_minute` messages per minute.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct BucketConfig {
+    pub capacity: u32,
+    pub per_minute: u32,
+}
+
+/// Limits of incoming messages and button presses. A sender who runs out of tokens is
+/// warned once and ignored for `mute` seconds, every next offence doubles the mute.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct FloodConfig {
+    #[serde(default = "default_user_bucket")]
+    pub user: BucketConfig,
+    #[serde(default = "default_chat_bucket")]
+    pub chat: BucketConfig,
+    /// Seconds of the first mute.
+    #[serde(default = "default_mute")]
+    pub mute: i64,
+    #[serde(default = "default_max_mute")]
+    pub max_mute: i64,
+    /// Seconds without offences after which the mute is back to `mute`.
+    #[serde(default = "default_forgive_after")]
+    pub forgive_after: i64,
+    /// Sent when a sender is muted, `{seconds}` is replaced with the mute duration.
+    #[serde(default = "default_warning")]
+    pub warning: String,
+}
+
+impl FloodConfig {
+    pub fn warning(&self, seconds: i64) -> String {
+        self.warning.replace("{seconds}", &seconds.to_string())
+    }
+}
+
+fn default_user_bucket() -> BucketConfig {
+    BucketConfig {
+        capacity: 10,
+        per_minute: 30,
+    }
+}
+
+fn default_chat_bucket() -> BucketConfig {
+    BucketConfig {
+        capacity: 30,
+        per_minute: 60,
+    }
+}
+
+fn default_mute() -> i64 {
+    60
+}
+
+fn default_max_mute() -> i64 {
+    3600
+}
+
+fn default_forgive_after() -> i64 {
+    3600
+}
+
+fn default_warning() -> String {
+    "Too many messages, I will not answer for {seconds} seconds".to_string()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Sender {
+    User(u64),
+    Chat(i64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Allow,
+    /// The sender ran out of tokens just now and is muted, warn them once.
+    Mute {
+        seconds: i64,
+    },
+    /// The sender is still muted, ignore the update silently.
+    Muted,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled_at: i64,
+    muted_until: i64,
+    /// Offences since the last forgiveness, each doubles the mute.
+    offences: u32,
+    offended_at: i64,
+}
+
+impl Bucket {
+    fn new(config: &BucketConfig, now: i64) -> Self {
+        Bucket {
+            tokens: config.capacity as f64,
+            refilled_at: now,
+            muted_until: 0,
+            offences: 0,
+            offended_at: 0,
+        }
+    }
+
+    fn refill(&mut self, config: &BucketConfig, now: i64) {
+        let elapsed = (now - self.refilled_at).max(0) as f64;
+
+        self.tokens =
+            (self.tokens + elapsed * config.per_minute as f64 / 60.0).min(config.capacity as f64);
+        self.refilled_at = now;
+    }
+
+    fn mute(&mut self, config: &FloodConfig, now: i64) -> i64 {
+        if now - self.offended_at >= config.forgive_after {
+            self.offences = 0;
+        }
+
+        let seconds = (config.mute << self.offences.min(16)).min(config.max_mute);
+        self.offences += 1;
+        self.offended_at = now;
+        self.muted_until = now + seconds;
+
+        seconds
+    }
+
+    /// Nothing to remember: full, not muted and offences are forgiven.
+    fn is_idle(&self, config: &FloodConfig, bucket: &BucketConfig, now: i64) -> bool {
+        let elapsed = (now - self.refilled_at).max(0) as f64;
+
+        self.muted_until <= now
+            && now - self.offended_at >= config.forgive_after
+            && self.tokens + elapsed * bucket.per_minute as f64 / 60.0 >= bucket.capacity as f64
+    }
+}
+
+/// Flood control state of a frontend, kept in memory: a restart forgives everyone.
+#[derive(Debug, Default)]
+pub struct FloodControl {
+    buckets: HashMap<Sender, Bucket>,
+}
+
+impl FloodControl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take a token from the user and the chat buckets. A muted user or chat is ignored
+    /// without spending tokens of the other one.
+    pub fn check(
+        &mut self,
+        config: &FloodConfig,
+        user_id: Option<u64>,
+        chat_id: i64,
+        now: i64,
+    ) -> Verdict {
+        if self.buckets.len() > TRACKED_LIMIT {
+            self.prune(config, now);
+        }
+
+        let senders = user_id
+            .map(|user_id| (Sender::User(user_id), &config.user))
+            .into_iter()
+            .chain([(Sender::Chat(chat_id), &config.chat)]);
+
+        let mut buckets = vec![];
+        for (sender, bucket_config) in senders {
+            let bucket = self
+                .buckets
+                .remove(&sender)
+                .unwrap_or_else(|| Bucket::new(bucket_config, now));
+            buckets.push((sender, bucket_config, bucket));
+        }
+
+        let verdict = if buckets
+            .iter()
+            .any(|(_, _, bucket)| bucket.muted_until > now)
+        {
+            Verdict::Muted
+        } else {
+            buckets
+                .iter_mut()
+                .for_each(|(_, bucket_config, bucket)| bucket.refill(bucket_config, now));
+
+            match buckets
+                .iter_mut()
+                .find(|(_, _, bucket)| bucket.tokens < 1.0)
+            {
+                Some((_, _, bucket)) => Verdict::Mute {
+                    seconds: bucket.mute(config, now),
+                },
+                None => {
+                    buckets
+                        .iter_mut()
+                        .for_each(|(_, _, bucket)| bucket.tokens -= 1.0);
+                    Verdict::Allow
+                }
+            }
+        };
+
+        for (sender, _, bucket) in buckets {
+            self.buckets.insert(sender, bucket);
+        }
+
+        verdict
+    }
+
+    fn prune(&mut self, config: &FloodConfig, now: i64) {
+        self.buckets.retain(|sender, bucket| {
+            let bucket_config = match sender {
+                Sender::User(_) => &config.user,
+                Sender::Chat(_) => &config.chat,
+            };
+
+            !bucket.is_idle(config, bucket_config, now)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> FloodConfig {
+        FloodConfig {
+            user: BucketConfig {
+                capacity: 2,
+                per_minute: 60,
+            },
+            chat: BucketConfig {
+                capacity: 3,
+                per_minute: 60,
+            },
+            mute: 10,
+            max_mute: 25,
+            forgive_after: 100,
+            warning: default_warning(),
+        }
+    }
+
+    #[test]
+    fn should_mute_user_after_burst_and_refill_tokens_over_time() {
+        let config = config();
+        let mut flood = FloodControl::new();
+
+        assert_eq!(flood.check(&config, Some(1), 1, 0), Verdict::Allow);
+        assert_eq!(flood.check(&config, Some(1), 1, 0), Verdict::Allow);
+        assert_eq!(
+            flood.check(&config, Some(1), 1, 0),
+            Verdict::Mute { seconds: 10 }
+        );
+        assert_eq!(flood.check(&config, Some(1), 1, 9), Verdict::Muted);
+        assert_eq!(flood.check(&config, Some(1), 1, 10), Verdict::Allow);
+    }
+
+    #[test]
+    fn should_limit_chat_with_many_users() {
+        let config = config();
+        let mut flood = FloodControl::new();
+
+        (1..=3).for_each(|user_id| {
+            assert_eq!(flood.check(&config, Some(user_id), -1, 0), Verdict::Allow)
+        });
+
+        assert_eq!(
+            flood.check(&config, Some(4), -1, 0),
+            Verdict::Mute { seconds: 10 }
+        );
+        assert_eq!(flood.check(&config, Some(1), -1, 5), Verdict::Muted);
+        assert_eq!(flood.check(&config, Some(1), -2, 5), Verdict::Allow);
+    }
+
+    #[test]
+    fn should_double_mute_of_repeat_offenders_until_forgiven() {
+        let config = config();
+        let mut flood = FloodControl::new();
+        let mut offend = |now| {
+            (0..3)
+                .map(|_| flood.check(&config, Some(1), 1, now))
+                .find(|verdict| *verdict != Verdict::Allow)
+        };
+
+        assert_eq!(offend(0), Some(Verdict::Mute { seconds: 10 }));
+        assert_eq!(offend(10), Some(Verdict::Mute { seconds: 20 }));
+        assert_eq!(offend(30), Some(Verdict::Mute { seconds: 25 }));
+        assert_eq!(offend(200), Some(Verdict::Mute { seconds: 10 }));
+    }
+}
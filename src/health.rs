@@ -0,0 +1,110 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, AtomicI64, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+/// How long a client may take to send its request, the endpoint answers one at a time.
+const READ_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// State shown by the health endpoint, updated by the polling loop.
+#[derive(Debug, Default)]
+pub struct Health {
+    maintenance: AtomicBool,
+    /// Unix timestamp of the last successful poll of updates, 0 before the first one.
+    last_poll_at: AtomicI64,
+}
+
+impl Health {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_maintenance(&self, enabled: bool) {
+        self.maintenance.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn set_last_poll_at(&self, now: i64) {
+        self.last_poll_at.store(now, Ordering::Relaxed);
+    }
+
+    fn body(&self) -> String {
+        serde_json::json!({
+            "status": "ok",
+            "maintenance": self.maintenance.load(Ordering::Relaxed),
+            "last_poll_at": self.last_poll_at.load(Ordering::Relaxed),
+        })
+        .to_string()
+    }
+}
+
+/// Answer every request on the listener with the health state as JSON, in a background thread.
+/// The bot is healthy during maintenance too: it still consumes updates.
+pub fn serve(listener: TcpListener, health: Arc<Health>) {
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            // a broken client must not stop the endpoint
+            let _ = respond(stream, &health);
+        }
+    });
+}
+
+fn respond(mut stream: TcpStream, health: &Health) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+
+    let body = health.body();
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        body
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+
+    #[test]
+    fn should_answer_with_health_state() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let health = Arc::new(Health::new());
+        health.set_maintenance(true);
+        health.set_last_poll_at(42);
+        serve(listener, Arc::clone(&health));
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(b"GET /health HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with(r#"{"last_poll_at":42,"maintenance":true,"status":"ok"}"#));
+    }
+
+    #[test]
+    fn should_not_be_stalled_by_idle_client() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        serve(listener, Arc::new(Health::new()));
+
+        let _idle = TcpStream::connect(addr).unwrap();
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.set_read_timeout(Some(READ_TIMEOUT * 3)).unwrap();
+        stream.write_all(b"GET /health HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+}
@@ -1,27 +1,95 @@
+pub mod access;
+pub mod budget;
+pub mod cli;
 mod commands;
 pub mod config;
 mod console;
+mod conversation;
+pub mod custom;
+pub mod flood;
+pub mod health;
 pub mod logger;
+pub mod maintenance;
+pub mod overrides;
+pub mod reload;
+pub mod storage;
 mod telegram;
 
+use std::{
+    net::TcpListener,
+    sync::{atomic::AtomicBool, Arc},
+};
+
 use config::{BotMode, Config};
+use health::Health;
 use logger::Logger;
+use reload::ConfigReloader;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use storage::{CachedStore, StoreError};
 pub use telegram::client_types::ClientError;
 
 pub enum BotError {
     Console(std::io::Error),
     Telegram(ClientError),
+    Storage(StoreError),
 }
 
-pub fn run_bot(config: Config, logger: &dyn Logger) -> Result<(), BotError> {
-    match config.mode {
+pub fn run_bot(
+    config: Config,
+    mut reloader: ConfigReloader,
+    logger: &dyn Logger,
+) -> Result<(), BotError> {
+    let mut store = storage::open(&config.storage).map_err(BotError::Storage)?;
+    logger.log_info(format!("open state storage: {:?}", config.storage).as_str());
+
+    if let Some(cache) = &config.cache {
+        logger.log_info(format!("cache chat states: {:?}", cache).as_str());
+        store = Box::new(CachedStore::new(store, cache.clone()));
+    }
+
+    let health = Arc::new(Health::new());
+    if let Some(addr) = &config.health_addr {
+        health::serve(
+            TcpListener::bind(addr).map_err(BotError::Console)?,
+            Arc::clone(&health),
+        );
+        logger.log_info(format!("serve health endpoint on {}", addr).as_str());
+    }
+
+    if let Err(e) = reloader.register_signal() {
+        logger.log_warn(format!("config reload on SIGHUP is off: {}", e).as_str());
+    }
+
+    let result = match config.mode {
         BotMode::Console => {
             logger.log_info("start console bot");
-            console::run_bot(&config, logger).map_err(BotError::Console)
+            console::run_bot(&config, store.as_mut(), &mut reloader, logger)
         }
         BotMode::Telegram => {
             logger.log_info("start telegram bot");
-            telegram::run_bot(&config, logger).map_err(BotError::Telegram)
+            let stop = register_stop().unwrap_or_else(|e| {
+                logger.log_warn(format!("SIGTERM and SIGINT stop without flush: {}", e).as_str());
+                Arc::new(AtomicBool::new(false))
+            });
+            telegram::run_bot(&config, store.as_mut(), &mut reloader, logger, &health, &stop)
+                .map_err(BotError::Telegram)
         }
+    };
+
+    store.flush().map_err(BotError::Storage)?;
+
+    result
+}
+
+/// `SIGTERM` and `SIGINT` stop the telegram bot after the current batch, so the storage is
+/// flushed before exit. A second signal terminates the bot at once.
+fn register_stop() -> std::io::Result<Arc<AtomicBool>> {
+    let stop = Arc::new(AtomicBool::new(false));
+
+    for signal in [SIGTERM, SIGINT] {
+        signal_hook::flag::register_conditional_shutdown(signal, 1, Arc::clone(&stop))?;
+        signal_hook::flag::register(signal, Arc::clone(&stop))?;
     }
+
+    Ok(stop)
 }
@@ -1,3 +1,5 @@
+use std::cell::RefCell;
+
 use chrono::Utc;
 use colored::*;
 use serde::{Deserialize, Serialize};
@@ -13,6 +15,9 @@ pub enum LogLevel {
 pub trait Logger {
     fn log(&self, log_level: LogLevel, msg: &str);
 
+    /// Change the lowest level which is written, e.g. after a config reload.
+    fn set_level(&self, _log_level: LogLevel) {}
+
     fn log_debug(&self, msg: &str) {
         self.log(LogLevel::Debug, msg)
     }
@@ -31,12 +36,12 @@ pub trait Logger {
 }
 
 pub struct ConsoleLogger {
-    log_level_for_output: LogLevel,
+    log_level_for_output: RefCell<LogLevel>,
 }
 
 impl Logger for ConsoleLogger {
     fn log(&self, log_level: LogLevel, msg: &str) {
-        if log_level >= self.log_level_for_output {
+        if log_level >= *self.log_level_for_output.borrow() {
             let formatted_date = Utc::now().format("%Y-%m-%d %H:%M:%S").to_string();
             match log_level {
                 LogLevel::Debug => {
@@ -74,12 +79,16 @@ impl Logger for ConsoleLogger {
             }
         }
     }
+
+    fn set_level(&self, log_level: LogLevel) {
+        *self.log_level_for_output.borrow_mut() = log_level;
+    }
 }
 
 impl ConsoleLogger {
     pub fn new(level: LogLevel) -> Self {
         Self {
-            log_level_for_output: level,
+            log_level_for_output: RefCell::new(level),
         }
     }
 }
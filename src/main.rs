@@ -1,19 +1,83 @@
-use std::process;
+use std::{env, io, process};
 
-use echo_bot::config::ConfigBuilder;
+use echo_bot::cli::{self, Cli, Subcommand};
+use echo_bot::config::{ConfigBuilder, ConfigError};
 use echo_bot::logger::{ConsoleLogger, Logger};
+use echo_bot::reload::ConfigReloader;
 use echo_bot::{BotError, ClientError};
 
 fn main() {
-    let config = ConfigBuilder::new()
-        .extract_path()
+    let cli = Cli::parse(env::args().skip(1)).unwrap_or_else(|message| {
+        eprintln!("{}", message);
+        process::exit(2);
+    });
+
+    // validation reads only the dump, a config may not exist on this host yet
+    if let Subcommand::Validate { dump } = &cli.command {
+        match cli::validate(dump, &mut io::stdout()) {
+            Ok(true) => process::exit(0),
+            Ok(false) => process::exit(1),
+            Err(_) => {
+                eprintln!("Cannot read dump file: {}", dump);
+                process::exit(1);
+            }
+        }
+    }
+
+    if let Subcommand::CheckConfig = &cli.command {
+        match cli::check_config(cli.config_path, cli.overrides, &mut io::stdout()) {
+            Ok(true) => process::exit(0),
+            _ => process::exit(1),
+        }
+    }
+
+    let builder = ConfigBuilder::new()
+        .path(cli.config_path)
+        .overrides(cli.overrides.clone())
         .extract_config_body()
-        .build();
+        .unwrap_or_else(config_error);
+    let config_path = builder.file_path();
+    let config_files = builder.files();
+    let (config, document) = builder.build_with_document().unwrap_or_else(config_error);
+
+    // printed before logging starts, so the output is the document only
+    if let Subcommand::ConfigPrint = &cli.command {
+        match cli::print_config(&document, &mut io::stdout()) {
+            Ok(_) => process::exit(0),
+            Err(_) => process::exit(1),
+        }
+    }
 
     let logger = ConsoleLogger::new(config.log_level.clone());
     logger.log_info("logger with config success build");
 
-    match echo_bot::run_bot(config, &logger) {
+    let result = match cli.command {
+        Subcommand::Run => {
+            let reloader = ConfigReloader::new(config_path, config_files, cli.overrides, document);
+            echo_bot::run_bot(config, reloader, &logger)
+        }
+        Subcommand::Outbox => cli::print_outbox(&config, &mut io::stdout()),
+        Subcommand::Chats => cli::print_chats(&config, &mut io::stdout()),
+        Subcommand::Export => cli::export(&config, &mut io::stdout()),
+        Subcommand::Audit => cli::audit(&config, &mut io::stdout()),
+        Subcommand::Import { dump, strategy } => {
+            match cli::import(&config, &dump, strategy, &mut io::stdout()) {
+                Ok(false) => process::exit(1),
+                result => result.map(|_| ()),
+            }
+        }
+        Subcommand::Maintenance { enabled } => {
+            match cli::maintenance(&config, enabled, &mut io::stdout()) {
+                Ok(false) => process::exit(1),
+                result => result.map(|_| ()),
+            }
+        }
+        Subcommand::Validate { .. } => unreachable!("validated before loading config"),
+        Subcommand::CheckConfig => unreachable!("checked before loading config"),
+        Subcommand::ConfigPrint => unreachable!("printed before logging starts"),
+    };
+
+    match result {
         Err(BotError::Console(err)) => {
             logger.log_error(format!("io error occurred: {}", err).as_str())
         }
@@ -28,9 +92,27 @@ fn main() {
                 ClientError::Serialize(e) => {
                     logger.log_error(format!("serialization error occurred : {}", e).as_str())
                 }
+                ClientError::ChatMigrated { to_chat_id } => logger.log_error(
+                    format!("chat was migrated to supergroup: {}", to_chat_id).as_str(),
+                ),
+                ClientError::ChatUnavailable(description) => logger.log_error(
+                    format!("bot cannot write to chat: {}", description).as_str(),
+                ),
+                ClientError::Storage(e) => {
+                    logger.log_error(format!("state storage error occurred: {}", e).as_str())
+                }
             }
             process::exit(1);
         }
+        Err(BotError::Storage(error)) => {
+            logger.log_error(format!("state storage error occurred: {}", error).as_str());
+            process::exit(1);
+        }
         Ok(_) => {}
     }
 }
+
+fn config_error<T>(error: ConfigError) -> T {
+    eprintln!("{}", error);
+    process::exit(1);
+}
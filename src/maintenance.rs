@@ -0,0 +1,172 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use chrono::{TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use signal_hook::consts::{SIGUSR1, SIGUSR2};
+
+use crate::{
+    logger::Logger,
+    storage::{AuditEntry, StateStore, StoreError},
+};
+
+/// Key of the maintenance flag among bot-wide values.
+const MAINTENANCE_KEY: &str = "maintenance";
+
+/// While maintenance is on, users get `maintenance_msg` instead of answers, admins are
+/// answered as usual. Kept in the state store, so it survives a restart.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Maintenance {
+    pub enabled: bool,
+    /// Unix timestamp when maintenance was switched on.
+    pub since: Option<i64>,
+}
+
+impl Maintenance {
+    pub fn load(store: &dyn StateStore) -> Result<Self, StoreError> {
+        Ok(store
+            .value(MAINTENANCE_KEY)?
+            .map(serde_json::from_value)
+            .transpose()?
+            .unwrap_or_default())
+    }
+
+    pub fn save(&self, store: &mut dyn StateStore) -> Result<(), StoreError> {
+        store.set_value(MAINTENANCE_KEY, Some(&serde_json::to_value(self)?))
+    }
+
+    /// Switch maintenance on or off, false if it already was in that state.
+    pub fn switch(store: &mut dyn StateStore, enabled: bool, now: i64) -> Result<bool, StoreError> {
+        if Self::load(store)?.enabled == enabled {
+            return Ok(false);
+        }
+
+        Maintenance {
+            enabled,
+            since: enabled.then_some(now),
+        }
+        .save(store)?;
+
+        Ok(true)
+    }
+
+    /// Switch made outside of chats, by a signal or a CLI command, recorded in the audit
+    /// log without a user.
+    pub fn switch_outside(
+        store: &mut dyn StateStore,
+        enabled: bool,
+        now: i64,
+    ) -> Result<bool, StoreError> {
+        let switched = Self::switch(store, enabled, now)?;
+
+        if switched {
+            store.append_audit(&AuditEntry::new(
+                now,
+                None,
+                0,
+                "maintenance",
+                (!enabled).into(),
+                enabled.into(),
+            ))?;
+        }
+
+        Ok(switched)
+    }
+
+    pub fn describe(&self) -> String {
+        match (self.enabled, self.since) {
+            (true, Some(since)) => format!(
+                "Maintenance is on since {}",
+                Utc.timestamp_opt(since, 0)
+                    .single()
+                    .map_or("-".to_string(), |date| date.to_rfc3339())
+            ),
+            (true, None) => "Maintenance is on".to_string(),
+            (false, _) => "Maintenance is off".to_string(),
+        }
+    }
+}
+
+/// `SIGUSR1` switches maintenance on and `SIGUSR2` switches it off. Signals only raise
+/// flags, the polling loop applies them to the store.
+pub struct MaintenanceSignals {
+    on: Arc<AtomicBool>,
+    off: Arc<AtomicBool>,
+}
+
+impl MaintenanceSignals {
+    pub fn register() -> std::io::Result<Self> {
+        let signals = MaintenanceSignals {
+            on: Arc::new(AtomicBool::new(false)),
+            off: Arc::new(AtomicBool::new(false)),
+        };
+
+        signal_hook::flag::register(SIGUSR1, Arc::clone(&signals.on))?;
+        signal_hook::flag::register(SIGUSR2, Arc::clone(&signals.off))?;
+
+        Ok(signals)
+    }
+
+    /// Apply signals received since the last call, off wins if both came.
+    pub fn apply(
+        &self,
+        store: &mut dyn StateStore,
+        logger: &dyn Logger,
+        now: i64,
+    ) -> Result<(), StoreError> {
+        let on = self.on.swap(false, Ordering::Relaxed);
+        let off = self.off.swap(false, Ordering::Relaxed);
+
+        if !on && !off {
+            return Ok(());
+        }
+        let enabled = !off;
+
+        if Maintenance::switch_outside(store, enabled, now)? {
+            logger.log_info(
+                format!(
+                    "maintenance is switched {} by signal",
+                    if enabled { "on" } else { "off" }
+                )
+                .as_str(),
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::MemoryStore;
+
+    use super::*;
+
+    #[test]
+    fn should_switch_maintenance_and_keep_it_in_store() {
+        let mut store = MemoryStore::new();
+
+        assert_eq!(Maintenance::load(&store).unwrap(), Maintenance::default());
+        assert!(Maintenance::switch(&mut store, true, 10).unwrap());
+        assert!(!Maintenance::switch(&mut store, true, 20).unwrap());
+        assert_eq!(
+            Maintenance::load(&store).unwrap(),
+            Maintenance {
+                enabled: true,
+                since: Some(10)
+            }
+        );
+        assert_eq!(
+            Maintenance::load(&store).unwrap().describe(),
+            "Maintenance is on since 1970-01-01T00:00:10+00:00"
+        );
+
+        assert!(Maintenance::switch(&mut store, false, 30).unwrap());
+        assert_eq!(
+            Maintenance::load(&store).unwrap().describe(),
+            "Maintenance is off"
+        );
+    }
+}
@@ -0,0 +1,188 @@
+use std::{borrow::Cow, collections::BTreeMap};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    config::{Config, MAX_KEYBOARD_REPEAT_NUMBER},
+    storage::{StateStore, StoreError},
+};
+
+/// Key of config overrides among bot-wide values.
+const OVERRIDES_KEY: &str = "config_overrides";
+
+/// Config keys which `/config` can change at runtime.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigKey {
+    HelpMsg,
+    RepeatMsg,
+    DefaultRepeatNumber,
+}
+
+impl ConfigKey {
+    pub const ALL: [ConfigKey; 3] = [
+        ConfigKey::HelpMsg,
+        ConfigKey::RepeatMsg,
+        ConfigKey::DefaultRepeatNumber,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ConfigKey::HelpMsg => "help_msg",
+            ConfigKey::RepeatMsg => "repeat_msg",
+            ConfigKey::DefaultRepeatNumber => "default_repeat_number",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|key| key.name() == name)
+    }
+
+    /// Value in the config file.
+    pub fn value(&self, config: &Config) -> Value {
+        match self {
+            ConfigKey::HelpMsg => Value::from(config.help_msg.as_str()),
+            ConfigKey::RepeatMsg => Value::from(config.repeat_msg.as_str()),
+            ConfigKey::DefaultRepeatNumber => Value::from(config.default_repeat_number),
+        }
+    }
+
+    /// Typed value of the text typed by an admin, or why it does not fit the key.
+    pub fn parse_value(&self, text: &str) -> Result<Value, String> {
+        match self {
+            ConfigKey::HelpMsg | ConfigKey::RepeatMsg if text.trim().is_empty() => {
+                Err(format!("{} cannot be empty", self.name()))
+            }
+            ConfigKey::HelpMsg | ConfigKey::RepeatMsg => Ok(Value::from(text)),
+            // the same bounds as in the config file
+            ConfigKey::DefaultRepeatNumber => match text.parse::<u8>() {
+                Ok(number @ 1..=MAX_KEYBOARD_REPEAT_NUMBER) => Ok(Value::from(number)),
+                _ => Err(format!(
+                    "{} must be a number from 1 to {}, got {}",
+                    self.name(),
+                    MAX_KEYBOARD_REPEAT_NUMBER,
+                    text
+                )),
+            },
+        }
+    }
+
+    fn apply(&self, config: &mut Config, value: &Value) {
+        match (self, value) {
+            (ConfigKey::HelpMsg, Value::String(text)) => config.help_msg = text.clone(),
+            (ConfigKey::RepeatMsg, Value::String(text)) => config.repeat_msg = text.clone(),
+            (ConfigKey::DefaultRepeatNumber, value) => {
+                if let Some(number) = value.as_u64().and_then(|n| u8::try_from(n).ok()) {
+                    config.default_repeat_number = number.max(1);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Values set by `/config set`, kept in the state store apart from the config file
+/// which stays as it was written.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConfigOverrides(BTreeMap<String, Value>);
+
+impl ConfigOverrides {
+    pub fn load(store: &dyn StateStore) -> Result<Self, StoreError> {
+        Ok(store
+            .value(OVERRIDES_KEY)?
+            .map(serde_json::from_value)
+            .transpose()?
+            .unwrap_or_default())
+    }
+
+    pub fn save(&self, store: &mut dyn StateStore) -> Result<(), StoreError> {
+        store.set_value(OVERRIDES_KEY, Some(&serde_json::to_value(self)?))
+    }
+
+    pub fn get(&self, key: ConfigKey) -> Option<&Value> {
+        self.0.get(key.name())
+    }
+
+    /// Override the key, returns the previous override.
+    pub fn set(&mut self, key: ConfigKey, value: Value) -> Option<Value> {
+        self.0.insert(key.name().to_string(), value)
+    }
+
+    /// Back to the value of the config file, returns the removed override.
+    pub fn reset(&mut self, key: ConfigKey) -> Option<Value> {
+        self.0.remove(key.name())
+    }
+
+    /// Value in effect: the override or the value of the config file.
+    pub fn value(&self, key: ConfigKey, config: &Config) -> Value {
+        self.get(key).cloned().unwrap_or_else(|| key.value(config))
+    }
+
+    /// Config with overrides applied, it is not copied if there are none.
+    pub fn apply<'c>(&self, config: &'c Config) -> Cow<'c, Config> {
+        if self.0.is_empty() {
+            return Cow::Borrowed(config);
+        }
+
+        let mut config = config.clone();
+        for key in ConfigKey::ALL {
+            if let Some(value) = self.get(key) {
+                key.apply(&mut config, value);
+            }
+        }
+
+        Cow::Owned(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{BotMode, ConfigBuilder};
+
+    use super::*;
+
+    #[test]
+    fn should_validate_values_by_key_type() {
+        assert_eq!(
+            ConfigKey::HelpMsg.parse_value("new help"),
+            Ok(Value::from("new help"))
+        );
+        assert!(ConfigKey::RepeatMsg.parse_value(" ").is_err());
+        assert_eq!(
+            ConfigKey::DefaultRepeatNumber.parse_value("3"),
+            Ok(Value::from(3))
+        );
+        assert_eq!(
+            ConfigKey::DefaultRepeatNumber.parse_value("0"),
+            Err("default_repeat_number must be a number from 1 to 5, got 0".to_string())
+        );
+        assert!(ConfigKey::DefaultRepeatNumber.parse_value("6").is_err());
+        assert!(ConfigKey::DefaultRepeatNumber.parse_value("many").is_err());
+        assert_eq!(ConfigKey::parse("log_level"), None);
+    }
+
+    #[test]
+    fn should_apply_overrides_on_top_of_config() {
+        let config = ConfigBuilder::build_default(BotMode::Console);
+        let mut overrides = ConfigOverrides::default();
+
+        assert!(matches!(overrides.apply(&config), Cow::Borrowed(_)));
+
+        overrides.set(ConfigKey::HelpMsg, Value::from("new help"));
+        overrides.set(ConfigKey::DefaultRepeatNumber, Value::from(4));
+        let applied = overrides.apply(&config);
+
+        assert_eq!(applied.help_msg, "new help");
+        assert_eq!(applied.repeat_msg, config.repeat_msg);
+        assert_eq!(applied.default_repeat_number, 4);
+
+        assert_eq!(
+            overrides.reset(ConfigKey::HelpMsg),
+            Some(Value::from("new help"))
+        );
+        assert_eq!(
+            overrides.value(ConfigKey::HelpMsg, &config),
+            Value::from("help msg")
+        );
+    }
+}
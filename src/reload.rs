@@ -0,0 +1,404 @@
+use std::{
+    fs,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::SystemTime,
+};
+
+use serde_yaml::Value;
+use signal_hook::consts::SIGHUP;
+
+use crate::{
+    config::{Config, ConfigBuilder, ConfigError},
+    logger::Logger,
+    storage::{AuditEntry, StateStore, StoreError},
+};
+
+/// Key of a reload requested by `/reload` among bot-wide values.
+const RELOAD_KEY: &str = "reload";
+
+/// Keys which are read once at start, their changes are only reported. `admins` is not
+/// one of them: it is checked on every update, and the telegram bot updates command
+/// menus of admins who were added or removed.
+pub const RESTART_KEYS: [&str; 8] = [
+    "mode",
+    "bot_token",
+    "bot_token_file",
+    "storage",
+    "cache",
+    "delivery_guarantee",
+    "health_addr",
+    "custom_commands",
+];
+
+/// Keys changed by a reload.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ReloadReport {
+    /// Applied to the running bot.
+    pub applied: Vec<String>,
+    /// Kept as they were until a restart.
+    pub restart: Vec<String>,
+}
+
+/// Reloads the config when one of its files changes, on `SIGHUP` and on `/reload`. The new
+/// config is validated as at start, an invalid one is logged and the running config is kept.
+pub struct ConfigReloader {
+    path: String,
+    /// The main file and the files it extends and includes.
+    files: Vec<String>,
+    overrides: Vec<String>,
+    /// Document of the running config, keys which need a restart keep their start values.
+    document: Value,
+    modified_at: Vec<Option<SystemTime>>,
+    signal: Arc<AtomicBool>,
+}
+
+impl ConfigReloader {
+    pub fn new(path: String, files: Vec<String>, overrides: Vec<String>, document: Value) -> Self {
+        ConfigReloader {
+            modified_at: modified_at(&files),
+            path,
+            files,
+            overrides,
+            document,
+            signal: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn register_signal(&self) -> std::io::Result<()> {
+        signal_hook::flag::register(SIGHUP, Arc::clone(&self.signal)).map(|_| ())
+    }
+
+    /// Ask the polling loop to reload the config, used by `/reload`.
+    pub fn request(store: &mut dyn StateStore) -> Result<(), StoreError> {
+        store.set_value(RELOAD_KEY, Some(&serde_json::Value::Bool(true)))
+    }
+
+    /// Reload if the file was modified, a signal came or a reload was requested since the
+    /// last call. Changes are logged and applied keys are written to the audit log.
+    pub fn poll(
+        &mut self,
+        config: &mut Config,
+        store: &mut dyn StateStore,
+        logger: &dyn Logger,
+        now: i64,
+    ) -> Result<(), StoreError> {
+        let requested = store.value(RELOAD_KEY)?.is_some();
+        if requested {
+            store.set_value(RELOAD_KEY, None)?;
+        }
+        let signaled = self.signal.swap(false, Ordering::Relaxed);
+        let modified_at = modified_at(&self.files);
+        let modified = modified_at != self.modified_at;
+
+        if !requested && !signaled && !modified {
+            return Ok(());
+        }
+        self.modified_at = modified_at;
+
+        let old = self.document.clone();
+        let report = match self.reload(config) {
+            Ok(report) => report,
+            Err(e) => {
+                logger.log_error(format!("config is not reloaded: {}", e).as_str());
+                return Ok(());
+            }
+        };
+        logger.set_level(config.log_level.clone());
+
+        if !report.restart.is_empty() {
+            logger.log_warn(
+                format!(
+                    "config keys need a restart to change: {}",
+                    report.restart.join(", ")
+                )
+                .as_str(),
+            );
+        }
+        if report.applied.is_empty() {
+            logger.log_info("config is reloaded, no changes to apply");
+            return Ok(());
+        }
+        logger.log_info(
+            format!("config is reloaded, changed: {}", report.applied.join(", ")).as_str(),
+        );
+
+        let values = |document: &Value| {
+            let values: serde_json::Map<_, _> = report
+                .applied
+                .iter()
+                .map(|key| {
+                    let value = document.get(key.as_str()).unwrap_or(&Value::Null);
+                    (key.clone(), serde_json::to_value(value).unwrap_or_default())
+                })
+                .collect();
+            serde_json::Value::Object(values)
+        };
+        store.append_audit(&AuditEntry::new(
+            now,
+            None,
+            0,
+            "config.reload",
+            values(&old),
+            values(&self.document),
+        ))
+    }
+
+    /// Read and validate the file, then swap reloadable keys into `config`.
+    fn reload(&mut self, config: &mut Config) -> Result<ReloadReport, ConfigError> {
+        let builder = ConfigBuilder::new()
+            .path(Some(self.path.clone()))
+            .overrides(self.overrides.clone())
+            .extract_config_body()?;
+        // watched even if the new config is invalid, so fixing an included file reloads it
+        self.watch(builder.files());
+        let (next, mut document) = builder.build_with_document()?;
+        let mut report = ReloadReport::default();
+
+        for key in changed_keys(&self.document, &document) {
+            match RESTART_KEYS.contains(&key.as_str()) {
+                true => report.restart.push(key),
+                false => report.applied.push(key),
+            }
+        }
+        for key in RESTART_KEYS {
+            match (self.document.get(key), document.as_mapping_mut()) {
+                (Some(value), Some(mapping)) => mapping.insert(key.into(), value.clone()),
+                (None, Some(mapping)) => mapping.remove(key),
+                (_, None) => None,
+            };
+        }
+
+        *config = Config {
+            mode: config.mode.clone(),
+            bot_token: config.bot_token.clone(),
+            storage: config.storage.clone(),
+            cache: config.cache.clone(),
+            delivery_guarantee: config.delivery_guarantee,
+            health_addr: config.health_addr.clone(),
+            custom_commands: config.custom_commands.clone(),
+            ..next
+        };
+        self.document = document;
+
+        Ok(report)
+    }
+
+    /// Watch the files the last load read: a reloaded file may extend or include other
+    /// files than before. Known files keep the times seen before they were read, so a
+    /// change made during the load is not missed.
+    fn watch(&mut self, files: Vec<String>) {
+        self.modified_at = files
+            .iter()
+            .map(
+                |file| match self.files.iter().position(|known| known == file) {
+                    Some(index) => self.modified_at[index],
+                    None => modified_at(std::slice::from_ref(file))[0],
+                },
+            )
+            .collect();
+        self.files = files;
+    }
+}
+
+fn modified_at(files: &[String]) -> Vec<Option<SystemTime>> {
+    files
+        .iter()
+        .map(|path| fs::metadata(path).and_then(|meta| meta.modified()).ok())
+        .collect()
+}
+
+/// Top-level keys with different values, in order of the new document.
+fn changed_keys(old: &Value, new: &Value) -> Vec<String> {
+    let keys = |document: &Value| -> Vec<String> {
+        document
+            .as_mapping()
+            .into_iter()
+            .flat_map(|mapping| mapping.keys())
+            .filter_map(|key| key.as_str().map(str::to_string))
+            .collect()
+    };
+    let mut all = keys(new);
+    all.extend(keys(old).into_iter().filter(|key| new.get(key).is_none()));
+
+    all.into_iter()
+        .filter(|key| old.get(key) != new.get(key))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use crate::{logger::LogLevel, storage::MemoryStore};
+
+    use super::*;
+
+    struct NoLogger;
+
+    impl Logger for NoLogger {
+        fn log(&self, _log_level: LogLevel, _msg: &str) {}
+    }
+
+    #[test]
+    fn should_watch_files_included_by_reloaded_config() {
+        let dir = env::temp_dir().join(format!("echo_bot_{}_reload_include", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.yaml");
+        let path_str = path.to_str().unwrap().to_string();
+        let content = "mode: console\nhelp_msg: help\nrepeat_msg: repeat\n\
+                       default_repeat_number: 1\nlog_level: Info\n";
+        fs::write(&path, content).unwrap();
+        let builder = ConfigBuilder::new()
+            .path(Some(path_str.clone()))
+            .extract_config_body()
+            .unwrap();
+        let files = builder.files();
+        let (mut config, document) = builder.build_with_document().unwrap();
+        let mut reloader = ConfigReloader::new(path_str, files, vec![], document);
+        let mut store = MemoryStore::new();
+        let touch = |file: &str, content: &str, seconds: u64| {
+            fs::write(dir.join(file), content).unwrap();
+            fs::File::options()
+                .write(true)
+                .open(dir.join(file))
+                .unwrap()
+                .set_modified(SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(seconds))
+                .unwrap();
+        };
+
+        // the new file is invalid, it is watched anyway
+        touch("messages.yaml", "help_msg: ''\n", 1);
+        touch(
+            "config.yaml",
+            &format!("{}include: messages.yaml\n", content),
+            1,
+        );
+        reloader
+            .poll(&mut config, &mut store, &NoLogger, 10)
+            .unwrap();
+        assert_eq!(config.help_msg, "help");
+        assert_eq!(reloader.files.len(), 2);
+
+        touch("messages.yaml", "help_msg: included help\n", 2);
+        reloader
+            .poll(&mut config, &mut store, &NoLogger, 20)
+            .unwrap();
+        assert_eq!(config.help_msg, "included help");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn should_swap_reloadable_keys_and_keep_restart_ones() {
+        let path = env::temp_dir().join(format!("echo_bot_{}_reload.yaml", process::id()));
+        let path_str = path.to_str().unwrap().to_string();
+        let content = "mode: console\nhelp_msg: help\nrepeat_msg: repeat\n\
+                       default_repeat_number: 1\nlog_level: Info\n";
+        fs::write(&path, content).unwrap();
+        let (mut config, document) = ConfigBuilder::new()
+            .path(Some(path_str.clone()))
+            .extract_config_body()
+            .unwrap()
+            .build_with_document()
+            .unwrap();
+        let mut reloader = ConfigReloader::new(path_str.clone(), vec![path_str], vec![], document);
+        let mut store = MemoryStore::new();
+
+        // an invalid file is not applied
+        fs::write(&path, content.replace("help_msg: help", "help_msg: ''")).unwrap();
+        assert!(reloader.reload(&mut config).is_err());
+        assert_eq!(config.help_msg, "help");
+
+        fs::write(
+            &path,
+            content
+                .replace("help_msg: help", "help_msg: new help")
+                .replace("mode: console", "mode: telegram\nbot_token_file: /missing")
+                + "broadcast_rate: 5\n",
+        )
+        .unwrap();
+        assert!(reloader.reload(&mut config).is_err());
+
+        fs::write(
+            &path,
+            content
+                .replace("help_msg: help", "help_msg: new help")
+                .replace(
+                    "mode: console",
+                    "mode: telegram\nbot_token: 1:AAEhBP0av28xHmDIx5XnKpFqbOVq1Bp3kVw",
+                )
+                + "broadcast_rate: 5\nadmins: [7]\n",
+        )
+        .unwrap();
+        assert_eq!(
+            reloader.reload(&mut config).unwrap(),
+            ReloadReport {
+                applied: vec![
+                    "help_msg".to_string(),
+                    "broadcast_rate".to_string(),
+                    "admins".to_string()
+                ],
+                restart: vec!["mode".to_string(), "bot_token".to_string()],
+            }
+        );
+        assert_eq!(config.help_msg, "new help");
+        assert_eq!(config.broadcast_rate, 5);
+        assert_eq!(config.admins, vec![7]);
+        assert!(matches!(config.mode, crate::config::BotMode::Console));
+        assert_eq!(config.bot_token, None);
+
+        // restart keys are reported until the bot is restarted
+        let content = fs::read_to_string(&path).unwrap();
+        fs::write(
+            &path,
+            content.replace("repeat_msg: repeat", "repeat_msg: again"),
+        )
+        .unwrap();
+        ConfigReloader::request(&mut store).unwrap();
+        reloader
+            .poll(&mut config, &mut store, &NoLogger, 10)
+            .unwrap();
+        assert_eq!(store.value(RELOAD_KEY).unwrap(), None);
+        assert_eq!(config.repeat_msg, "again");
+        let audit = store.audit_entries().unwrap();
+        assert_eq!(
+            (audit[0].action.as_str(), &audit[0].old, &audit[0].new),
+            (
+                "config.reload",
+                &serde_json::json!({"repeat_msg": "repeat"}),
+                &serde_json::json!({"repeat_msg": "again"})
+            )
+        );
+        assert_eq!(
+            reloader.reload(&mut config).unwrap(),
+            ReloadReport {
+                applied: vec![],
+                restart: vec!["mode".to_string(), "bot_token".to_string()],
+            }
+        );
+
+        // the token read from its file is compared, a new one needs a restart too
+        let token_path = path.with_extension("token");
+        fs::write(&token_path, "2:AAEhBP0av28xHmDIx5XnKpFqbOVq1Bp3kVw\n").unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        fs::write(
+            &path,
+            content.replace(
+                "bot_token: 1:AAEhBP0av28xHmDIx5XnKpFqbOVq1Bp3kVw",
+                &format!("bot_token_file: {}", token_path.display()),
+            ),
+        )
+        .unwrap();
+        assert_eq!(
+            reloader.reload(&mut config).unwrap().restart,
+            vec!["mode".to_string(), "bot_token".to_string()]
+        );
+        assert_eq!(config.bot_token, None);
+
+        fs::remove_file(token_path).unwrap();
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,212 @@
+use chrono::{TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Longest value shown in a line of `/audit`, longer ones are cut.
+const LINE_VALUE_CHARS: usize = 100;
+
+/// Change of bot settings made by a user or an admin. Entries are only appended, never changed.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AuditEntry {
+    /// Position in the log, assigned by the store.
+    pub id: u64,
+    /// Unix timestamp.
+    pub at: i64,
+    /// Telegram user id, `None` for the console.
+    pub actor: Option<u64>,
+    /// Chat the change was made from, 0 for changes made outside of chats, by a signal
+    /// or a CLI command.
+    pub chat_id: i64,
+    /// What was changed, e.g. `config.help_msg`.
+    pub action: String,
+    /// Values are opaque for the store, `null` if there was no value.
+    pub old: serde_json::Value,
+    pub new: serde_json::Value,
+}
+
+impl AuditEntry {
+    pub fn new(
+        at: i64,
+        actor: Option<u64>,
+        chat_id: i64,
+        action: &str,
+        old: serde_json::Value,
+        new: serde_json::Value,
+    ) -> Self {
+        AuditEntry {
+            id: 0,
+            at,
+            actor,
+            chat_id,
+            action: action.to_string(),
+            old,
+            new,
+        }
+    }
+
+    /// One line for `/audit`.
+    pub fn line(&self) -> String {
+        let at = Utc
+            .timestamp_opt(self.at, 0)
+            .single()
+            .map_or("-".to_string(), |date| date.to_rfc3339());
+        let actor = self
+            .actor
+            .map_or("no user".to_string(), |actor| format!("user {}", actor));
+
+        format!(
+            "#{} {} {} in chat {}: {} {} -> {}",
+            self.id,
+            at,
+            actor,
+            self.chat_id,
+            self.action,
+            shorten(&self.old),
+            shorten(&self.new)
+        )
+    }
+}
+
+fn shorten(value: &serde_json::Value) -> String {
+    let text = value.to_string();
+
+    match text.char_indices().nth(LINE_VALUE_CHARS) {
+        Some((end, _)) => format!("{}…", &text[..end]),
+        None => text,
+    }
+}
+
+/// Which entries `/audit` shows, newest first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditFilter {
+    pub chat_id: Option<i64>,
+    pub actor: Option<u64>,
+    /// Prefix of the action, `config` matches every `config.*` action.
+    pub action: Option<String>,
+    pub limit: usize,
+}
+
+impl AuditFilter {
+    pub const DEFAULT_LIMIT: usize = 20;
+    pub const MAX_LIMIT: usize = 100;
+
+    pub fn matches(&self, entry: &AuditEntry) -> bool {
+        self.chat_id.is_none_or(|chat_id| entry.chat_id == chat_id)
+            && self.actor.is_none_or(|actor| entry.actor == Some(actor))
+            && self
+                .action
+                .as_ref()
+                .is_none_or(|action| entry.action.starts_with(action.as_str()))
+    }
+
+    /// Matching entries, newest first, at most `limit` of them.
+    pub fn select(&self, entries: Vec<AuditEntry>) -> Vec<AuditEntry> {
+        entries
+            .into_iter()
+            .rev()
+            .filter(|entry| self.matches(entry))
+            .take(self.limit)
+            .collect()
+    }
+}
+
+impl Default for AuditFilter {
+    fn default() -> Self {
+        AuditFilter {
+            chat_id: None,
+            actor: None,
+            action: None,
+            limit: Self::DEFAULT_LIMIT,
+        }
+    }
+}
+
+/// Audit log kept in memory, used by stores which do not have their own.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct AuditLog {
+    next_id: u64,
+    entries: Vec<AuditEntry>,
+}
+
+impl AuditLog {
+    pub fn append(&mut self, entry: &AuditEntry) {
+        self.next_id += 1;
+        self.entries.push(AuditEntry {
+            id: self.next_id,
+            ..entry.clone()
+        });
+    }
+
+    pub fn entries(&self) -> Vec<AuditEntry> {
+        self.entries.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::*;
+
+    #[test]
+    fn should_select_newest_matching_entries() {
+        let mut log = AuditLog::default();
+        log.append(&AuditEntry::new(
+            1,
+            Some(7),
+            5,
+            "repeat",
+            Value::Null,
+            Value::from(3),
+        ));
+        log.append(&AuditEntry::new(
+            2,
+            Some(7),
+            8,
+            "access.9",
+            Value::Null,
+            Value::from("denied"),
+        ));
+        log.append(&AuditEntry::new(
+            3,
+            None,
+            0,
+            "maintenance",
+            Value::from(false),
+            Value::from(true),
+        ));
+        log.append(&AuditEntry::new(
+            4,
+            Some(7),
+            8,
+            "access.10",
+            Value::Null,
+            Value::from("denied"),
+        ));
+
+        let filter = AuditFilter {
+            chat_id: Some(8),
+            action: Some("access".to_string()),
+            ..AuditFilter::default()
+        };
+        let ids: Vec<_> = filter
+            .select(log.entries())
+            .iter()
+            .map(|entry| entry.id)
+            .collect();
+        assert_eq!(ids, vec![4, 2]);
+
+        let filter = AuditFilter {
+            actor: Some(7),
+            limit: 1,
+            ..AuditFilter::default()
+        };
+        assert_eq!(
+            filter.select(log.entries())[0].line(),
+            "#4 1970-01-01T00:00:04+00:00 user 7 in chat 8: access.10 null -> \"denied\""
+        );
+        assert_eq!(
+            log.entries()[2].line(),
+            "#3 1970-01-01T00:00:03+00:00 no user in chat 0: maintenance false -> true"
+        );
+    }
+}
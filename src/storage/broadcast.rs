@@ -0,0 +1,136 @@
+use serde::{Deserialize, Serialize};
+
+use super::{StateStore, StoreError};
+
+/// Key of the running or the last finished broadcast among bot-wide values.
+const BROADCAST_KEY: &str = "broadcast";
+
+/// At most this many failed chats are listed in the final report.
+const FAILURES_REPORT_LIMIT: usize = 20;
+
+/// Chat which did not get the broadcast.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BroadcastFailure {
+    pub chat_id: i64,
+    pub error: String,
+}
+
+/// Message sent to every active chat. It is saved after every chat, so a broadcast
+/// interrupted by a restart continues with the next chat.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Broadcast {
+    pub text: String,
+    /// Chat of the admin who started it, progress is reported there.
+    pub admin_chat_id: i64,
+    /// Message with progress which is edited in place, `None` until it is sent.
+    pub progress_message_id: Option<i64>,
+    /// Recipients in sending order.
+    pub chats: Vec<i64>,
+    /// Index of the next recipient in `chats`.
+    pub next: usize,
+    /// Failed attempts to send to the next recipient.
+    pub attempts: u32,
+    /// Unix timestamp, nothing is sent before it.
+    pub next_attempt_at: i64,
+    pub delivered: usize,
+    pub failed: Vec<BroadcastFailure>,
+    /// Unix timestamps.
+    pub started_at: i64,
+    pub finished_at: Option<i64>,
+}
+
+impl Broadcast {
+    pub fn new(text: String, admin_chat_id: i64, chats: Vec<i64>, now: i64) -> Self {
+        Broadcast {
+            text,
+            admin_chat_id,
+            progress_message_id: None,
+            chats,
+            next: 0,
+            attempts: 0,
+            next_attempt_at: 0,
+            delivered: 0,
+            failed: vec![],
+            started_at: now,
+            finished_at: None,
+        }
+    }
+
+    /// Chats the bot is in and can write to.
+    pub fn recipients(store: &mut dyn StateStore) -> Result<Vec<i64>, StoreError> {
+        Ok(store
+            .chats()?
+            .into_iter()
+            .filter(|(_, chat)| {
+                !chat.inactive
+                    && chat
+                        .info
+                        .as_ref()
+                        .is_some_and(|info| info.status.is_present())
+            })
+            .map(|(chat_id, _)| chat_id)
+            .collect())
+    }
+
+    pub fn load(store: &dyn StateStore) -> Result<Option<Self>, StoreError> {
+        Ok(store
+            .value(BROADCAST_KEY)?
+            .map(serde_json::from_value)
+            .transpose()?)
+    }
+
+    pub fn save(&self, store: &mut dyn StateStore) -> Result<(), StoreError> {
+        store.set_value(BROADCAST_KEY, Some(&serde_json::to_value(self)?))
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished_at.is_some()
+    }
+
+    /// Next recipient is done with, successfully or not.
+    pub fn advance(&mut self, failure: Option<String>) {
+        match failure {
+            Some(error) => self.failed.push(BroadcastFailure {
+                chat_id: self.chats[self.next],
+                error,
+            }),
+            None => self.delivered += 1,
+        }
+
+        self.next += 1;
+        self.attempts = 0;
+        self.next_attempt_at = 0;
+    }
+
+    /// Text of the progress message, the final one lists failed chats.
+    pub fn progress(&self) -> String {
+        let mut lines = vec![
+            match self.is_finished() {
+                true => format!("Broadcast finished: {} chats", self.chats.len()),
+                false => format!("Broadcast: {} of {} chats", self.next, self.chats.len()),
+            },
+            format!(
+                "Delivered: {}, failed: {}",
+                self.delivered,
+                self.failed.len()
+            ),
+        ];
+
+        if self.is_finished() {
+            lines.extend(
+                self.failed
+                    .iter()
+                    .take(FAILURES_REPORT_LIMIT)
+                    .map(|failure| format!("{}: {}", failure.chat_id, failure.error)),
+            );
+            if self.failed.len() > FAILURES_REPORT_LIMIT {
+                lines.push(format!(
+                    "and {} more",
+                    self.failed.len() - FAILURES_REPORT_LIMIT
+                ));
+            }
+        }
+
+        lines.join("\n")
+    }
+}
@@ -0,0 +1,408 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::Display,
+};
+
+use chrono::Utc;
+use serde::Deserialize;
+
+use super::{AuditEntry, ChatState, OutboxEntry, StateStore, StoreError};
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CacheConfig {
+    /// Maximum number of chats kept in memory.
+    pub capacity: usize,
+    /// Seconds after which a chat which was not accessed is evicted.
+    #[serde(default = "default_idle_ttl")]
+    pub idle_ttl: i64,
+    /// Seconds between writes of changed chats to the storage.
+    #[serde(default = "default_flush_interval")]
+    pub flush_interval: i64,
+}
+
+fn default_idle_ttl() -> i64 {
+    3600
+}
+
+fn default_flush_interval() -> i64 {
+    5
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheMetrics {
+    pub entries: usize,
+    pub hits: u64,
+    pub misses: u64,
+    /// Chats evicted because the cache was full.
+    pub evictions: u64,
+    /// Chats evicted because they were idle longer than ttl.
+    pub expirations: u64,
+}
+
+impl CacheMetrics {
+    pub fn hit_rate(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            total => self.hits as f64 / total as f64,
+        }
+    }
+}
+
+impl Display for CacheMetrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} chats cached, hit rate {:.1}% ({} hits, {} misses), {} evicted, {} expired",
+            self.entries,
+            self.hit_rate() * 100.0,
+            self.hits,
+            self.misses,
+            self.evictions,
+            self.expirations
+        )
+    }
+}
+
+struct CachedChat {
+    state: ChatState,
+    last_access: i64,
+    /// Key of the chat in the recency index.
+    tick: u64,
+}
+
+/// Bounded LRU cache of chat states in front of another store. Changed chats are written
+/// back when evicted, every `flush_interval` seconds and before an update is marked
+/// processed or the offset is committed, so a committed update never loses its chat
+/// changes in a crash. Everything else is passed through.
+pub struct CachedStore {
+    inner: Box<dyn StateStore>,
+    config: CacheConfig,
+    chats: HashMap<i64, CachedChat>,
+    /// Access tick to chat id, the first one is the least recently used.
+    recency: BTreeMap<u64, i64>,
+    /// Chats changed since they were loaded or written, so a flush does not scan the cache.
+    dirty: HashSet<i64>,
+    tick: u64,
+    last_flush: i64,
+    metrics: CacheMetrics,
+    clock: Box<dyn Fn() -> i64>,
+}
+
+impl CachedStore {
+    pub fn new(inner: Box<dyn StateStore>, config: CacheConfig) -> Self {
+        Self::with_clock(inner, config, Box::new(|| Utc::now().timestamp()))
+    }
+
+    pub fn with_clock(
+        inner: Box<dyn StateStore>,
+        config: CacheConfig,
+        clock: Box<dyn Fn() -> i64>,
+    ) -> Self {
+        let last_flush = clock();
+
+        CachedStore {
+            inner,
+            config,
+            chats: HashMap::new(),
+            recency: BTreeMap::new(),
+            dirty: HashSet::new(),
+            tick: 0,
+            last_flush,
+            metrics: CacheMetrics::default(),
+            clock,
+        }
+    }
+
+    /// The store behind the cache, cached changes are dropped as in a crash.
+    #[cfg(test)]
+    pub fn into_inner(self) -> Box<dyn StateStore> {
+        self.inner
+    }
+
+    fn touch(&mut self, chat_id: i64, now: i64) {
+        self.tick += 1;
+
+        if let Some(chat) = self.chats.get_mut(&chat_id) {
+            self.recency.remove(&chat.tick);
+            chat.tick = self.tick;
+            chat.last_access = now;
+            self.recency.insert(self.tick, chat_id);
+        }
+    }
+
+    fn insert(&mut self, chat_id: i64, state: ChatState, now: i64) {
+        self.tick += 1;
+        self.recency.insert(self.tick, chat_id);
+        self.chats.insert(
+            chat_id,
+            CachedChat {
+                state,
+                last_access: now,
+                tick: self.tick,
+            },
+        );
+    }
+
+    /// Drop idle chats and, if the cache is full, the least recently used ones.
+    fn evict(&mut self, now: i64, reserve: usize) -> Result<(), StoreError> {
+        while let Some((&tick, &chat_id)) = self.recency.first_key_value() {
+            let chat = &self.chats[&chat_id];
+            let expired = chat.last_access + self.config.idle_ttl <= now;
+            let full = self.chats.len() + reserve > self.config.capacity;
+
+            if !expired && !full {
+                break;
+            }
+
+            if self.dirty.remove(&chat_id) {
+                self.inner.save_chat(chat_id, &chat.state)?;
+            }
+
+            self.recency.remove(&tick);
+            self.chats.remove(&chat_id);
+
+            if expired {
+                self.metrics.expirations += 1;
+            } else {
+                self.metrics.evictions += 1;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl StateStore for CachedStore {
+    fn load_chat(&mut self, chat_id: i64) -> Result<ChatState, StoreError> {
+        let now = (self.clock)();
+        self.evict(now, 0)?;
+
+        if let Some(chat) = self.chats.get(&chat_id) {
+            let state = chat.state.clone();
+            self.metrics.hits += 1;
+            self.touch(chat_id, now);
+            return Ok(state);
+        }
+
+        self.metrics.misses += 1;
+        let state = self.inner.load_chat(chat_id)?;
+
+        self.evict(now, 1)?;
+        self.insert(chat_id, state.clone(), now);
+
+        Ok(state)
+    }
+
+    fn save_chat(&mut self, chat_id: i64, state: &ChatState) -> Result<(), StoreError> {
+        let now = (self.clock)();
+
+        if let Some(chat) = self.chats.get_mut(&chat_id) {
+            chat.state = state.clone();
+            self.touch(chat_id, now);
+        } else {
+            self.evict(now, 1)?;
+            self.insert(chat_id, state.clone(), now);
+        }
+        self.dirty.insert(chat_id);
+
+        if now - self.last_flush >= self.config.flush_interval {
+            self.flush()?;
+        }
+
+        Ok(())
+    }
+
+    fn chats(&mut self) -> Result<Vec<(i64, ChatState)>, StoreError> {
+        self.flush()?;
+
+        self.inner.chats()
+    }
+
+    fn last_update_id(&self) -> Result<Option<u64>, StoreError> {
+        self.inner.last_update_id()
+    }
+
+    fn set_last_update_id(&mut self, update_id: u64) -> Result<(), StoreError> {
+        self.flush()?;
+
+        self.inner.set_last_update_id(update_id)
+    }
+
+    fn is_processed(&self, update_id: u64) -> Result<bool, StoreError> {
+        self.inner.is_processed(update_id)
+    }
+
+    fn mark_processed(&mut self, update_id: u64) -> Result<(), StoreError> {
+        self.flush()?;
+
+        self.inner.mark_processed(update_id)
+    }
+
+    fn enqueue(
+        &mut self,
+        key: &str,
+        chat_id: i64,
+        payload: &serde_json::Value,
+    ) -> Result<bool, StoreError> {
+        self.inner.enqueue(key, chat_id, payload)
+    }
+
+    fn undelivered(&self) -> Result<Vec<OutboxEntry>, StoreError> {
+        self.inner.undelivered()
+    }
+
+    fn failed_deliveries(&self) -> Result<Vec<OutboxEntry>, StoreError> {
+        self.inner.failed_deliveries()
+    }
+
+    fn save_outbox_entry(&mut self, entry: &OutboxEntry) -> Result<(), StoreError> {
+        self.inner.save_outbox_entry(entry)
+    }
+
+    fn value(&self, key: &str) -> Result<Option<serde_json::Value>, StoreError> {
+        self.inner.value(key)
+    }
+
+    fn values(&self) -> Result<BTreeMap<String, serde_json::Value>, StoreError> {
+        self.inner.values()
+    }
+
+    fn set_value(
+        &mut self,
+        key: &str,
+        value: Option<&serde_json::Value>,
+    ) -> Result<(), StoreError> {
+        self.inner.set_value(key, value)
+    }
+
+    fn append_audit(&mut self, entry: &AuditEntry) -> Result<(), StoreError> {
+        self.inner.append_audit(entry)
+    }
+
+    fn audit_entries(&self) -> Result<Vec<AuditEntry>, StoreError> {
+        self.inner.audit_entries()
+    }
+
+    fn flush(&mut self) -> Result<(), StoreError> {
+        for chat_id in self.dirty.iter() {
+            self.inner.save_chat(*chat_id, &self.chats[chat_id].state)?;
+        }
+        self.dirty.clear();
+
+        self.last_flush = (self.clock)();
+
+        self.inner.flush()
+    }
+
+    fn cache_metrics(&self) -> Option<CacheMetrics> {
+        Some(CacheMetrics {
+            entries: self.chats.len(),
+            ..self.metrics
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, rc::Rc};
+
+    use crate::storage::MemoryStore;
+
+    use super::*;
+
+    fn chat(repeat_number: u8) -> ChatState {
+        ChatState {
+            repeat_number: Some(repeat_number),
+            ..Default::default()
+        }
+    }
+
+    fn prepare(capacity: usize) -> (CachedStore, Rc<Cell<i64>>) {
+        let now = Rc::new(Cell::new(0));
+        let clock = now.clone();
+        let config = CacheConfig {
+            capacity,
+            idle_ttl: 100,
+            flush_interval: 10,
+        };
+        let store = CachedStore::with_clock(
+            Box::new(MemoryStore::new()),
+            config,
+            Box::new(move || clock.get()),
+        );
+
+        (store, now)
+    }
+
+    #[test]
+    fn should_evict_least_recently_used_chat_and_write_it_back() {
+        let (mut store, _) = prepare(2);
+
+        store.save_chat(1, &chat(1)).unwrap();
+        store.save_chat(2, &chat(2)).unwrap();
+        store.load_chat(1).unwrap();
+        store.save_chat(3, &chat(3)).unwrap();
+
+        assert!(store.chats.contains_key(&1));
+        assert!(!store.chats.contains_key(&2));
+        assert_eq!(store.inner.load_chat(2).unwrap(), chat(2));
+        assert_eq!(store.inner.load_chat(1).unwrap(), ChatState::default());
+
+        assert_eq!(store.load_chat(2).unwrap(), chat(2));
+        let metrics = store.cache_metrics().unwrap();
+        assert_eq!(metrics.entries, 2);
+        assert_eq!(metrics.hits, 1);
+        assert_eq!(metrics.misses, 1);
+        assert_eq!(metrics.evictions, 2);
+    }
+
+    #[test]
+    fn should_expire_idle_chats() {
+        let (mut store, now) = prepare(10);
+
+        store.save_chat(1, &chat(1)).unwrap();
+        now.set(50);
+        store.save_chat(2, &chat(2)).unwrap();
+        now.set(120);
+        store.load_chat(2).unwrap();
+
+        assert!(!store.chats.contains_key(&1));
+        assert_eq!(store.inner.load_chat(1).unwrap(), chat(1));
+        assert_eq!(store.cache_metrics().unwrap().expirations, 1);
+    }
+
+    #[test]
+    fn should_flush_changed_chats_periodically_and_before_commits() {
+        let (mut store, now) = prepare(10);
+
+        store.save_chat(1, &chat(1)).unwrap();
+        assert_eq!(store.inner.load_chat(1).unwrap(), ChatState::default());
+
+        now.set(10);
+        store.save_chat(2, &chat(2)).unwrap();
+        assert_eq!(store.inner.load_chat(1).unwrap(), chat(1));
+        assert!(store.dirty.is_empty());
+
+        store.save_chat(1, &chat(3)).unwrap();
+        store.mark_processed(1).unwrap();
+        assert_eq!(store.inner.load_chat(1).unwrap(), chat(3));
+
+        store.save_chat(1, &chat(4)).unwrap();
+        store.set_last_update_id(1).unwrap();
+        assert_eq!(store.inner.load_chat(1).unwrap(), chat(4));
+    }
+
+    #[test]
+    fn should_stay_bounded_with_many_chats() {
+        let (mut store, _) = prepare(100);
+
+        (0..100_000).for_each(|chat_id| {
+            store.load_chat(chat_id).unwrap();
+        });
+
+        let metrics = store.cache_metrics().unwrap();
+        assert_eq!(metrics.entries, 100);
+        assert_eq!(store.recency.len(), 100);
+        assert_eq!(metrics.evictions, 100_000 - 100);
+    }
+}
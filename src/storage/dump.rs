@@ -0,0 +1,424 @@
+use std::{collections::BTreeMap, fmt::Display};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::commands::CommandRegistry;
+
+use super::{AuditEntry, ChatState, OutboxEntry, OutboxStatus, StateStore, StoreError};
+
+/// Version of the dump format written by `export`.
+pub const DUMP_VERSION: u32 = 2;
+
+type Migration = fn(Value) -> Value;
+
+/// Upgrades of older dumps, the function turns version `n` into `n + 1`.
+const MIGRATIONS: [(u32, Migration); 1] = [(1, migrate_v1_to_v2)];
+
+/// Everything the bot persisted, as written by `export`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Dump {
+    pub version: u32,
+    /// Unix timestamp.
+    pub exported_at: i64,
+    pub last_update_id: Option<u64>,
+    pub chats: BTreeMap<i64, ChatState>,
+    /// Bot-wide values by key: runtime access lists, budget overrides, maintenance,
+    /// `/config` overrides and a running broadcast.
+    pub values: BTreeMap<String, Value>,
+    /// Audit log from the oldest entry.
+    pub audit: Vec<AuditEntry>,
+    /// Messages waiting for delivery.
+    pub outbox: Vec<OutboxEntry>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ImportStrategy {
+    /// Chats and values from the dump overwrite the same ones in the store, other ones are
+    /// kept.
+    #[default]
+    Merge,
+    /// The store keeps only chats, values and queued messages from the dump.
+    Replace,
+}
+
+#[derive(Debug)]
+pub enum DumpError {
+    Parse(serde_json::Error),
+    MissingVersion,
+    UnsupportedVersion(u32),
+}
+
+impl Display for DumpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DumpError::Parse(e) => write!(f, "cannot parse dump: {}", e),
+            DumpError::MissingVersion => write!(f, "dump has no version"),
+            DumpError::UnsupportedVersion(version) => write!(
+                f,
+                "dump version {} is not supported, versions 1 to {} are",
+                version, DUMP_VERSION
+            ),
+        }
+    }
+}
+
+impl From<serde_json::Error> for DumpError {
+    fn from(value: serde_json::Error) -> Self {
+        DumpError::Parse(value)
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ImportReport {
+    /// Chats.
+    pub imported: usize,
+    pub removed: usize,
+    pub values: usize,
+    /// Audit entries which were missing from the store.
+    pub audit: usize,
+    /// Messages which were not queued in the store yet.
+    pub outbox: usize,
+    /// Queued messages of the replaced state which are not sent.
+    pub dropped: usize,
+}
+
+pub fn export(store: &mut dyn StateStore, now: i64) -> Result<Dump, StoreError> {
+    Ok(Dump {
+        version: DUMP_VERSION,
+        exported_at: now,
+        last_update_id: store.last_update_id()?,
+        chats: store.chats()?.into_iter().collect(),
+        values: store.values()?,
+        audit: store.audit_entries()?,
+        outbox: store.undelivered()?,
+    })
+}
+
+/// Parse a dump of any known version, older ones are migrated to the current one.
+/// Returns the dump and its original version.
+pub fn parse(content: &str) -> Result<(Dump, u32), DumpError> {
+    let mut value: Value = serde_json::from_str(content)?;
+
+    let original = value
+        .get("version")
+        .and_then(Value::as_u64)
+        .ok_or(DumpError::MissingVersion)? as u32;
+
+    if original == 0 || original > DUMP_VERSION {
+        return Err(DumpError::UnsupportedVersion(original));
+    }
+
+    for (from, migrate) in MIGRATIONS {
+        if from >= original {
+            value = migrate(value);
+            value["version"] = json!(from + 1);
+        }
+    }
+
+    Ok((serde_json::from_value(value)?, original))
+}
+
+/// Problems which would make the imported state unusable.
+pub fn validate(dump: &Dump) -> Vec<String> {
+    let registry = CommandRegistry::new();
+    let mut problems = vec![];
+
+    for (chat_id, chat) in &dump.chats {
+        if chat.repeat_number == Some(0) {
+            problems.push(format!(
+                "chat {}: repeat number must be greater than 0",
+                chat_id
+            ));
+        }
+
+        if let Some(conversation) = &chat.conversation {
+            match registry.dialog(&conversation.dialog) {
+                None => problems.push(format!(
+                    "chat {}: unknown dialog {}",
+                    chat_id, conversation.dialog
+                )),
+                Some(dialog) if dialog.step(&conversation.step).is_none() => {
+                    problems.push(format!(
+                        "chat {}: unknown step {} of dialog {}",
+                        chat_id, conversation.step, conversation.dialog
+                    ))
+                }
+                Some(_) => {}
+            }
+        }
+    }
+
+    problems
+}
+
+pub fn import(
+    store: &mut dyn StateStore,
+    dump: &Dump,
+    strategy: ImportStrategy,
+) -> Result<ImportReport, StoreError> {
+    let mut report = ImportReport::default();
+
+    if strategy == ImportStrategy::Replace {
+        for (chat_id, _) in store.chats()? {
+            if !dump.chats.contains_key(&chat_id) {
+                store.save_chat(chat_id, &ChatState::default())?;
+                report.removed += 1;
+            }
+        }
+        for key in store.values()?.keys() {
+            if !dump.values.contains_key(key) {
+                store.set_value(key, None)?;
+            }
+        }
+        // messages of the replaced state may be for chats which are gone, they are given up
+        // and kept as failed, so their keys are still known
+        for mut entry in store.undelivered()? {
+            if !dump.outbox.iter().any(|queued| queued.key == entry.key) {
+                entry.status = OutboxStatus::Failed;
+                entry.last_error = Some("dropped by import with replace".to_string());
+                store.save_outbox_entry(&entry)?;
+                report.dropped += 1;
+            }
+        }
+    }
+
+    for (chat_id, chat) in &dump.chats {
+        store.save_chat(*chat_id, chat)?;
+        report.imported += 1;
+    }
+
+    for (key, value) in &dump.values {
+        store.set_value(key, Some(value))?;
+        report.values += 1;
+    }
+
+    // the audit log is append-only, so both strategies add only entries it does not have
+    let known = store.audit_entries()?;
+    let same = |a: &AuditEntry, b: &AuditEntry| {
+        AuditEntry { id: 0, ..a.clone() } == AuditEntry { id: 0, ..b.clone() }
+    };
+    for entry in &dump.audit {
+        if !known.iter().any(|known| same(known, entry)) {
+            store.append_audit(entry)?;
+            report.audit += 1;
+        }
+    }
+
+    // a message with a known idempotency key is not queued again
+    for entry in &dump.outbox {
+        if store.enqueue(&entry.key, entry.chat_id, &entry.payload)? {
+            report.outbox += 1;
+        }
+    }
+
+    let last_update_id = match (strategy, store.last_update_id()?, dump.last_update_id) {
+        (ImportStrategy::Merge, Some(current), Some(imported)) => Some(current.max(imported)),
+        (_, current, imported) => imported.or(current),
+    };
+
+    if let Some(update_id) = last_update_id {
+        store.set_last_update_id(update_id)?;
+    }
+
+    store.flush()?;
+
+    Ok(report)
+}
+
+/// Version 2 added bot-wide values, the audit log and the outbox.
+fn migrate_v1_to_v2(mut value: Value) -> Value {
+    value["values"] = json!({});
+    value["audit"] = json!([]);
+    value["outbox"] = json!([]);
+
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::MemoryStore;
+
+    use super::*;
+
+    fn chat(repeat_number: u8) -> ChatState {
+        ChatState {
+            repeat_number: Some(repeat_number),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn should_export_and_parse_dump() {
+        let mut store = MemoryStore::new();
+        store.save_chat(1, &chat(3)).unwrap();
+        store.set_last_update_id(7).unwrap();
+        store
+            .set_value("access", Some(&json!({"banned": [5]})))
+            .unwrap();
+        store
+            .append_audit(&AuditEntry::new(
+                1,
+                Some(2),
+                3,
+                "maintenance",
+                json!(false),
+                json!(true),
+            ))
+            .unwrap();
+        store.enqueue("7:0", 1, &json!({"text": "hi"})).unwrap();
+
+        let dump = export(&mut store, 100).unwrap();
+        let content = serde_json::to_string(&dump).unwrap();
+
+        assert_eq!(dump.values.len(), 1);
+        assert_eq!(dump.audit.len(), 1);
+        assert_eq!(dump.outbox.len(), 1);
+        assert_eq!(parse(&content).unwrap(), (dump.clone(), DUMP_VERSION));
+
+        // another host gets the same bans, audit log and queued messages
+        let mut other = MemoryStore::new();
+        let report = import(&mut other, &dump, ImportStrategy::Merge).unwrap();
+        assert_eq!(
+            report,
+            ImportReport {
+                imported: 1,
+                removed: 0,
+                values: 1,
+                audit: 1,
+                outbox: 1,
+                dropped: 0
+            }
+        );
+        assert_eq!(export(&mut other, 100).unwrap(), dump);
+
+        // importing twice does not duplicate the log or the queue
+        let report = import(&mut other, &dump, ImportStrategy::Merge).unwrap();
+        assert_eq!((report.audit, report.outbox), (0, 0));
+        assert_eq!(other.audit_entries().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn should_migrate_version_1_dump() {
+        let content = r#"{"version": 1, "exported_at": 0, "last_update_id": 5, "chats": {
+            "1": {"repeat_number": 3, "conversation": null}
+        }}"#;
+
+        let (dump, version) = parse(content).unwrap();
+
+        assert_eq!(version, 1);
+        assert_eq!(dump.version, DUMP_VERSION);
+        assert_eq!(dump.chats, BTreeMap::from([(1, chat(3))]));
+        assert_eq!((dump.values.len(), dump.audit.len()), (0, 0));
+        assert_eq!(dump.outbox, vec![]);
+    }
+
+    #[test]
+    fn should_reject_unknown_versions() {
+        assert!(matches!(
+            parse(r#"{"version": 99}"#),
+            Err(DumpError::UnsupportedVersion(99))
+        ));
+        assert!(matches!(
+            parse(r#"{"version": 0}"#),
+            Err(DumpError::UnsupportedVersion(0))
+        ));
+        assert!(matches!(parse(r#"{}"#), Err(DumpError::MissingVersion)));
+    }
+
+    #[test]
+    fn should_find_invalid_chats() {
+        let content = r#"{"version": 1, "exported_at": 0, "last_update_id": null, "chats": {
+            "1": {"repeat_number": 0, "conversation": null},
+            "2": {"repeat_number": null, "conversation":
+                {"dialog": "unknown", "step": "number", "expires_at": 0, "data": {}}}
+        }}"#;
+
+        let (dump, _) = parse(content).unwrap();
+
+        assert_eq!(
+            validate(&dump),
+            vec![
+                "chat 1: repeat number must be greater than 0",
+                "chat 2: unknown dialog unknown"
+            ]
+        );
+    }
+
+    #[test]
+    fn should_merge_dump_into_store() {
+        let mut store = MemoryStore::new();
+        store.save_chat(1, &chat(2)).unwrap();
+        store.save_chat(2, &chat(2)).unwrap();
+        store.set_last_update_id(10).unwrap();
+        store.set_value("maintenance", Some(&json!(true))).unwrap();
+
+        let dump = Dump {
+            version: DUMP_VERSION,
+            exported_at: 0,
+            last_update_id: Some(5),
+            chats: BTreeMap::from([(2, chat(4)), (3, chat(5))]),
+            values: BTreeMap::from([("budgets".to_string(), json!({"1": [5, 100]}))]),
+            audit: vec![],
+            outbox: vec![],
+        };
+
+        let report = import(&mut store, &dump, ImportStrategy::Merge).unwrap();
+
+        assert_eq!(
+            report,
+            ImportReport {
+                imported: 2,
+                removed: 0,
+                values: 1,
+                ..Default::default()
+            }
+        );
+        assert_eq!(store.values().unwrap().len(), 2);
+        assert_eq!(store.load_chat(1).unwrap(), chat(2));
+        assert_eq!(store.load_chat(2).unwrap(), chat(4));
+        assert_eq!(store.load_chat(3).unwrap(), chat(5));
+        assert_eq!(store.last_update_id().unwrap(), Some(10));
+    }
+
+    #[test]
+    fn should_replace_store_with_dump() {
+        let mut store = MemoryStore::new();
+        store.save_chat(1, &chat(2)).unwrap();
+        store.set_last_update_id(10).unwrap();
+        store.set_value("maintenance", Some(&json!(true))).unwrap();
+        store.enqueue("1:0", 1, &json!({"text": "old"})).unwrap();
+        store.enqueue("3:0", 3, &json!({"text": "kept"})).unwrap();
+        let kept = store.undelivered().unwrap().pop().unwrap();
+
+        let dump = Dump {
+            version: DUMP_VERSION,
+            exported_at: 0,
+            last_update_id: Some(5),
+            chats: BTreeMap::from([(3, chat(5))]),
+            values: BTreeMap::from([("budgets".to_string(), json!({"1": [5, 100]}))]),
+            audit: vec![],
+            outbox: vec![kept.clone()],
+        };
+
+        let report = import(&mut store, &dump, ImportStrategy::Replace).unwrap();
+
+        assert_eq!(
+            report,
+            ImportReport {
+                imported: 1,
+                removed: 1,
+                values: 1,
+                dropped: 1,
+                ..Default::default()
+            }
+        );
+        // a message of a removed chat is not sent, one from the dump still is
+        assert_eq!(store.undelivered().unwrap(), vec![kept]);
+        assert_eq!(store.failed_deliveries().unwrap()[0].key, "1:0");
+        assert_eq!(store.values().unwrap(), dump.values);
+        assert_eq!(store.load_chat(1).unwrap(), ChatState::default());
+        assert_eq!(store.load_chat(3).unwrap(), chat(5));
+        assert_eq!(store.last_update_id().unwrap(), Some(5));
+    }
+}
@@ -0,0 +1,170 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{ErrorKind, Write},
+    path::PathBuf,
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    audit::AuditLog, outbox::Outbox, recent::RecentUpdates, AuditEntry, ChatState, OutboxEntry,
+    StateStore, StoreError,
+};
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct Snapshot {
+    last_update_id: Option<u64>,
+    chats: BTreeMap<i64, ChatState>,
+    #[serde(default)]
+    recent_updates: RecentUpdates,
+    #[serde(default)]
+    outbox: Outbox,
+    #[serde(default)]
+    values: BTreeMap<String, serde_json::Value>,
+    #[serde(default)]
+    audit: AuditLog,
+}
+
+/// Keeps whole state in memory and rewrites the file on every change.
+pub struct JsonFileStore {
+    path: PathBuf,
+    snapshot: Snapshot,
+}
+
+impl JsonFileStore {
+    pub fn open(path: &str) -> Result<Self, StoreError> {
+        let path = PathBuf::from(path);
+
+        let snapshot = match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content)?,
+            Err(e) if e.kind() == ErrorKind::NotFound => Snapshot::default(),
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(JsonFileStore { path, snapshot })
+    }
+
+    /// Write to a temporary file and rename it, so a crash never leaves a half written file.
+    fn write_snapshot(&self) -> Result<(), StoreError> {
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(&serde_json::to_vec_pretty(&self.snapshot)?)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, &self.path)?;
+
+        Ok(())
+    }
+}
+
+impl StateStore for JsonFileStore {
+    fn load_chat(&mut self, chat_id: i64) -> Result<ChatState, StoreError> {
+        Ok(self
+            .snapshot
+            .chats
+            .get(&chat_id)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    fn save_chat(&mut self, chat_id: i64, state: &ChatState) -> Result<(), StoreError> {
+        if *state == ChatState::default() {
+            self.snapshot.chats.remove(&chat_id);
+        } else {
+            self.snapshot.chats.insert(chat_id, state.clone());
+        }
+
+        self.write_snapshot()
+    }
+
+    fn chats(&mut self) -> Result<Vec<(i64, ChatState)>, StoreError> {
+        Ok(self
+            .snapshot
+            .chats
+            .iter()
+            .map(|(chat_id, chat)| (*chat_id, chat.clone()))
+            .collect())
+    }
+
+    fn last_update_id(&self) -> Result<Option<u64>, StoreError> {
+        Ok(self.snapshot.last_update_id)
+    }
+
+    fn set_last_update_id(&mut self, update_id: u64) -> Result<(), StoreError> {
+        self.snapshot.last_update_id = Some(update_id);
+
+        self.write_snapshot()
+    }
+
+    fn is_processed(&self, update_id: u64) -> Result<bool, StoreError> {
+        Ok(self.snapshot.recent_updates.contains(update_id))
+    }
+
+    fn mark_processed(&mut self, update_id: u64) -> Result<(), StoreError> {
+        self.snapshot.recent_updates.insert(update_id);
+
+        self.write_snapshot()
+    }
+
+    fn enqueue(
+        &mut self,
+        key: &str,
+        chat_id: i64,
+        payload: &serde_json::Value,
+    ) -> Result<bool, StoreError> {
+        let queued = self.snapshot.outbox.enqueue(key, chat_id, payload);
+
+        if queued {
+            self.write_snapshot()?;
+        }
+
+        Ok(queued)
+    }
+
+    fn undelivered(&self) -> Result<Vec<OutboxEntry>, StoreError> {
+        Ok(self.snapshot.outbox.undelivered())
+    }
+
+    fn failed_deliveries(&self) -> Result<Vec<OutboxEntry>, StoreError> {
+        Ok(self.snapshot.outbox.failed())
+    }
+
+    fn save_outbox_entry(&mut self, entry: &OutboxEntry) -> Result<(), StoreError> {
+        self.snapshot.outbox.save(entry);
+
+        self.write_snapshot()
+    }
+
+    fn value(&self, key: &str) -> Result<Option<serde_json::Value>, StoreError> {
+        Ok(self.snapshot.values.get(key).cloned())
+    }
+
+    fn values(&self) -> Result<BTreeMap<String, serde_json::Value>, StoreError> {
+        Ok(self.snapshot.values.clone())
+    }
+
+    fn set_value(
+        &mut self,
+        key: &str,
+        value: Option<&serde_json::Value>,
+    ) -> Result<(), StoreError> {
+        match value {
+            Some(value) => self.snapshot.values.insert(key.to_string(), value.clone()),
+            None => self.snapshot.values.remove(key),
+        };
+
+        self.write_snapshot()
+    }
+
+    fn append_audit(&mut self, entry: &AuditEntry) -> Result<(), StoreError> {
+        self.snapshot.audit.append(entry);
+
+        self.write_snapshot()
+    }
+
+    fn audit_entries(&self) -> Result<Vec<AuditEntry>, StoreError> {
+        Ok(self.snapshot.audit.entries())
+    }
+}
@@ -0,0 +1,123 @@
+use std::collections::{BTreeMap, HashMap};
+
+use super::{
+    audit::AuditLog, outbox::Outbox, recent::RecentUpdates, AuditEntry, ChatState, OutboxEntry,
+    StateStore, StoreError,
+};
+
+#[derive(Default)]
+pub struct MemoryStore {
+    last_update_id: Option<u64>,
+    chats: HashMap<i64, ChatState>,
+    recent_updates: RecentUpdates,
+    outbox: Outbox,
+    values: BTreeMap<String, serde_json::Value>,
+    audit: AuditLog,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl StateStore for MemoryStore {
+    fn load_chat(&mut self, chat_id: i64) -> Result<ChatState, StoreError> {
+        Ok(self.chats.get(&chat_id).cloned().unwrap_or_default())
+    }
+
+    fn save_chat(&mut self, chat_id: i64, state: &ChatState) -> Result<(), StoreError> {
+        if *state == ChatState::default() {
+            self.chats.remove(&chat_id);
+        } else {
+            self.chats.insert(chat_id, state.clone());
+        }
+
+        Ok(())
+    }
+
+    fn chats(&mut self) -> Result<Vec<(i64, ChatState)>, StoreError> {
+        let mut chats: Vec<_> = self
+            .chats
+            .iter()
+            .map(|(chat_id, chat)| (*chat_id, chat.clone()))
+            .collect();
+        chats.sort_by_key(|(chat_id, _)| *chat_id);
+
+        Ok(chats)
+    }
+
+    fn last_update_id(&self) -> Result<Option<u64>, StoreError> {
+        Ok(self.last_update_id)
+    }
+
+    fn set_last_update_id(&mut self, update_id: u64) -> Result<(), StoreError> {
+        self.last_update_id = Some(update_id);
+
+        Ok(())
+    }
+
+    fn is_processed(&self, update_id: u64) -> Result<bool, StoreError> {
+        Ok(self.recent_updates.contains(update_id))
+    }
+
+    fn mark_processed(&mut self, update_id: u64) -> Result<(), StoreError> {
+        self.recent_updates.insert(update_id);
+
+        Ok(())
+    }
+
+    fn enqueue(
+        &mut self,
+        key: &str,
+        chat_id: i64,
+        payload: &serde_json::Value,
+    ) -> Result<bool, StoreError> {
+        Ok(self.outbox.enqueue(key, chat_id, payload))
+    }
+
+    fn undelivered(&self) -> Result<Vec<OutboxEntry>, StoreError> {
+        Ok(self.outbox.undelivered())
+    }
+
+    fn failed_deliveries(&self) -> Result<Vec<OutboxEntry>, StoreError> {
+        Ok(self.outbox.failed())
+    }
+
+    fn save_outbox_entry(&mut self, entry: &OutboxEntry) -> Result<(), StoreError> {
+        self.outbox.save(entry);
+
+        Ok(())
+    }
+
+    fn value(&self, key: &str) -> Result<Option<serde_json::Value>, StoreError> {
+        Ok(self.values.get(key).cloned())
+    }
+
+    fn values(&self) -> Result<BTreeMap<String, serde_json::Value>, StoreError> {
+        Ok(self.values.clone())
+    }
+
+    fn set_value(
+        &mut self,
+        key: &str,
+        value: Option<&serde_json::Value>,
+    ) -> Result<(), StoreError> {
+        match value {
+            Some(value) => self.values.insert(key.to_string(), value.clone()),
+            None => self.values.remove(key),
+        };
+
+        Ok(())
+    }
+
+    fn append_audit(&mut self, entry: &AuditEntry) -> Result<(), StoreError> {
+        self.audit.append(entry);
+
+        Ok(())
+    }
+
+    fn audit_entries(&self) -> Result<Vec<AuditEntry>, StoreError> {
+        Ok(self.audit.entries())
+    }
+}
@@ -0,0 +1,395 @@
+mod audit;
+mod broadcast;
+mod cache;
+pub mod dump;
+mod json_file;
+mod memory;
+mod outbox;
+mod recent;
+mod registry;
+mod sqlite;
+
+use std::{collections::BTreeMap, fmt::Display};
+
+use serde::{Deserialize, Serialize};
+
+pub use audit::{AuditEntry, AuditFilter};
+pub use broadcast::{Broadcast, BroadcastFailure};
+pub use cache::{CacheConfig, CacheMetrics, CachedStore};
+pub use json_file::JsonFileStore;
+pub use memory::MemoryStore;
+pub use outbox::{OutboxEntry, OutboxStatus, DELIVERED_LIMIT, FAILED_LIMIT};
+pub use recent::RECENT_UPDATES_LIMIT;
+pub use registry::{BotStatus, ChatInfo};
+pub use sqlite::SqliteStore;
+
+use crate::{commands::Session, conversation::Conversation};
+
+/// Everything the bot remembers about one chat.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChatState {
+    pub repeat_number: Option<u8>,
+    pub conversation: Option<Conversation>,
+    /// Bot cannot write to the chat until it writes to the bot again.
+    #[serde(default)]
+    pub inactive: bool,
+    /// Registry entry, known for telegram chats.
+    #[serde(default)]
+    pub info: Option<ChatInfo>,
+}
+
+impl Session for ChatState {
+    fn repeat_number(&self) -> Option<u8> {
+        self.repeat_number
+    }
+
+    fn set_repeat_number(&mut self, number: u8) {
+        self.repeat_number = Some(number);
+    }
+
+    fn conversation(&self) -> Option<Conversation> {
+        self.conversation.clone()
+    }
+
+    fn set_conversation(&mut self, conversation: Option<Conversation>) {
+        self.conversation = conversation;
+    }
+}
+
+#[derive(Debug)]
+pub enum StoreError {
+    Io(std::io::Error),
+    Serialize(serde_json::Error),
+    Sqlite(rusqlite::Error),
+}
+
+impl Display for StoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StoreError::Io(e) => write!(f, "io error: {}", e),
+            StoreError::Serialize(e) => write!(f, "serialization error: {}", e),
+            StoreError::Sqlite(e) => write!(f, "sqlite error: {}", e),
+        }
+    }
+}
+
+impl From<std::io::Error> for StoreError {
+    fn from(value: std::io::Error) -> Self {
+        StoreError::Io(value)
+    }
+}
+
+impl From<serde_json::Error> for StoreError {
+    fn from(value: serde_json::Error) -> Self {
+        StoreError::Serialize(value)
+    }
+}
+
+impl From<rusqlite::Error> for StoreError {
+    fn from(value: rusqlite::Error) -> Self {
+        StoreError::Sqlite(value)
+    }
+}
+
+/// Persistent state of the bot: per-chat state and telegram polling offset.
+pub trait StateStore {
+    /// State of the chat, default one if the chat is not known.
+    fn load_chat(&mut self, chat_id: i64) -> Result<ChatState, StoreError>;
+    /// Saving default state forgets the chat.
+    fn save_chat(&mut self, chat_id: i64, state: &ChatState) -> Result<(), StoreError>;
+    /// All known chats ordered by id.
+    fn chats(&mut self) -> Result<Vec<(i64, ChatState)>, StoreError>;
+    fn last_update_id(&self) -> Result<Option<u64>, StoreError>;
+    fn set_last_update_id(&mut self, update_id: u64) -> Result<(), StoreError>;
+    /// Whether the update is among the last `RECENT_UPDATES_LIMIT` processed ones.
+    fn is_processed(&self, update_id: u64) -> Result<bool, StoreError>;
+    fn mark_processed(&mut self, update_id: u64) -> Result<(), StoreError>;
+    /// Queue an outgoing message, false if a message with this key was already queued.
+    fn enqueue(
+        &mut self,
+        key: &str,
+        chat_id: i64,
+        payload: &serde_json::Value,
+    ) -> Result<bool, StoreError>;
+    /// Pending messages in queue order.
+    fn undelivered(&self) -> Result<Vec<OutboxEntry>, StoreError>;
+    /// Messages given up on in queue order, at most `FAILED_LIMIT` latest ones.
+    fn failed_deliveries(&self) -> Result<Vec<OutboxEntry>, StoreError>;
+    /// Save status and attempts of a queued message.
+    fn save_outbox_entry(&mut self, entry: &OutboxEntry) -> Result<(), StoreError>;
+    /// Bot-wide value, e.g. a running broadcast. Like an outbox payload it is opaque for the store.
+    fn value(&self, key: &str) -> Result<Option<serde_json::Value>, StoreError>;
+    /// Every bot-wide value by key, e.g. to export them.
+    fn values(&self) -> Result<BTreeMap<String, serde_json::Value>, StoreError>;
+    /// Saving `None` removes the value.
+    fn set_value(&mut self, key: &str, value: Option<&serde_json::Value>)
+        -> Result<(), StoreError>;
+    /// Append an entry to the audit log, its id is assigned by the store.
+    fn append_audit(&mut self, entry: &AuditEntry) -> Result<(), StoreError>;
+    /// Audit log from the oldest entry.
+    fn audit_entries(&self) -> Result<Vec<AuditEntry>, StoreError>;
+    /// Write buffered changes, stores without a buffer write on every change.
+    fn flush(&mut self) -> Result<(), StoreError> {
+        Ok(())
+    }
+    fn cache_metrics(&self) -> Option<CacheMetrics> {
+        None
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(tag = "type")]
+pub enum StorageConfig {
+    /// State is lost on restart.
+    #[default]
+    #[serde(rename = "memory")]
+    Memory,
+    #[serde(rename = "json")]
+    Json { path: String },
+    #[serde(rename = "sqlite")]
+    Sqlite { path: String },
+}
+
+pub fn open(config: &StorageConfig) -> Result<Box<dyn StateStore>, StoreError> {
+    let store: Box<dyn StateStore> = match config {
+        StorageConfig::Memory => Box::new(MemoryStore::new()),
+        StorageConfig::Json { path } => Box::new(JsonFileStore::open(path)?),
+        StorageConfig::Sqlite { path } => Box::new(SqliteStore::open(path)?),
+    };
+
+    Ok(store)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, path::PathBuf, process};
+
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("echo_bot_{}_{}", process::id(), name));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn chat_with_repeat(number: u8) -> ChatState {
+        ChatState {
+            repeat_number: Some(number),
+            ..Default::default()
+        }
+    }
+
+    fn check_store(store: &mut dyn StateStore) {
+        assert_eq!(store.load_chat(1).unwrap(), ChatState::default());
+        assert_eq!(store.last_update_id().unwrap(), None);
+
+        store.save_chat(1, &chat_with_repeat(3)).unwrap();
+        store.save_chat(2, &chat_with_repeat(4)).unwrap();
+        store.save_chat(2, &chat_with_repeat(5)).unwrap();
+        store.set_last_update_id(10).unwrap();
+
+        assert_eq!(store.load_chat(1).unwrap(), chat_with_repeat(3));
+        assert_eq!(store.load_chat(2).unwrap(), chat_with_repeat(5));
+        assert_eq!(store.last_update_id().unwrap(), Some(10));
+
+        store.save_chat(1, &ChatState::default()).unwrap();
+        assert_eq!(store.load_chat(1).unwrap(), ChatState::default());
+
+        let inactive = ChatState {
+            inactive: true,
+            ..chat_with_repeat(5)
+        };
+        store.save_chat(2, &inactive).unwrap();
+        assert_eq!(store.load_chat(2).unwrap(), inactive);
+        store.save_chat(2, &chat_with_repeat(5)).unwrap();
+
+        assert!(!store.is_processed(10).unwrap());
+        store.mark_processed(10).unwrap();
+        store.mark_processed(10).unwrap();
+        assert!(store.is_processed(10).unwrap());
+
+        let payload = serde_json::json!({"text": "hi"});
+        assert!(store.enqueue("10:0", 1, &payload).unwrap());
+        assert!(store.enqueue("10:1", 1, &payload).unwrap());
+        assert!(!store.enqueue("10:0", 1, &payload).unwrap());
+
+        let mut entries = store.undelivered().unwrap();
+        assert_eq!(
+            entries.iter().map(|e| e.key.as_str()).collect::<Vec<_>>(),
+            vec!["10:0", "10:1"]
+        );
+        assert_eq!(entries[0].payload, payload);
+
+        entries[0].status = OutboxStatus::Delivered;
+        store.save_outbox_entry(&entries[0]).unwrap();
+        entries[1].attempts = 1;
+        entries[1].chat_id = -1;
+        entries[1].last_error = Some("timeout".to_string());
+        store.save_outbox_entry(&entries[1]).unwrap();
+
+        assert_eq!(store.undelivered().unwrap(), vec![entries[1].clone()]);
+        assert!(!store.enqueue("10:0", 1, &payload).unwrap());
+
+        assert!(store.enqueue("10:2", 1, &payload).unwrap());
+        let mut failed = store.undelivered().unwrap().remove(1);
+        failed.status = OutboxStatus::Failed;
+        store.save_outbox_entry(&failed).unwrap();
+        assert_eq!(store.undelivered().unwrap(), vec![entries[1].clone()]);
+        assert_eq!(store.failed_deliveries().unwrap(), vec![failed]);
+        assert!(!store.enqueue("10:2", 1, &payload).unwrap());
+
+        assert_eq!(store.value("broadcast").unwrap(), None);
+        store.set_value("broadcast", Some(&payload)).unwrap();
+        store
+            .set_value("other", Some(&serde_json::json!(1)))
+            .unwrap();
+        assert_eq!(store.value("broadcast").unwrap(), Some(payload));
+        store.set_value("other", None).unwrap();
+        assert_eq!(store.value("other").unwrap(), None);
+
+        let entry = AuditEntry::new(
+            5,
+            Some(7),
+            -1,
+            "config.help_msg",
+            serde_json::Value::Null,
+            serde_json::json!("help"),
+        );
+        store.append_audit(&entry).unwrap();
+        store
+            .append_audit(&AuditEntry::new(6, None, 0, "repeat", 1.into(), 2.into()))
+            .unwrap();
+        let entries = store.audit_entries().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0], AuditEntry { id: 1, ..entry });
+        assert_eq!((entries[1].id, entries[1].actor), (2, None));
+    }
+
+    #[test]
+    fn memory_store_should_keep_state() {
+        check_store(&mut MemoryStore::new());
+    }
+
+    #[test]
+    fn outbox_should_forget_oldest_failed_entries() {
+        let mut store = MemoryStore::new();
+        let payload = serde_json::json!({"text": "hi"});
+
+        for n in 0..=FAILED_LIMIT {
+            store.enqueue(&n.to_string(), 1, &payload).unwrap();
+            let mut entry = store.undelivered().unwrap().remove(0);
+            entry.status = OutboxStatus::Failed;
+            store.save_outbox_entry(&entry).unwrap();
+        }
+
+        let failed = store.failed_deliveries().unwrap();
+        assert_eq!(failed.len(), FAILED_LIMIT);
+        assert_eq!(failed[0].key, "1");
+        assert_eq!(store.undelivered().unwrap(), vec![]);
+    }
+
+    #[test]
+    fn json_store_should_keep_state_after_reopen() {
+        let path = temp_path("store.json");
+        let path_str = path.to_str().unwrap();
+
+        check_store(&mut JsonFileStore::open(path_str).unwrap());
+
+        let mut store = JsonFileStore::open(path_str).unwrap();
+        assert_eq!(store.load_chat(1).unwrap(), ChatState::default());
+        assert_eq!(store.load_chat(2).unwrap(), chat_with_repeat(5));
+        assert_eq!(store.last_update_id().unwrap(), Some(10));
+        assert!(store.is_processed(10).unwrap());
+        assert_eq!(store.undelivered().unwrap().len(), 1);
+        assert!(store.value("broadcast").unwrap().is_some());
+        assert_eq!(store.audit_entries().unwrap().len(), 2);
+
+        let mut tmp_path = path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        assert!(!PathBuf::from(tmp_path).exists());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn json_store_should_fail_on_broken_file() {
+        let path = temp_path("broken.json");
+        fs::write(&path, "{ not json").unwrap();
+
+        assert!(matches!(
+            JsonFileStore::open(path.to_str().unwrap()),
+            Err(StoreError::Serialize(_))
+        ));
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn sqlite_store_should_keep_state_after_reopen() {
+        let path = temp_path("store.sqlite");
+        let path_str = path.to_str().unwrap();
+
+        check_store(&mut SqliteStore::open(path_str).unwrap());
+
+        let mut store = SqliteStore::open(path_str).unwrap();
+        assert_eq!(store.load_chat(1).unwrap(), ChatState::default());
+        assert_eq!(store.load_chat(2).unwrap(), chat_with_repeat(5));
+        assert_eq!(store.last_update_id().unwrap(), Some(10));
+        assert!(store.is_processed(10).unwrap());
+        assert_eq!(store.undelivered().unwrap().len(), 1);
+        assert!(store.value("broadcast").unwrap().is_some());
+        assert_eq!(store.audit_entries().unwrap().len(), 2);
+
+        let connection = rusqlite::Connection::open(&path).unwrap();
+        assert!(connection.execute("DELETE FROM audit_log", []).is_err());
+        assert!(connection
+            .execute("UPDATE audit_log SET action = 'repeat'", [])
+            .is_err());
+        assert_eq!(store.audit_entries().unwrap().len(), 2);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn sqlite_store_should_upgrade_database_without_inactive_column() {
+        let path = temp_path("old.sqlite");
+        rusqlite::Connection::open(&path)
+            .unwrap()
+            .execute_batch(
+                "CREATE TABLE chats (chat_id INTEGER PRIMARY KEY, repeat_number INTEGER,
+                     conversation TEXT);
+                 INSERT INTO chats VALUES (1, 3, NULL);",
+            )
+            .unwrap();
+
+        let mut store = SqliteStore::open(path.to_str().unwrap()).unwrap();
+        assert_eq!(store.load_chat(1).unwrap(), chat_with_repeat(3));
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn sqlite_store_should_forget_oldest_processed_updates() {
+        let path = temp_path("recent.sqlite");
+        let mut store = SqliteStore::open(path.to_str().unwrap()).unwrap();
+
+        (0..RECENT_UPDATES_LIMIT as u64 + 5).for_each(|id| store.mark_processed(id).unwrap());
+
+        assert!(!store.is_processed(4).unwrap());
+        assert!(store.is_processed(5).unwrap());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn should_parse_storage_config() {
+        let config: StorageConfig = serde_yaml::from_str("type: sqlite\npath: bot.db").unwrap();
+
+        assert_eq!(
+            config,
+            StorageConfig::Sqlite {
+                path: "bot.db".to_string()
+            }
+        );
+    }
+}
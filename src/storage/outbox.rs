@@ -0,0 +1,127 @@
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+/// How many delivered entries are kept to recognize their idempotency keys.
+pub const DELIVERED_LIMIT: usize = 1000;
+
+/// How many entries given up on are kept to be listed and to recognize their keys.
+pub const FAILED_LIMIT: usize = 1000;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutboxStatus {
+    #[serde(rename = "pending")]
+    Pending,
+    #[serde(rename = "delivered")]
+    Delivered,
+    /// Delivery was given up after too many attempts.
+    #[serde(rename = "failed")]
+    Failed,
+}
+
+impl OutboxStatus {
+    pub fn name(&self) -> &'static str {
+        match self {
+            OutboxStatus::Pending => "pending",
+            OutboxStatus::Delivered => "delivered",
+            OutboxStatus::Failed => "failed",
+        }
+    }
+}
+
+impl Display for OutboxStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Outgoing message waiting for delivery. Payload is opaque for the store,
+/// it is defined by the frontend which sends it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct OutboxEntry {
+    /// Position in the queue, assigned by the store.
+    pub id: u64,
+    /// Idempotency key: a message with a known key is not queued again.
+    pub key: String,
+    pub chat_id: i64,
+    pub payload: serde_json::Value,
+    pub status: OutboxStatus,
+    pub attempts: u32,
+    /// Unix timestamp, the entry is not sent before it.
+    pub next_attempt_at: i64,
+    pub last_error: Option<String>,
+}
+
+/// Queue kept in memory, used by stores which do not have their own.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct Outbox {
+    next_id: u64,
+    entries: Vec<OutboxEntry>,
+}
+
+impl Outbox {
+    pub fn enqueue(&mut self, key: &str, chat_id: i64, payload: &serde_json::Value) -> bool {
+        if self.entries.iter().any(|entry| entry.key == key) {
+            return false;
+        }
+
+        self.next_id += 1;
+        self.entries.push(OutboxEntry {
+            id: self.next_id,
+            key: key.to_string(),
+            chat_id,
+            payload: payload.clone(),
+            status: OutboxStatus::Pending,
+            attempts: 0,
+            next_attempt_at: 0,
+            last_error: None,
+        });
+
+        true
+    }
+
+    pub fn undelivered(&self) -> Vec<OutboxEntry> {
+        self.with_status(OutboxStatus::Pending)
+    }
+
+    pub fn failed(&self) -> Vec<OutboxEntry> {
+        self.with_status(OutboxStatus::Failed)
+    }
+
+    fn with_status(&self, status: OutboxStatus) -> Vec<OutboxEntry> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.status == status)
+            .cloned()
+            .collect()
+    }
+
+    /// Delivered and failed entries are kept only to remember their keys, the oldest are
+    /// forgotten.
+    pub fn save(&mut self, entry: &OutboxEntry) {
+        if let Some(saved) = self.entries.iter_mut().find(|saved| saved.id == entry.id) {
+            *saved = entry.clone();
+        }
+
+        self.forget_oldest(OutboxStatus::Delivered, DELIVERED_LIMIT);
+        self.forget_oldest(OutboxStatus::Failed, FAILED_LIMIT);
+    }
+
+    fn forget_oldest(&mut self, status: OutboxStatus, limit: usize) {
+        let count = self
+            .entries
+            .iter()
+            .filter(|entry| entry.status == status)
+            .count();
+        let mut excess = count.saturating_sub(limit);
+
+        self.entries.retain(|entry| {
+            if excess > 0 && entry.status == status {
+                excess -= 1;
+                return false;
+            }
+
+            true
+        });
+    }
+}
@@ -0,0 +1,48 @@
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+/// How many processed update ids are remembered for de-duplication.
+pub const RECENT_UPDATES_LIMIT: usize = 1000;
+
+/// Bounded set of recently processed update ids, the oldest one is forgotten first.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct RecentUpdates {
+    ids: VecDeque<u64>,
+}
+
+impl RecentUpdates {
+    pub fn contains(&self, update_id: u64) -> bool {
+        self.ids.contains(&update_id)
+    }
+
+    pub fn insert(&mut self, update_id: u64) {
+        if self.contains(update_id) {
+            return;
+        }
+
+        self.ids.push_back(update_id);
+
+        while self.ids.len() > RECENT_UPDATES_LIMIT {
+            self.ids.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_forget_oldest_updates() {
+        let mut recent = RecentUpdates::default();
+
+        (0..RECENT_UPDATES_LIMIT as u64 + 5).for_each(|id| recent.insert(id));
+
+        assert!(!recent.contains(4));
+        assert!(recent.contains(5));
+        assert!(recent.contains(RECENT_UPDATES_LIMIT as u64 + 4));
+        assert_eq!(recent.ids.len(), RECENT_UPDATES_LIMIT);
+    }
+}
@@ -0,0 +1,96 @@
+use std::fmt::Display;
+
+use chrono::{TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Status of the bot in a chat. Deserialized from telegram `ChatMember.status` as well.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BotStatus {
+    #[serde(rename = "member", alias = "restricted")]
+    Member,
+    #[serde(rename = "admin", alias = "administrator", alias = "creator")]
+    Admin,
+    /// Removed from the group or blocked by the user.
+    #[serde(rename = "kicked")]
+    Kicked,
+    #[serde(rename = "left")]
+    Left,
+}
+
+impl BotStatus {
+    pub fn name(&self) -> &'static str {
+        match self {
+            BotStatus::Member => "member",
+            BotStatus::Admin => "admin",
+            BotStatus::Kicked => "kicked",
+            BotStatus::Left => "left",
+        }
+    }
+
+    /// The bot can write to the chat.
+    pub fn is_present(&self) -> bool {
+        matches!(self, BotStatus::Member | BotStatus::Admin)
+    }
+}
+
+impl Display for BotStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// What is known about a chat the bot is or was in.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ChatInfo {
+    /// Telegram chat type: private, group, supergroup or channel.
+    pub kind: String,
+    /// Title of a group or username of a private chat.
+    pub title: Option<String>,
+    pub status: BotStatus,
+    /// Unix timestamp when the bot was last added to the chat, unknown for
+    /// chats which wrote to the bot before they were tracked.
+    pub joined_at: Option<i64>,
+    /// Unix timestamp of the last message or button press.
+    pub last_activity_at: Option<i64>,
+}
+
+impl ChatInfo {
+    /// Chat which wrote to the bot before any membership change was seen.
+    pub fn new(kind: String, title: Option<String>) -> Self {
+        ChatInfo {
+            kind,
+            title,
+            status: BotStatus::Member,
+            joined_at: None,
+            last_activity_at: None,
+        }
+    }
+
+    /// Apply a membership change of the bot.
+    pub fn set_status(&mut self, status: BotStatus, date: i64) {
+        let joined = status.is_present() && (!self.status.is_present() || self.joined_at.is_none());
+        if joined {
+            self.joined_at = Some(date);
+        }
+        self.status = status;
+    }
+
+    /// One line of the chat list: id, type, title, status and dates.
+    pub fn line(&self, chat_id: i64) -> String {
+        let date = |timestamp: Option<i64>| {
+            timestamp
+                .and_then(|timestamp| Utc.timestamp_opt(timestamp, 0).single())
+                .map_or("-".to_string(), |date| date.to_rfc3339())
+        };
+
+        format!(
+            "{} {} {} {}, joined {}, last activity {}",
+            chat_id,
+            self.kind,
+            self.title.as_deref().unwrap_or("-"),
+            self.status,
+            date(self.joined_at),
+            date(self.last_activity_at)
+        )
+    }
+}
@@ -0,0 +1,431 @@
+use std::collections::BTreeMap;
+
+use rusqlite::{params, Connection, OptionalExtension};
+
+use super::{
+    AuditEntry, ChatState, OutboxEntry, OutboxStatus, StateStore, StoreError, DELIVERED_LIMIT,
+    FAILED_LIMIT, RECENT_UPDATES_LIMIT,
+};
+
+pub struct SqliteStore {
+    connection: Connection,
+}
+
+impl SqliteStore {
+    pub fn open(path: &str) -> Result<Self, StoreError> {
+        let connection = Connection::open(path)?;
+
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS meta (
+                key TEXT PRIMARY KEY,
+                value INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS chats (
+                chat_id INTEGER PRIMARY KEY,
+                repeat_number INTEGER,
+                conversation TEXT,
+                inactive INTEGER NOT NULL DEFAULT 0,
+                info TEXT
+            );
+            CREATE TABLE IF NOT EXISTS processed_updates (
+                update_id INTEGER PRIMARY KEY
+            );
+            CREATE TABLE IF NOT EXISTS outbox (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                key TEXT NOT NULL UNIQUE,
+                chat_id INTEGER NOT NULL,
+                payload TEXT NOT NULL,
+                status TEXT NOT NULL,
+                attempts INTEGER NOT NULL,
+                next_attempt_at INTEGER NOT NULL,
+                last_error TEXT
+            );
+            CREATE TABLE IF NOT EXISTS bot_values (
+                key TEXT PRIMARY KEY,
+                value TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS audit_log (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                at INTEGER NOT NULL,
+                actor INTEGER,
+                chat_id INTEGER NOT NULL,
+                action TEXT NOT NULL,
+                old TEXT NOT NULL,
+                new TEXT NOT NULL
+            );
+            -- the audit log is append-only
+            CREATE TRIGGER IF NOT EXISTS audit_log_no_update BEFORE UPDATE ON audit_log
+            BEGIN
+                SELECT RAISE(ABORT, 'audit log is append-only');
+            END;
+            CREATE TRIGGER IF NOT EXISTS audit_log_no_delete BEFORE DELETE ON audit_log
+            BEGIN
+                SELECT RAISE(ABORT, 'audit log is append-only');
+            END;",
+        )?;
+
+        // databases created before the column was added
+        add_missing_column(&connection, "chats", "inactive INTEGER NOT NULL DEFAULT 0")?;
+        add_missing_column(&connection, "chats", "info TEXT")?;
+
+        Ok(SqliteStore { connection })
+    }
+
+    fn outbox_entries(&self, status: OutboxStatus) -> Result<Vec<OutboxEntry>, StoreError> {
+        let mut statement = self.connection.prepare(
+            "SELECT id, key, chat_id, payload, status, attempts, next_attempt_at, last_error
+             FROM outbox WHERE status = ?1 ORDER BY id",
+        )?;
+
+        let rows = statement
+            .query_map(params![status.name()], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, i64>(2)?,
+                    row.get::<_, String>(3)?,
+                    row.get::<_, String>(4)?,
+                    row.get::<_, u32>(5)?,
+                    row.get::<_, i64>(6)?,
+                    row.get::<_, Option<String>>(7)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        rows.into_iter()
+            .map(
+                |(id, key, chat_id, payload, status, attempts, next_attempt_at, last_error)| {
+                    Ok(OutboxEntry {
+                        id: id as u64,
+                        key,
+                        chat_id,
+                        payload: serde_json::from_str(&payload)?,
+                        status: serde_json::from_value(serde_json::Value::String(status))?,
+                        attempts,
+                        next_attempt_at,
+                        last_error,
+                    })
+                },
+            )
+            .collect()
+    }
+}
+
+fn add_missing_column(
+    connection: &Connection,
+    table: &str,
+    definition: &str,
+) -> Result<(), StoreError> {
+    let column = definition.split_whitespace().next().unwrap_or_default();
+    let exists = connection
+        .prepare(&format!(
+            "SELECT 1 FROM pragma_table_info('{}') WHERE name = ?1",
+            table
+        ))?
+        .exists(params![column])?;
+
+    if !exists {
+        connection.execute_batch(&format!("ALTER TABLE {} ADD COLUMN {}", table, definition))?;
+    }
+
+    Ok(())
+}
+
+/// Repeat number, conversation, inactive flag and registry entry, the last ones as json.
+type ChatRow = (Option<u8>, Option<String>, bool, Option<String>);
+
+fn chat_from_row(
+    (repeat_number, conversation, inactive, info): ChatRow,
+) -> Result<ChatState, StoreError> {
+    Ok(ChatState {
+        repeat_number,
+        conversation: conversation
+            .map(|json| serde_json::from_str(&json))
+            .transpose()?,
+        inactive,
+        info: info.map(|json| serde_json::from_str(&json)).transpose()?,
+    })
+}
+
+impl StateStore for SqliteStore {
+    fn load_chat(&mut self, chat_id: i64) -> Result<ChatState, StoreError> {
+        let row: Option<ChatRow> = self
+            .connection
+            .query_row(
+                "SELECT repeat_number, conversation, inactive, info FROM chats WHERE chat_id = ?1",
+                params![chat_id],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+            )
+            .optional()?;
+
+        row.map_or(Ok(ChatState::default()), chat_from_row)
+    }
+
+    fn save_chat(&mut self, chat_id: i64, state: &ChatState) -> Result<(), StoreError> {
+        if *state == ChatState::default() {
+            self.connection
+                .execute("DELETE FROM chats WHERE chat_id = ?1", params![chat_id])?;
+            return Ok(());
+        }
+
+        let conversation = state
+            .conversation
+            .as_ref()
+            .map(serde_json::to_string)
+            .transpose()?;
+        let info = state.info.as_ref().map(serde_json::to_string).transpose()?;
+
+        self.connection.execute(
+            "INSERT INTO chats (chat_id, repeat_number, conversation, inactive, info)
+             VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT (chat_id) DO UPDATE
+             SET repeat_number = excluded.repeat_number, conversation = excluded.conversation,
+                 inactive = excluded.inactive, info = excluded.info",
+            params![
+                chat_id,
+                state.repeat_number,
+                conversation,
+                state.inactive,
+                info
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    fn chats(&mut self) -> Result<Vec<(i64, ChatState)>, StoreError> {
+        let mut statement = self.connection.prepare(
+            "SELECT chat_id, repeat_number, conversation, inactive, info FROM chats
+             ORDER BY chat_id",
+        )?;
+
+        let rows = statement
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    (row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?),
+                ))
+            })?
+            .collect::<Result<Vec<(i64, ChatRow)>, _>>()?;
+
+        rows.into_iter()
+            .map(|(chat_id, row)| Ok((chat_id, chat_from_row(row)?)))
+            .collect()
+    }
+
+    fn last_update_id(&self) -> Result<Option<u64>, StoreError> {
+        let value: Option<i64> = self
+            .connection
+            .query_row(
+                "SELECT value FROM meta WHERE key = 'last_update_id'",
+                [],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        Ok(value.map(|v| v as u64))
+    }
+
+    fn set_last_update_id(&mut self, update_id: u64) -> Result<(), StoreError> {
+        self.connection.execute(
+            "INSERT INTO meta (key, value) VALUES ('last_update_id', ?1)
+             ON CONFLICT (key) DO UPDATE SET value = excluded.value",
+            params![update_id as i64],
+        )?;
+
+        Ok(())
+    }
+
+    fn is_processed(&self, update_id: u64) -> Result<bool, StoreError> {
+        let found: Option<i64> = self
+            .connection
+            .query_row(
+                "SELECT update_id FROM processed_updates WHERE update_id = ?1",
+                params![update_id as i64],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        Ok(found.is_some())
+    }
+
+    fn mark_processed(&mut self, update_id: u64) -> Result<(), StoreError> {
+        let transaction = self.connection.transaction()?;
+
+        transaction.execute(
+            "INSERT OR IGNORE INTO processed_updates (update_id) VALUES (?1)",
+            params![update_id as i64],
+        )?;
+        transaction.execute(
+            "DELETE FROM processed_updates WHERE update_id NOT IN (
+                SELECT update_id FROM processed_updates ORDER BY update_id DESC LIMIT ?1
+            )",
+            params![RECENT_UPDATES_LIMIT as i64],
+        )?;
+
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    fn enqueue(
+        &mut self,
+        key: &str,
+        chat_id: i64,
+        payload: &serde_json::Value,
+    ) -> Result<bool, StoreError> {
+        let inserted = self.connection.execute(
+            "INSERT OR IGNORE INTO outbox
+             (key, chat_id, payload, status, attempts, next_attempt_at, last_error)
+             VALUES (?1, ?2, ?3, ?4, 0, 0, NULL)",
+            params![
+                key,
+                chat_id,
+                payload.to_string(),
+                OutboxStatus::Pending.name()
+            ],
+        )?;
+
+        Ok(inserted > 0)
+    }
+
+    fn undelivered(&self) -> Result<Vec<OutboxEntry>, StoreError> {
+        self.outbox_entries(OutboxStatus::Pending)
+    }
+
+    fn failed_deliveries(&self) -> Result<Vec<OutboxEntry>, StoreError> {
+        self.outbox_entries(OutboxStatus::Failed)
+    }
+
+    fn save_outbox_entry(&mut self, entry: &OutboxEntry) -> Result<(), StoreError> {
+        let transaction = self.connection.transaction()?;
+
+        transaction.execute(
+            "UPDATE outbox SET chat_id = ?2, status = ?3, attempts = ?4, next_attempt_at = ?5,
+                 last_error = ?6
+             WHERE id = ?1",
+            params![
+                entry.id as i64,
+                entry.chat_id,
+                entry.status.name(),
+                entry.attempts,
+                entry.next_attempt_at,
+                entry.last_error
+            ],
+        )?;
+        for (status, limit) in [
+            (OutboxStatus::Delivered, DELIVERED_LIMIT),
+            (OutboxStatus::Failed, FAILED_LIMIT),
+        ] {
+            transaction.execute(
+                "DELETE FROM outbox WHERE status = ?1 AND id NOT IN (
+                    SELECT id FROM outbox WHERE status = ?1 ORDER BY id DESC LIMIT ?2
+                )",
+                params![status.name(), limit as i64],
+            )?;
+        }
+
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    fn value(&self, key: &str) -> Result<Option<serde_json::Value>, StoreError> {
+        let value: Option<String> = self
+            .connection
+            .query_row(
+                "SELECT value FROM bot_values WHERE key = ?1",
+                params![key],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        Ok(value.map(|json| serde_json::from_str(&json)).transpose()?)
+    }
+
+    fn values(&self) -> Result<BTreeMap<String, serde_json::Value>, StoreError> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT key, value FROM bot_values ORDER BY key")?;
+
+        let rows = statement
+            .query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        rows.into_iter()
+            .map(|(key, json)| Ok((key, serde_json::from_str(&json)?)))
+            .collect()
+    }
+
+    fn set_value(
+        &mut self,
+        key: &str,
+        value: Option<&serde_json::Value>,
+    ) -> Result<(), StoreError> {
+        match value {
+            Some(value) => self.connection.execute(
+                "INSERT INTO bot_values (key, value) VALUES (?1, ?2)
+                 ON CONFLICT (key) DO UPDATE SET value = excluded.value",
+                params![key, value.to_string()],
+            )?,
+            None => self
+                .connection
+                .execute("DELETE FROM bot_values WHERE key = ?1", params![key])?,
+        };
+
+        Ok(())
+    }
+
+    fn append_audit(&mut self, entry: &AuditEntry) -> Result<(), StoreError> {
+        self.connection.execute(
+            "INSERT INTO audit_log (at, actor, chat_id, action, old, new)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                entry.at,
+                entry.actor.map(|actor| actor as i64),
+                entry.chat_id,
+                entry.action,
+                entry.old.to_string(),
+                entry.new.to_string()
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    fn audit_entries(&self) -> Result<Vec<AuditEntry>, StoreError> {
+        let mut statement = self.connection.prepare(
+            "SELECT id, at, actor, chat_id, action, old, new FROM audit_log ORDER BY id",
+        )?;
+
+        let rows = statement
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, Option<i64>>(2)?,
+                    row.get::<_, i64>(3)?,
+                    row.get::<_, String>(4)?,
+                    row.get::<_, String>(5)?,
+                    row.get::<_, String>(6)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        rows.into_iter()
+            .map(|(id, at, actor, chat_id, action, old, new)| {
+                Ok(AuditEntry {
+                    id: id as u64,
+                    at,
+                    actor: actor.map(|actor| actor as u64),
+                    chat_id,
+                    action,
+                    old: serde_json::from_str(&old)?,
+                    new: serde_json::from_str(&new)?,
+                })
+            })
+            .collect()
+    }
+}
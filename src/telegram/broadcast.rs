@@ -0,0 +1,121 @@
+use crate::{
+    logger::Logger,
+    storage::{Broadcast, StateStore, StoreError},
+};
+
+use super::{
+    client::TelegramClient,
+    client_types::{ClientError, Payload},
+    outbox::{mark_inactive, migrate_chat, retry_delay, MAX_ATTEMPTS},
+};
+
+/// Send the running broadcast to at most `rate` chats and edit the progress message
+/// if anything changed. Progress is saved after every chat. A chat which blocked the bot
+/// fails at once, other errors are retried like outbox messages and the broadcast waits.
+pub fn deliver<T: TelegramClient<Err = ClientError>>(
+    client: &T,
+    logger: &dyn Logger,
+    store: &mut dyn StateStore,
+    rate: u32,
+    now: i64,
+) -> Result<(), StoreError> {
+    let Some(mut broadcast) = Broadcast::load(store)? else {
+        return Ok(());
+    };
+    if broadcast.is_finished() {
+        return Ok(());
+    }
+
+    if broadcast.progress_message_id.is_none() {
+        match client.send(
+            broadcast.admin_chat_id,
+            Payload::Text(&broadcast.progress()),
+        ) {
+            Ok(message) => {
+                broadcast.progress_message_id = Some(message.message_id);
+                broadcast.save(store)?;
+            }
+            Err(e) => logger.log_warn(
+                format!(
+                    "Failed to send broadcast progress to chat {}: {:?}",
+                    broadcast.admin_chat_id, e
+                )
+                .as_str(),
+            ),
+        }
+    }
+
+    let reported = broadcast.progress();
+    let mut sent = 0;
+
+    while sent < rate && broadcast.next < broadcast.chats.len() && broadcast.next_attempt_at <= now
+    {
+        let chat_id = broadcast.chats[broadcast.next];
+        sent += 1;
+
+        if store.load_chat(chat_id)?.inactive {
+            broadcast.advance(Some("chat is inactive".to_string()));
+            broadcast.save(store)?;
+            continue;
+        }
+
+        let mut result = client.send(chat_id, Payload::Text(&broadcast.text));
+
+        if let Err(ClientError::ChatMigrated { to_chat_id }) = result {
+            migrate_chat(logger, store, chat_id, to_chat_id)?;
+            broadcast.chats[broadcast.next] = to_chat_id;
+            result = client.send(to_chat_id, Payload::Text(&broadcast.text));
+        }
+
+        match result {
+            Ok(_) => broadcast.advance(None),
+            Err(ClientError::ChatUnavailable(description)) => {
+                mark_inactive(logger, store, broadcast.chats[broadcast.next], &description)?;
+                broadcast.advance(Some(description));
+            }
+            Err(e) if broadcast.attempts + 1 >= MAX_ATTEMPTS => {
+                logger
+                    .log_error(format!("Give up broadcast to chat {}: {:?}", chat_id, e).as_str());
+                broadcast.advance(Some(format!("{:?}", e)));
+            }
+            Err(e) => {
+                broadcast.attempts += 1;
+                broadcast.next_attempt_at = now + retry_delay(broadcast.attempts);
+                logger.log_warn(
+                    format!(
+                        "Failed to broadcast to chat {}, attempt {}: {:?}",
+                        chat_id, broadcast.attempts, e
+                    )
+                    .as_str(),
+                );
+            }
+        }
+
+        broadcast.save(store)?;
+    }
+
+    if broadcast.next == broadcast.chats.len() {
+        broadcast.finished_at = Some(now);
+        broadcast.save(store)?;
+        logger.log_info(
+            format!(
+                "Broadcast finished: {} delivered, {} failed",
+                broadcast.delivered,
+                broadcast.failed.len()
+            )
+            .as_str(),
+        );
+    }
+
+    let progress = broadcast.progress();
+    if let Some(message_id) = broadcast
+        .progress_message_id
+        .filter(|_| progress != reported)
+    {
+        if let Err(e) = client.edit_text(broadcast.admin_chat_id, message_id, &progress) {
+            logger.log_warn(format!("Failed to edit broadcast progress: {:?}", e).as_str());
+        }
+    }
+
+    Ok(())
+}
@@ -2,18 +2,26 @@ use serde::de::DeserializeOwned;
 use serde_json::json;
 use ureq::Response;
 
-use crate::logger::Logger;
+use crate::{custom::MediaKind, logger::Logger};
 
 use super::client_types::{
-    ClientError, Message, Payload, RawUpdate, TelegramCommand, TelegramResponse,
+    ChatMember, ClientError, CommandScope, Message, Payload, RawUpdate, TelegramCommand,
+    TelegramResponse,
 };
 
 pub trait TelegramClient {
     type Err;
     fn get_updates(&self, offset: u64) -> Result<Vec<RawUpdate>, Self::Err>;
-    fn send(&self, chat_id: u64, payload: Payload) -> Result<Message, Self::Err>;
+    fn send(&self, chat_id: i64, payload: Payload) -> Result<Message, Self::Err>;
     fn answer_callback_query(&self, id: &str, text: &str) -> Result<bool, Self::Err>;
-    fn set_commands(&self, commands: Vec<TelegramCommand>) -> Result<bool, Self::Err>;
+    fn set_commands(
+        &self,
+        commands: Vec<TelegramCommand>,
+        scope: CommandScope,
+    ) -> Result<bool, Self::Err>;
+    fn get_chat_member(&self, chat_id: i64, user_id: u64) -> Result<ChatMember, Self::Err>;
+    /// Replace text of a message sent by the bot.
+    fn edit_text(&self, chat_id: i64, message_id: i64, text: &str) -> Result<Message, Self::Err>;
 }
 
 pub struct TelegramHttpClient<'a> {
@@ -39,8 +47,14 @@ impl<'a> TelegramHttpClient<'a> {
         &self,
         response: Result<Response, ureq::Error>,
     ) -> Result<T, ClientError> {
+        let response = match response {
+            Ok(response) => response,
+            // telegram describes failed requests in the body of 4xx responses
+            Err(ureq::Error::Status(_, response)) => response,
+            Err(e) => return Err(ClientError::Http(Box::new(e))),
+        };
+
         response
-            .map_err(|e| ClientError::Http(Box::new(e)))?
             .into_json::<TelegramResponse<T>>()
             .map_err(ClientError::Serialize)?
             .into_result()
@@ -66,7 +80,7 @@ impl<'a> TelegramClient for TelegramHttpClient<'a> {
         response
     }
 
-    fn send(&self, chat_id: u64, payload: Payload) -> Result<Message, Self::Err> {
+    fn send(&self, chat_id: i64, payload: Payload) -> Result<Message, Self::Err> {
         let body;
         let method;
 
@@ -85,6 +99,26 @@ impl<'a> TelegramClient for TelegramHttpClient<'a> {
                 }});
                 method = "sendMessage";
             }
+            Payload::Media {
+                kind,
+                file_id,
+                caption,
+                keyboard,
+            } => {
+                let mut json = json!({"chat_id": chat_id, kind.name(): file_id});
+                if let Some(caption) = caption {
+                    json["caption"] = json!(caption);
+                }
+                if let Some(keyboard) = keyboard {
+                    json["reply_markup"] = json!({ "inline_keyboard": keyboard.into_json() });
+                }
+                body = json;
+                method = match kind {
+                    MediaKind::Photo => "sendPhoto",
+                    MediaKind::Video => "sendVideo",
+                    MediaKind::Document => "sendDocument",
+                };
+            }
         }
 
         let response = ureq::post(self.url(method).as_str()).send_json(body);
@@ -107,15 +141,48 @@ impl<'a> TelegramClient for TelegramHttpClient<'a> {
         self.parse(response)
     }
 
-    fn set_commands(&self, commands: Vec<TelegramCommand>) -> Result<bool, Self::Err> {
+    fn set_commands(
+        &self,
+        commands: Vec<TelegramCommand>,
+        scope: CommandScope,
+    ) -> Result<bool, Self::Err> {
         let json_commands: Vec<serde_json::Value> = commands
             .into_iter()
-            .map(|tg_command| json!({"command": tg_command.command.into_string(), "description": tg_command.description}))
+            .map(|tg_command| json!({"command": tg_command.command, "description": tg_command.description}))
             .collect();
+        let json_scope = match scope {
+            CommandScope::Default => json!({"type": "default"}),
+            CommandScope::Chat(chat_id) => json!({"type": "chat", "chat_id": chat_id}),
+            CommandScope::AllChatAdministrators => json!({"type": "all_chat_administrators"}),
+        };
 
         let response = ureq::post(self.url("setMyCommands").as_str())
-            .send_json(json!({ "commands": json_commands }));
+            .send_json(json!({ "commands": json_commands, "scope": json_scope }));
 
         self.parse(response)
     }
+
+    fn get_chat_member(&self, chat_id: i64, user_id: u64) -> Result<ChatMember, Self::Err> {
+        let response = ureq::post(self.url("getChatMember").as_str())
+            .send_json(json!({"chat_id": chat_id, "user_id": user_id}));
+
+        let response: Result<ChatMember, Self::Err> = self.parse(response);
+
+        self.logger
+            .log_debug(format!("get response from getChatMember: {:#?}", response).as_str());
+
+        response
+    }
+
+    fn edit_text(&self, chat_id: i64, message_id: i64, text: &str) -> Result<Message, Self::Err> {
+        let response = ureq::post(self.url("editMessageText").as_str())
+            .send_json(json!({"chat_id": chat_id, "message_id": message_id, "text": text}));
+
+        let response: Result<Message, Self::Err> = self.parse(response);
+
+        self.logger
+            .log_debug(format!("get response from editMessageText: {:#?}", response).as_str());
+
+        response
+    }
 }
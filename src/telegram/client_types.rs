@@ -1,6 +1,10 @@
 use serde::Deserialize;
 
-use crate::commands::Command;
+use crate::{
+    commands::CommandSpec,
+    custom::MediaKind,
+    storage::{BotStatus, StoreError},
+};
 
 use super::keyboard::InlineKeyboardMarkup;
 
@@ -15,7 +19,7 @@ impl<T> TelegramResponse<T> {
     pub fn into_result(self) -> Result<T, ClientError> {
         match self {
             Self::Success { result } => Ok(result),
-            Self::Failure(error) => Err(ClientError::Api(error)),
+            Self::Failure(error) => Err(error.into()),
         }
     }
 }
@@ -25,12 +29,48 @@ pub enum ClientError {
     Http(Box<ureq::Error>),
     Serialize(std::io::Error),
     Api(TelegramApiError),
+    /// Group was upgraded to a supergroup, which has a new chat id.
+    ChatMigrated {
+        to_chat_id: i64,
+    },
+    /// Bot cannot write to the chat (403): blocked by the user, kicked from the group
+    /// or the user is deactivated. Contains description from telegram.
+    ChatUnavailable(String),
+    /// State store failed while an update was handled.
+    Storage(StoreError),
+}
+
+impl From<StoreError> for ClientError {
+    fn from(value: StoreError) -> Self {
+        ClientError::Storage(value)
+    }
 }
 
 #[derive(Deserialize, Debug)]
 pub struct TelegramApiError {
-    error_code: u8,
-    description: String,
+    pub error_code: u16,
+    pub description: String,
+    pub parameters: Option<ResponseParameters>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ResponseParameters {
+    pub migrate_to_chat_id: Option<i64>,
+}
+
+impl From<TelegramApiError> for ClientError {
+    fn from(error: TelegramApiError) -> Self {
+        let migrate_to_chat_id = error
+            .parameters
+            .as_ref()
+            .and_then(|parameters| parameters.migrate_to_chat_id);
+
+        match (migrate_to_chat_id, error.error_code) {
+            (Some(to_chat_id), _) => ClientError::ChatMigrated { to_chat_id },
+            (None, 403) => ClientError::ChatUnavailable(error.description),
+            (None, _) => ClientError::Api(error),
+        }
+    }
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -38,10 +78,13 @@ pub struct RawUpdate {
     pub update_id: u64,
     pub message: Option<Message>,
     pub callback_query: Option<CallbackQuery>,
+    /// Status of the bot in a chat was changed: it was added, promoted, removed or blocked.
+    pub my_chat_member: Option<ChatMemberUpdated>,
 }
 
 #[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct Message {
+    pub message_id: i64,
     pub chat: Chat,
     pub from: Option<User>,
     pub video: Option<Video>,
@@ -51,6 +94,8 @@ pub struct Message {
 #[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct CallbackQuery {
     pub id: String,
+    /// User who pressed the button.
+    pub from: Option<User>,
     pub message: Message,
     pub data: String,
 }
@@ -61,9 +106,28 @@ pub struct User {
     pub is_bot: bool,
 }
 
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
 pub struct Chat {
-    pub id: u64,
+    pub id: i64,
+    #[serde(rename = "type", default)]
+    pub kind: String,
+    /// Title of groups and channels.
+    pub title: Option<String>,
+    /// Username of private chats.
+    pub username: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ChatMemberUpdated {
+    pub chat: Chat,
+    /// Unix timestamp of the change.
+    pub date: i64,
+    pub new_chat_member: ChatMember,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ChatMember {
+    pub status: BotStatus,
 }
 
 #[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
@@ -75,19 +139,42 @@ pub enum Payload<'a> {
     Text(&'a str),
     Video(&'a str),
     TextWithKeyboard(InlineKeyboardMarkup, &'a str),
+    Media {
+        kind: MediaKind,
+        file_id: &'a str,
+        caption: Option<&'a str>,
+        keyboard: Option<InlineKeyboardMarkup>,
+    },
+}
+
+/// Who sees a list of commands in the telegram menu.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandScope {
+    /// Everyone without a more specific scope.
+    Default,
+    /// One chat, for a private chat it is the user with the same id.
+    Chat(i64),
+    /// Administrators of every group.
+    AllChatAdministrators,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct TelegramCommand {
-    pub command: Command,
+    pub command: String,
     pub description: String,
 }
 
 impl TelegramCommand {
-    pub fn new(command: Command, description: String) -> Self {
+    pub fn new(command: String, description: String) -> Self {
         Self {
             command,
             description,
         }
     }
-}
\ No newline at end of file
+}
+
+impl From<&CommandSpec> for TelegramCommand {
+    fn from(spec: &CommandSpec) -> Self {
+        Self::new(spec.name.clone(), spec.description.clone())
+    }
+}
@@ -1,11 +1,30 @@
-use std::collections::HashMap;
-
-use crate::{commands::Command, config::Config, logger::Logger};
+use std::cell::{OnceCell, RefCell};
+
+use chrono::Utc;
+
+use crate::{
+    access::{AccessLists, Denial, Sender},
+    budget::{BudgetOverrides, OutgoingBudget, Remaining, DEFAULT_NOTICE},
+    commands::{
+        dispatch, CommandContext, CommandRegistry, Frontend, IsCommand, Reply, Role, Session,
+        MAX_TEXT_CHARS,
+    },
+    config::{Config, DeliveryGuarantee, MAX_KEYBOARD_REPEAT_NUMBER},
+    conversation,
+    custom::{find_auto_reply, StaticReply},
+    flood::{FloodControl, Verdict},
+    logger::Logger,
+    maintenance::Maintenance,
+    overrides::ConfigOverrides,
+    storage::{BotStatus, ChatInfo, ChatState, StateStore, StoreError},
+};
 
 use super::{
+    broadcast,
     client::TelegramClient,
-    client_types::{Payload, RawUpdate},
+    client_types::{Chat, ClientError, RawUpdate},
     keyboard::{InlineKeyboardButton, InlineKeyboardMarkup},
+    outbox::{self, OutgoingMessage},
     update_converter::{CallbackData, MessageContent, TelegramUpdate},
 };
 
@@ -13,128 +32,600 @@ pub trait Handler<T: TelegramClient> {
     fn handle(
         &self,
         config: &Config,
-        state: &mut TelegramState,
+        store: &mut dyn StateStore,
         raw_update: RawUpdate,
     ) -> Result<(), T::Err>;
+    /// Send messages queued in the outbox which are due at `now`, then the next part
+    /// of a running broadcast.
+    fn deliver(&self, config: &Config, store: &mut dyn StateStore, now: i64) -> Result<(), T::Err>;
+}
+
+pub struct TelegramHandler<'a, 'b, T: TelegramClient> {
+    logger: &'a dyn Logger,
+    client: &'b T,
+    registry: &'a CommandRegistry,
+    flood: RefCell<FloodControl>,
+    budget: RefCell<OutgoingBudget>,
 }
 
-pub struct TelegramState {
-    pub last_update_id: Option<u64>,
-    pub repeat_numbers: HashMap<u64, u8>,
+/// Replies to one update after the outgoing budget of the chat is applied.
+enum Shaped {
+    Fits(Vec<OutgoingMessage>),
+    /// Texts combined into one message.
+    Collapsed(OutgoingMessage),
+    /// Only the first reply.
+    Truncated(OutgoingMessage),
 }
 
-impl TelegramState {
-    pub fn new() -> Self {
-        TelegramState {
-            last_update_id: None,
-            repeat_numbers: HashMap::new(),
+impl<'a, 'b, T: TelegramClient<Err = ClientError>> TelegramHandler<'a, 'b, T> {
+    pub fn new(logger: &'a dyn Logger, client: &'b T, registry: &'a CommandRegistry) -> Self {
+        Self {
+            logger,
+            client,
+            registry,
+            flood: RefCell::new(FloodControl::new()),
+            budget: RefCell::new(OutgoingBudget::new()),
         }
     }
 
-    pub fn set_repeat_number(&mut self, chat_id: u64, number: u8) {
-        self.repeat_numbers.insert(chat_id, number);
+    /// Handle the update on the chat state, queue the replies and save the state if it changed.
+    /// Replies get `<update_id>:<n>` idempotency keys, so a replayed update queues nothing new.
+    fn process(
+        &self,
+        config: &Config,
+        store: &mut dyn StateStore,
+        update: TelegramUpdate,
+    ) -> Result<(), T::Err> {
+        let (chat_id, mut chat) = match &update {
+            TelegramUpdate::Message { chat, .. }
+            | TelegramUpdate::CallbackQuery { chat, .. }
+            | TelegramUpdate::MemberStatus { chat, .. } => (chat.id, store.load_chat(chat.id)?),
+            TelegramUpdate::Ignore { .. } => return Ok(()),
+        };
+        let file_config = config;
+        let overrides = ConfigOverrides::load(store)?;
+        let config = &*overrides.apply(file_config);
+        if let Some(denial) = self.denial(config, store, &update)? {
+            return self.deny(config, store, update, denial);
+        }
+        if let Some(verdict) = self.flood_verdict(config, &update) {
+            return self.ignore_flood(config, store, update, verdict);
+        }
+        if self.is_in_maintenance(config, store, &update)? {
+            self.logger.log_debug(
+                format!(
+                    "Answer update {} with maintenance message",
+                    update.update_id()
+                )
+                .as_str(),
+            );
+            return self.drop_update(store, update, Some(&config.maintenance_msg));
+        }
+        let previous = chat.clone();
+        let update_id = update.update_id();
+        let mut outgoing = vec![];
+
+        match update {
+            TelegramUpdate::Message {
+                chat: source,
+                user_id,
+                content,
+                ..
+            } => {
+                self.logger
+                    .log_info(format!("Handle update: {}", update_id).as_str());
+                track_activity(&mut chat, &source);
+
+                let role = self.role_resolver(config, chat_id, user_id);
+                let ctx = self.context(config, file_config, store, chat_id, user_id, &role);
+
+                match content {
+                    MessageContent::Text(msg) => {
+                        self.handle_text_message(&ctx, &mut chat, msg, chat_id, &mut outgoing);
+                    }
+                    MessageContent::Command(initial_msg) => {
+                        self.handle_command_message(
+                            &ctx,
+                            &mut chat,
+                            initial_msg,
+                            chat_id,
+                            &mut outgoing,
+                        );
+                    }
+                    MessageContent::Video { file_id } => {
+                        self.handle_video_message(&mut chat, config, file_id, &mut outgoing);
+                    }
+                }
+            }
+            TelegramUpdate::CallbackQuery {
+                chat: source,
+                user_id,
+                content,
+                ..
+            } => {
+                track_activity(&mut chat, &source);
+
+                let role = self.role_resolver(config, chat_id, user_id);
+                let ctx = self.context(config, file_config, store, chat_id, user_id, &role);
+
+                self.handle_callback_query(&ctx, &mut chat, chat_id, content, &mut outgoing)?;
+            }
+            TelegramUpdate::MemberStatus {
+                chat: source,
+                status,
+                date,
+                ..
+            } => {
+                self.logger.log_info(
+                    format!("Bot status in chat {} changed to {}", chat_id, status).as_str(),
+                );
+                chat.info
+                    .get_or_insert_with(|| chat_info(&source))
+                    .set_status(status, date);
+                chat.inactive = !status.is_present();
+            }
+            TelegramUpdate::Ignore { .. } => {}
+        }
+
+        let outgoing = self.limit_outgoing(config, store, chat_id, update_id, outgoing)?;
+        for (index, message) in outgoing.iter().enumerate() {
+            let payload = serde_json::to_value(message).map_err(StoreError::from)?;
+            let key = format!("{}:{}", update_id, index);
+
+            if !store.enqueue(&key, chat_id, &payload)? {
+                self.logger
+                    .log_info(format!("Skip already queued message: {}", key).as_str());
+            }
+        }
+
+        if chat != previous {
+            store.save_chat(chat_id, &chat)?;
+        }
+
+        Ok(())
     }
-}
 
-pub struct TelegramHandler<'a, 'b, T: TelegramClient> {
-    logger: &'a dyn Logger,
-    client: &'b T,
-}
+    /// Fit replies into the outgoing budget of the chat. Over the budget the update still gets
+    /// one message: the texts combined if they fit, otherwise the first reply and, once a minute,
+    /// the notice.
+    fn limit_outgoing(
+        &self,
+        config: &Config,
+        store: &dyn StateStore,
+        chat_id: i64,
+        update_id: u64,
+        outgoing: Vec<OutgoingMessage>,
+    ) -> Result<Vec<OutgoingMessage>, StoreError> {
+        if outgoing.is_empty() {
+            return Ok(outgoing);
+        }
+        let global = config.outgoing_budget.as_ref();
+        let Some(limits) = BudgetOverrides::load(store)?.limits(chat_id, global) else {
+            return Ok(outgoing);
+        };
+
+        let now = Utc::now().timestamp();
+        let mut budget = self.budget.borrow_mut();
+        let replies = outgoing.len();
+        let outgoing = match shape(outgoing, budget.remaining(chat_id, &limits, now)) {
+            Shaped::Fits(outgoing) => outgoing,
+            Shaped::Collapsed(message) => {
+                self.logger.log_info(
+                    format!(
+                        "Collapse {} replies to update {}: outgoing budget of chat {} is low",
+                        replies, update_id, chat_id
+                    )
+                    .as_str(),
+                );
+                vec![message]
+            }
+            Shaped::Truncated(message) => {
+                self.logger.log_warn(
+                    format!(
+                        "Skip {} of {} replies to update {}: outgoing budget of chat {} is exceeded",
+                        replies - 1,
+                        replies,
+                        update_id,
+                        chat_id
+                    )
+                    .as_str(),
+                );
+                let mut outgoing = vec![message];
+                if budget.take_notice(chat_id, now) {
+                    let notice = global.map_or(DEFAULT_NOTICE, |budget| budget.notice.as_str());
+                    outgoing.push(OutgoingMessage::text(notice));
+                }
+                outgoing
+            }
+        };
+
+        let chars = outgoing.iter().map(OutgoingMessage::chars).sum();
+        budget.spend(chat_id, outgoing.len() as u32, chars, now);
 
-impl<'a, 'b, T: TelegramClient> TelegramHandler<'a, 'b, T> {
-    pub fn new(logger: &'a dyn Logger, client: &'b T) -> Self {
-        Self { logger, client }
+        Ok(outgoing)
     }
 
-    fn handle_text_message(
+    /// Why the sender of a message or a button press is not answered, if they are not.
+    fn denial(
         &self,
-        state: &mut TelegramState,
         config: &Config,
-        message: String,
-        chat_id: u64,
+        store: &dyn StateStore,
+        update: &TelegramUpdate,
+    ) -> Result<Option<Denial>, StoreError> {
+        let (chat, user_id) = match update {
+            TelegramUpdate::Message { chat, user_id, .. }
+            | TelegramUpdate::CallbackQuery { chat, user_id, .. } => (chat, *user_id),
+            _ => return Ok(None),
+        };
+        let sender = Sender {
+            user_id,
+            chat_id: chat.id,
+            chat_type: &chat.kind,
+        };
+
+        Ok(config
+            .access
+            .check(&AccessLists::load(store)?, &config.admins, &sender)
+            .err())
+    }
+
+    /// Denied updates are logged and get `denied_reply` if it is configured.
+    fn deny(
+        &self,
+        config: &Config,
+        store: &mut dyn StateStore,
+        update: TelegramUpdate,
+        denial: Denial,
     ) -> Result<(), T::Err> {
-        let repeat_number = state
-            .repeat_numbers
-            .get(&chat_id)
-            .map_or(config.default_repeat_number, |v| v.to_owned());
+        self.logger
+            .log_warn(format!("Deny update {}: {}", update.update_id(), denial).as_str());
 
-        (0..repeat_number).try_for_each(|_| {
-            self.client
-                .send(chat_id, Payload::Text(&message))
-                .map(|_| ())
-        })?;
+        self.drop_update(store, update, config.access.denied_reply.as_deref())
+    }
+
+    /// Muted senders are warned once, then ignored until the mute is over.
+    fn ignore_flood(
+        &self,
+        config: &Config,
+        store: &mut dyn StateStore,
+        update: TelegramUpdate,
+        verdict: Verdict,
+    ) -> Result<(), T::Err> {
+        let update_id = update.update_id();
+        let warning = match verdict {
+            Verdict::Mute { seconds } => {
+                self.logger.log_warn(
+                    format!(
+                        "Mute sender of update {} for {} seconds: too many messages",
+                        update_id, seconds
+                    )
+                    .as_str(),
+                );
+                config
+                    .flood_control
+                    .as_ref()
+                    .map(|flood| flood.warning(seconds))
+            }
+            _ => {
+                self.logger
+                    .log_debug(format!("Ignore update {} of muted sender", update_id).as_str());
+                None
+            }
+        };
+
+        self.drop_update(store, update, warning.as_deref())
+    }
+
+    /// Leave an update unhandled. A button press is always answered to stop its spinner,
+    /// a message gets `reply` if it is set.
+    fn drop_update(
+        &self,
+        store: &mut dyn StateStore,
+        update: TelegramUpdate,
+        reply: Option<&str>,
+    ) -> Result<(), T::Err> {
+        let update_id = update.update_id();
+
+        match update {
+            TelegramUpdate::CallbackQuery { content, .. } => {
+                self.client
+                    .answer_callback_query(&content.id, reply.unwrap_or_default())?;
+            }
+            TelegramUpdate::Message { chat, .. } => {
+                if let Some(reply) = reply {
+                    let payload = serde_json::to_value(OutgoingMessage::text(reply))
+                        .map_err(StoreError::from)?;
+                    store.enqueue(&format!("{}:0", update_id), chat.id, &payload)?;
+                }
+            }
+            _ => {}
+        }
 
         Ok(())
     }
 
-    fn handle_command_message(
+    /// Flood control of a message or a button press, `None` if the update is allowed.
+    fn flood_verdict(&self, config: &Config, update: &TelegramUpdate) -> Option<Verdict> {
+        let flood_config = config.flood_control.as_ref()?;
+        let (chat, user_id) = match update {
+            TelegramUpdate::Message { chat, user_id, .. }
+            | TelegramUpdate::CallbackQuery { chat, user_id, .. } => (chat, *user_id),
+            _ => return None,
+        };
+
+        let verdict =
+            self.flood
+                .borrow_mut()
+                .check(flood_config, user_id, chat.id, Utc::now().timestamp());
+
+        (verdict != Verdict::Allow).then_some(verdict)
+    }
+
+    /// Maintenance is on and the sender of a message or a button press is not an admin.
+    fn is_in_maintenance(
         &self,
-        state: &mut TelegramState,
         config: &Config,
-        command: Command,
+        store: &dyn StateStore,
+        update: &TelegramUpdate,
+    ) -> Result<bool, StoreError> {
+        let (chat, user_id) = match update {
+            TelegramUpdate::Message { chat, user_id, .. }
+            | TelegramUpdate::CallbackQuery { chat, user_id, .. } => (chat, *user_id),
+            _ => return Ok(false),
+        };
+
+        Ok(Maintenance::load(store)?.enabled
+            && self.resolve_role(config, chat.id, user_id) != Role::Admin)
+    }
+
+    /// Remember the update as processed and move the offset past it.
+    fn commit(&self, store: &mut dyn StateStore, update_id: u64) -> Result<(), StoreError> {
+        store.mark_processed(update_id)?;
+        store.set_last_update_id(update_id)
+    }
+
+    fn context<'c>(
+        &'c self,
+        config: &'c Config,
+        file_config: &'c Config,
+        store: &'c mut dyn StateStore,
+        chat_id: i64,
+        user_id: Option<u64>,
+        role: &'c dyn Fn() -> Role,
+    ) -> CommandContext<'c> {
+        CommandContext {
+            config,
+            file_config,
+            registry: self.registry,
+            frontend: Frontend::Telegram,
+            chat_id,
+            user_id,
+            logger: self.logger,
+            store: RefCell::new(store),
+            role,
+        }
+    }
+
+    /// Role of the sender, resolved once and only if a command asks for it.
+    fn role_resolver<'c>(
+        &'c self,
+        config: &'c Config,
+        chat_id: i64,
+        user_id: Option<u64>,
+    ) -> impl Fn() -> Role + 'c {
+        let role = OnceCell::new();
+
+        move || *role.get_or_init(|| self.resolve_role(config, chat_id, user_id))
+    }
+
+    /// Admins from config are admins everywhere. With `chat_admins` administrators
+    /// of a group are admins too, which is checked with `getChatMember`.
+    fn resolve_role(&self, config: &Config, chat_id: i64, user_id: Option<u64>) -> Role {
+        let Some(user_id) = user_id else {
+            return Role::User;
+        };
+
+        if config.admins.contains(&user_id) {
+            return Role::Admin;
+        }
+
+        // in a private chat the user is always "administrator" of it
+        if !config.chat_admins || chat_id == user_id as i64 {
+            return Role::User;
+        }
+
+        match self.client.get_chat_member(chat_id, user_id) {
+            Ok(member) if member.status == BotStatus::Admin => Role::Admin,
+            Ok(_) => Role::User,
+            Err(e) => {
+                self.logger.log_warn(
+                    format!(
+                        "Failed to get member {} of chat {}: {:?}",
+                        user_id, chat_id, e
+                    )
+                    .as_str(),
+                );
+                Role::User
+            }
+        }
+    }
+
+    fn handle_text_message(
+        &self,
+        ctx: &CommandContext,
+        chat: &mut ChatState,
+        message: String,
+        chat_id: i64,
+        outgoing: &mut Vec<OutgoingMessage>,
+    ) {
+        let config = ctx.config;
+
+        if let Some(reply) = conversation::handle_input(ctx, chat, &message) {
+            return self.send_reply(config, chat_id, reply, outgoing);
+        }
+
+        if let Some(reply) = find_auto_reply(&config.auto_replies, &message) {
+            self.logger
+                .log_info(format!("Auto reply to message in chat: {}", chat_id).as_str());
+            return self.send_static_reply(reply, outgoing);
+        }
+
+        let repeat_number = chat.repeat_number().unwrap_or(config.default_repeat_number);
+
+        (0..repeat_number).for_each(|_| outgoing.push(OutgoingMessage::text(message.as_str())));
+    }
+
+    fn handle_command_message(
+        &self,
+        ctx: &CommandContext,
+        chat: &mut ChatState,
         initial_msg: String,
-        chat_id: u64,
-    ) -> Result<(), T::Err> {
-        match command {
-            Command::Help => {
-                self.client.send(chat_id, Payload::Text(&config.help_msg))?;
-            }
-            Command::Repeat => {
-                let repeat_number = state
-                    .repeat_numbers
-                    .get(&chat_id)
-                    .map_or(config.default_repeat_number, |x| x.to_owned());
+        chat_id: i64,
+        outgoing: &mut Vec<OutgoingMessage>,
+    ) {
+        let reply = dispatch(ctx, chat, &initial_msg);
+
+        self.send_reply(ctx.config, chat_id, reply, outgoing)
+    }
+
+    fn send_reply(
+        &self,
+        config: &Config,
+        chat_id: i64,
+        reply: Reply,
+        outgoing: &mut Vec<OutgoingMessage>,
+    ) {
+        match reply {
+            Reply::Text(msg) => {
+                outgoing.push(OutgoingMessage::text(msg));
+            }
+            Reply::UnknownCommand { text, suggestions } if suggestions.is_empty() => {
+                outgoing.push(OutgoingMessage::text(text));
+            }
+            Reply::UnknownCommand { text, suggestions } => {
+                let mut markup = InlineKeyboardMarkup::new();
+                markup.add(
+                    suggestions
+                        .into_iter()
+                        .map(|name| InlineKeyboardButton::new(name.clone(), name))
+                        .collect(),
+                );
+
+                outgoing.push(OutgoingMessage::Text {
+                    text,
+                    keyboard: Some(markup),
+                });
+            }
+            Reply::Static(reply) => {
+                self.send_static_reply(&reply, outgoing);
+            }
+            Reply::RepeatPrompt { current } => {
                 let msg = format!(
                     "{}\nCurrent repeat number is {}",
-                    config.repeat_msg, repeat_number
+                    config.repeat_msg, current
                 );
-                self.client.send(
-                    chat_id,
-                    Payload::TextWithKeyboard(self.construct_inline_keyboard(), &msg),
-                )?;
+                outgoing.push(OutgoingMessage::Text {
+                    text: msg,
+                    keyboard: Some(self.construct_inline_keyboard()),
+                });
             }
-            Command::Unknown => {
-                let msg = format!("get unknown command: {}", initial_msg);
-                self.logger.log_warn(&msg);
-                self.client.send(chat_id, Payload::Text(&msg))?;
+            Reply::RepeatChanged(repeat_number) => {
+                self.logger.log_info(
+                    format!(
+                        "Set repeat number: {} for this chat: {}",
+                        repeat_number, chat_id
+                    )
+                    .as_str(),
+                );
+
+                outgoing.push(OutgoingMessage::text(format!(
+                    "Repeats number was changed to {}",
+                    repeat_number
+                )));
             }
-            _ => {}
-        }
+            Reply::Choice { text, options } => {
+                let mut markup = InlineKeyboardMarkup::new();
+                markup.add(
+                    options
+                        .into_iter()
+                        .map(|option| InlineKeyboardButton::new(option.clone(), option))
+                        .collect(),
+                );
 
-        Ok(())
+                outgoing.push(OutgoingMessage::Text {
+                    text,
+                    keyboard: Some(markup),
+                });
+            }
+            // exit command is not registered for telegram
+            Reply::Exit => {}
+        }
     }
 
     fn handle_video_message(
         &self,
-        state: &mut TelegramState,
+        chat: &mut ChatState,
         config: &Config,
         file_id: String,
-        chat_id: u64,
-    ) -> Result<(), T::Err> {
-        let repeat_number = state
-            .repeat_numbers
-            .get(&chat_id)
-            .map_or(config.default_repeat_number, |v| v.to_owned());
-
-        (0..repeat_number).try_for_each(|_| {
-            self.client
-                .send(chat_id, Payload::Video(&file_id))
-                .map(|_| ())
-        })?;
-
-        Ok(())
+        outgoing: &mut Vec<OutgoingMessage>,
+    ) {
+        let repeat_number = chat.repeat_number().unwrap_or(config.default_repeat_number);
+
+        (0..repeat_number).for_each(|_| {
+            outgoing.push(OutgoingMessage::Video {
+                file_id: file_id.clone(),
+            })
+        });
     }
 
     fn handle_callback_query(
         &self,
-        state: &mut TelegramState,
-        chat_id: u64,
+        ctx: &CommandContext,
+        chat: &mut ChatState,
+        chat_id: i64,
         content: CallbackData,
+        outgoing: &mut Vec<OutgoingMessage>,
     ) -> Result<(), T::Err> {
-        let repeat_number = content.data.parse::<u8>().unwrap(); // this error never not be throw because buttons construct on bot side
+        // buttons with a command as data are "did you mean" suggestions
+        if content.data.is_command() {
+            self.client.answer_callback_query(&content.id, "")?;
+            self.handle_command_message(ctx, chat, content.data, chat_id, outgoing);
+            return Ok(());
+        }
 
-        state.set_repeat_number(chat_id, repeat_number);
+        // keyboard of an active dialog, e.g. repeat number choice
+        if let Some(reply) = conversation::handle_input(ctx, chat, &content.data) {
+            return match reply {
+                Reply::RepeatChanged(repeat_number) => {
+                    self.answer_repeat_changed(&content.id, chat_id, repeat_number)
+                }
+                reply => {
+                    self.client.answer_callback_query(&content.id, "")?;
+                    self.send_reply(ctx.config, chat_id, reply, outgoing);
+                    Ok(())
+                }
+            };
+        }
+
+        // buttons of a finished or expired dialog still work
+        let Ok(repeat_number) = content.data.parse::<u8>() else {
+            self.logger
+                .log_warn(format!("get unexpected callback data: {}", content.data).as_str());
+            self.client.answer_callback_query(&content.id, "")?;
+            return Ok(());
+        };
+
+        ctx.set_repeat_number(chat, repeat_number);
+
+        self.answer_repeat_changed(&content.id, chat_id, repeat_number)
+    }
 
+    fn answer_repeat_changed(
+        &self,
+        callback_id: &str,
+        chat_id: i64,
+        repeat_number: u8,
+    ) -> Result<(), T::Err> {
         let answer = format!("Repeats number was changed to {}", repeat_number);
 
         self.logger.log_info(
@@ -145,16 +636,41 @@ impl<'a, 'b, T: TelegramClient> TelegramHandler<'a, 'b, T> {
             .as_str(),
         );
 
-        self.client.answer_callback_query(&content.id, &answer)?;
+        self.client.answer_callback_query(callback_id, &answer)?;
 
         Ok(())
     }
 
+    fn send_static_reply(&self, reply: &StaticReply, outgoing: &mut Vec<OutgoingMessage>) {
+        let keyboard = (!reply.keyboard.is_empty())
+            .then(|| InlineKeyboardMarkup::from_buttons(&reply.keyboard));
+
+        let message = match (&reply.media, &reply.text, keyboard) {
+            (Some(media), caption, keyboard) => OutgoingMessage::Media {
+                kind: media.kind,
+                file_id: media.file_id.clone(),
+                caption: caption.clone(),
+                keyboard,
+            },
+            (None, Some(text), keyboard) => OutgoingMessage::Text {
+                text: text.clone(),
+                keyboard,
+            },
+            (None, None, _) => {
+                self.logger
+                    .log_warn("static reply has neither text nor media, nothing to send");
+                return;
+            }
+        };
+
+        outgoing.push(message);
+    }
+
     fn construct_inline_keyboard(&self) -> InlineKeyboardMarkup {
         let mut markup = InlineKeyboardMarkup::new();
         let mut buttons = vec![];
 
-        (1..6).for_each(|i| {
+        (1..=MAX_KEYBOARD_REPEAT_NUMBER).for_each(|i| {
             let button = InlineKeyboardButton::new(i.to_string(), i.to_string());
 
             buttons.push(button);
@@ -166,54 +682,95 @@ impl<'a, 'b, T: TelegramClient> TelegramHandler<'a, 'b, T> {
     }
 }
 
-impl<'a, 'b, T: TelegramClient> Handler<T> for TelegramHandler<'a, 'b, T> {
+impl<'a, 'b, T: TelegramClient<Err = ClientError>> Handler<T> for TelegramHandler<'a, 'b, T> {
     fn handle(
         &self,
         config: &Config,
-        state: &mut TelegramState,
+        store: &mut dyn StateStore,
         raw_update: RawUpdate,
     ) -> Result<(), T::Err> {
-        let update = raw_update.into();
+        let update: TelegramUpdate = raw_update.into();
+        let update_id = update.update_id();
 
         self.logger
             .log_debug(format!("Receive update: {:#?}", &update).as_str());
 
-        match update {
-            TelegramUpdate::Message {
-                update_id,
-                chat_id,
-                content,
-            } => {
-                self.logger
-                    .log_info(format!("Handle update: {}", update_id).as_str());
-
-                match content {
-                    MessageContent::Text(msg) => {
-                        self.handle_text_message(state, config, msg, chat_id)?;
-                    }
-                    MessageContent::Command(command, initial_msg) => {
-                        self.handle_command_message(state, config, command, initial_msg, chat_id)?;
-                    }
-                    MessageContent::Video { file_id } => {
-                        self.handle_video_message(state, config, file_id, chat_id)?;
-                    }
-                }
+        if store.is_processed(update_id)? {
+            self.logger
+                .log_info(format!("Skip already processed update: {}", update_id).as_str());
+            store.set_last_update_id(update_id)?;
+            return Ok(());
+        }
 
-                state.last_update_id = Some(update_id);
-            }
-            TelegramUpdate::CallbackQuery {
-                update_id,
-                chat_id,
-                content,
-            } => {
-                self.handle_callback_query(state, chat_id, content)?;
-                state.last_update_id = Some(update_id);
+        match config.delivery_guarantee {
+            DeliveryGuarantee::AtMostOnce => {
+                self.commit(store, update_id)?;
+                self.process(config, store, update)?;
             }
-            TelegramUpdate::Ignore { update_id } => {
-                state.last_update_id = Some(update_id);
+            DeliveryGuarantee::AtLeastOnce => {
+                self.process(config, store, update)?;
+                self.commit(store, update_id)?;
             }
         }
 
         Ok(())
     }
+
+    fn deliver(&self, config: &Config, store: &mut dyn StateStore, now: i64) -> Result<(), T::Err> {
+        outbox::deliver(self.client, self.logger, store, now)?;
+        broadcast::deliver(self.client, self.logger, store, config.broadcast_rate, now)?;
+
+        Ok(())
+    }
+}
+
+fn shape(outgoing: Vec<OutgoingMessage>, remaining: Remaining) -> Shaped {
+    let chars: usize = outgoing.iter().map(OutgoingMessage::chars).sum();
+    if outgoing.len() <= remaining.messages as usize && chars <= remaining.chars {
+        return Shaped::Fits(outgoing);
+    }
+
+    let texts = outgoing
+        .iter()
+        .map(|message| match message {
+            OutgoingMessage::Text {
+                text,
+                keyboard: None,
+            } => Some(text.as_str()),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>();
+    if let Some(texts) = texts.filter(|texts| texts.len() > 1) {
+        let combined = texts.join("\n");
+        let length = combined.chars().count();
+
+        if remaining.messages > 0 && length <= remaining.chars.min(MAX_TEXT_CHARS) {
+            return Shaped::Collapsed(OutgoingMessage::text(combined));
+        }
+    }
+
+    Shaped::Truncated(outgoing.into_iter().next().expect("outgoing is not empty"))
+}
+
+fn chat_info(source: &Chat) -> ChatInfo {
+    ChatInfo::new(source.kind.clone(), chat_title(source))
+}
+
+fn chat_title(source: &Chat) -> Option<String> {
+    source.title.clone().or_else(|| source.username.clone())
+}
+
+/// Update the registry entry of a chat which wrote to the bot. The bot is in the chat
+/// and can write to it again.
+fn track_activity(chat: &mut ChatState, source: &Chat) {
+    let now = Utc::now().timestamp();
+    let info = chat.info.get_or_insert_with(|| chat_info(source));
+
+    info.kind = source.kind.clone();
+    info.title = chat_title(source);
+    info.last_activity_at = Some(now);
+    if !info.status.is_present() {
+        info.set_status(BotStatus::Member, now);
+    }
+    chat.inactive = false;
 }
@@ -1,7 +1,9 @@
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use serde_json::json;
 
-#[derive(Serialize, Debug, Clone)]
+use crate::custom::Button;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct InlineKeyboardMarkup {
     rows: Vec<Vec<InlineKeyboardButton>>,
 }
@@ -15,6 +17,27 @@ impl InlineKeyboardMarkup {
         self.rows.push(row);
     }
 
+    pub fn from_buttons(rows: &[Vec<Button>]) -> Self {
+        let mut markup = InlineKeyboardMarkup::new();
+
+        rows.iter().for_each(|row| {
+            markup.add(
+                row.iter()
+                    .map(|button| match button {
+                        Button::Command { text, command } => {
+                            InlineKeyboardButton::new(text.clone(), command.clone())
+                        }
+                        Button::Url { text, url } => {
+                            InlineKeyboardButton::url(text.clone(), url.clone())
+                        }
+                    })
+                    .collect(),
+            )
+        });
+
+        markup
+    }
+
     pub fn into_json(self) -> Vec<Vec<serde_json::Value>> {
         self.rows
             .into_iter()
@@ -23,24 +46,40 @@ impl InlineKeyboardMarkup {
     }
 }
 
-#[derive(Serialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct InlineKeyboardButton {
     text: String,
-    callback_data: String,
+    callback_data: Option<String>,
+    url: Option<String>,
 }
 
 impl InlineKeyboardButton {
     pub fn new(text: String, callback_data: String) -> Self {
         InlineKeyboardButton {
             text,
-            callback_data,
+            callback_data: Some(callback_data),
+            url: None,
+        }
+    }
+
+    pub fn url(text: String, url: String) -> Self {
+        InlineKeyboardButton {
+            text,
+            callback_data: None,
+            url: Some(url),
         }
     }
 
     pub fn into_json(self) -> serde_json::Value {
-        json!({
-            "text": self.text,
-            "callback_data": self.callback_data
-        })
+        match (self.callback_data, self.url) {
+            (_, Some(url)) => json!({
+                "text": self.text,
+                "url": url
+            }),
+            (callback_data, None) => json!({
+                "text": self.text,
+                "callback_data": callback_data.unwrap_or_default()
+            }),
+        }
     }
 }
@@ -1,64 +1,204 @@
+mod broadcast;
 mod client;
 pub mod client_types;
 mod handler;
 mod keyboard;
-mod response_parser;
+mod outbox;
 mod update_converter;
 
-use std::{thread::sleep, time::Duration};
+use std::{
+    fmt::Debug,
+    sync::atomic::{AtomicBool, Ordering},
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+use chrono::Utc;
 
 use self::{
     client::{TelegramClient, TelegramHttpClient},
-    client_types::{ClientError, TelegramCommand},
-    handler::{Handler, TelegramHandler, TelegramState},
+    client_types::{ClientError, CommandScope, TelegramCommand},
+    handler::{Handler, TelegramHandler},
 };
-use crate::{commands::Command, config::Config, logger::Logger};
+use crate::{
+    commands::{CommandRegistry, Frontend, Role},
+    config::Config,
+    health::Health,
+    logger::Logger,
+    maintenance::{Maintenance, MaintenanceSignals},
+    reload::ConfigReloader,
+    storage::{StateStore, StoreError},
+};
+
+const METRICS_LOG_INTERVAL: Duration = Duration::from_secs(600);
 
-pub fn run_bot(config: &Config, logger: &dyn Logger) -> Result<(), ClientError> {
+pub fn run_bot(
+    config: &Config,
+    store: &mut dyn StateStore,
+    reloader: &mut ConfigReloader,
+    logger: &dyn Logger,
+    health: &Health,
+    stop: &AtomicBool,
+) -> Result<(), ClientError> {
     let token = config.bot_token.as_ref().unwrap();
     let client = TelegramHttpClient::new(token.clone(), logger);
-    let mut state = TelegramState::new();
-    let handler = TelegramHandler::new(logger, &client);
-
-    client.set_commands(vec![
-        TelegramCommand::new(
-            Command::new("/help"),
-            "provide help message about bot".to_string(),
-        ),
-        TelegramCommand::new(
-            Command::new("/repeat"),
-            "provide menu for choose repeat number".to_string(),
-        ),
-    ])?;
-
-    loop {
-        communicate(&mut state, &client, &handler, config)?;
+    let registry = CommandRegistry::from_config(config);
+    let handler = TelegramHandler::new(logger, &client, &registry);
+
+    register_commands(&client, &registry, config, logger)?;
+
+    let signals = MaintenanceSignals::register()
+        .map_err(|e| logger.log_warn(format!("maintenance signals are ignored: {}", e).as_str()))
+        .ok();
+    let mut metrics_logged_at = Instant::now();
+    let mut config = config.clone();
+
+    // a stop signal is checked between batches, so a handled batch is fully committed
+    while !stop.load(Ordering::Relaxed) {
+        if let Some(signals) = &signals {
+            signals.apply(store, logger, Utc::now().timestamp())?;
+        }
+        let admins = config.admins.clone();
+        reloader.poll(&mut config, store, logger, Utc::now().timestamp())?;
+        if config.admins != admins {
+            update_admin_commands(&client, &registry, &admins, &config, logger);
+        }
+
+        communicate(store, &client, &handler, &config)?;
+        health.set_last_poll_at(Utc::now().timestamp());
+        health.set_maintenance(Maintenance::load(store)?.enabled);
+
+        if metrics_logged_at.elapsed() >= METRICS_LOG_INTERVAL {
+            if let Some(metrics) = store.cache_metrics() {
+                logger.log_info(format!("state cache: {}", metrics).as_str());
+            }
+            metrics_logged_at = Instant::now();
+        }
+
         sleep(Duration::from_secs(1));
     }
+
+    logger.log_info("stop telegram bot on signal");
+    Ok(())
+}
+
+/// Regular users see commands without admin ones, admins see all of them:
+/// admins from config in their private chats, group administrators if they are admins.
+fn register_commands<T: TelegramClient>(
+    client: &T,
+    registry: &CommandRegistry,
+    config: &Config,
+    logger: &dyn Logger,
+) -> Result<(), T::Err>
+where
+    T::Err: Debug,
+{
+    let commands = |role| {
+        registry
+            .visible(Frontend::Telegram, role)
+            .map(TelegramCommand::from)
+            .collect::<Vec<_>>()
+    };
+
+    client.set_commands(commands(Role::User), CommandScope::Default)?;
+
+    let admin_scopes = config
+        .admins
+        .iter()
+        .map(|user_id| CommandScope::Chat(*user_id as i64))
+        .chain(
+            config
+                .chat_admins
+                .then_some(CommandScope::AllChatAdministrators),
+        );
+
+    // a user who never started the bot has no private chat yet, it is not fatal
+    for scope in admin_scopes {
+        if let Err(e) = client.set_commands(commands(Role::Admin), scope) {
+            logger.log_warn(
+                format!("Failed to set admin commands for {:?}: {:?}", scope, e).as_str(),
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// After a reload changed admins, removed ones get the commands of regular users in their
+/// private chats and new ones get all commands. Failures are only logged, as at start.
+fn update_admin_commands<T: TelegramClient>(
+    client: &T,
+    registry: &CommandRegistry,
+    old_admins: &[u64],
+    config: &Config,
+    logger: &dyn Logger,
+) where
+    T::Err: Debug,
+{
+    let changes = old_admins
+        .iter()
+        .filter(|user_id| !config.admins.contains(user_id))
+        .map(|user_id| (*user_id, Role::User))
+        .chain(
+            config
+                .admins
+                .iter()
+                .filter(|user_id| !old_admins.contains(user_id))
+                .map(|user_id| (*user_id, Role::Admin)),
+        );
+
+    for (user_id, role) in changes {
+        let commands = registry
+            .visible(Frontend::Telegram, role)
+            .map(TelegramCommand::from)
+            .collect();
+        let scope = CommandScope::Chat(user_id as i64);
+        if let Err(e) = client.set_commands(commands, scope) {
+            logger.log_warn(format!("Failed to set commands for {:?}: {:?}", scope, e).as_str());
+        }
+    }
 }
 
 fn communicate<T: TelegramClient, H: Handler<T>>(
-    state: &mut TelegramState,
+    store: &mut dyn StateStore,
     client: &T,
     handler: &H,
     config: &Config,
-) -> Result<(), T::Err> {
-    let offset = state.last_update_id.map_or(0, |v| v + 1);
+) -> Result<(), T::Err>
+where
+    T::Err: From<StoreError> + Debug,
+{
+    let offset = store.last_update_id()?.map_or(0, |v| v + 1);
     client
         .get_updates(offset)?
         .into_iter()
-        .try_for_each(|update| handler.handle(config, state, update))
+        .try_for_each(|update| handler.handle(config, store, update))?;
+
+    handler.deliver(config, store, Utc::now().timestamp())
 }
 
 #[cfg(test)]
 mod tests {
-    use std::cell::RefCell;
+    use std::{
+        cell::{Cell, RefCell},
+        collections::{BTreeMap, HashMap, HashSet},
+        io,
+    };
 
-    use crate::config::{BotMode, ConfigBuilder};
+    use crate::{
+        access::AccessLists,
+        config::{BotMode, ConfigBuilder, DeliveryGuarantee},
+        custom::MediaKind,
+        storage::{
+            AuditEntry, BotStatus, Broadcast, CacheConfig, CachedStore, ChatInfo, ChatState,
+            MemoryStore, OutboxEntry, OutboxStatus,
+        },
+    };
 
     use super::{
-        client_types::{CallbackQuery, Chat, Message, Payload, RawUpdate, User, Video},
+        client_types::{CallbackQuery, Chat, ChatMember, Message, Payload, RawUpdate, User, Video},
         keyboard::InlineKeyboardMarkup,
+        outbox::OutgoingMessage,
         *,
     };
 
@@ -71,11 +211,22 @@ mod tests {
     struct MockTelegramClient {
         pub updates: RefCell<Vec<RawUpdate>>,
         pub handled_ids: RefCell<Vec<u64>>,
-        pub commands: RefCell<Vec<TelegramCommand>>,
+        pub commands: RefCell<Vec<(CommandScope, Vec<TelegramCommand>)>>,
+        /// Chat id and user id of group administrators.
+        pub chat_admins: RefCell<HashSet<(i64, u64)>>,
         pub messages: RefCell<Vec<Message>>,
         pub videos: RefCell<Vec<Video>>,
         pub answers_on_callback: RefCell<Vec<CallbackQuery>>,
         pub keyboards: RefCell<Vec<InlineKeyboardMarkup>>,
+        pub media: RefCell<Vec<(MediaKind, String)>>,
+        pub fail_send: Cell<bool>,
+        /// Old chat id to the id of the supergroup it became.
+        pub migrated_chats: RefCell<HashMap<i64, i64>>,
+        /// Chats which blocked the bot.
+        pub blocked_chats: RefCell<HashSet<i64>>,
+        /// Chat id, message id and new text of edited messages.
+        pub edits: RefCell<Vec<(i64, i64, String)>>,
+        pub last_message_id: Cell<i64>,
     }
 
     impl MockTelegramClient {
@@ -84,22 +235,41 @@ mod tests {
                 updates: RefCell::new(updates),
                 handled_ids: RefCell::new(vec![]),
                 commands: RefCell::new(vec![]),
+                chat_admins: RefCell::new(HashSet::new()),
                 messages: RefCell::new(vec![]),
                 videos: RefCell::new(vec![]),
                 answers_on_callback: RefCell::new(vec![]),
                 keyboards: RefCell::new(vec![]),
+                media: RefCell::new(vec![]),
+                fail_send: Cell::new(false),
+                migrated_chats: RefCell::new(HashMap::new()),
+                blocked_chats: RefCell::new(HashSet::new()),
+                edits: RefCell::new(vec![]),
+                last_message_id: Cell::new(0),
             }
         }
     }
 
     impl TelegramClient for MockTelegramClient {
-        type Err = ();
+        type Err = ClientError;
         fn get_updates(&self, offset: u64) -> Result<Vec<RawUpdate>, Self::Err> {
             self.handled_ids.borrow_mut().push(offset);
             Ok(self.updates.borrow().clone())
         }
 
-        fn send(&self, chat_id: u64, payload: client_types::Payload) -> Result<Message, Self::Err> {
+        fn send(&self, chat_id: i64, payload: client_types::Payload) -> Result<Message, Self::Err> {
+            if self.fail_send.get() {
+                return Err(ClientError::Serialize(io::Error::other("connection lost")));
+            }
+            if let Some(&to_chat_id) = self.migrated_chats.borrow().get(&chat_id) {
+                return Err(ClientError::ChatMigrated { to_chat_id });
+            }
+            if self.blocked_chats.borrow().contains(&chat_id) {
+                return Err(ClientError::ChatUnavailable(
+                    "Forbidden: bot was blocked by the user".to_string(),
+                ));
+            }
+
             let mut video: Option<Video> = None;
             let mut text: Option<String> = None;
 
@@ -107,7 +277,11 @@ mod tests {
                 Payload::Text(txt) => {
                     text = Some(txt.to_string());
                     self.messages.borrow_mut().push(Message {
-                        chat: Chat { id: chat_id },
+                        message_id: 0,
+                        chat: Chat {
+                            id: chat_id,
+                            ..Default::default()
+                        },
                         from: None,
                         video: video.clone(),
                         text: text.clone(),
@@ -124,19 +298,41 @@ mod tests {
                 Payload::TextWithKeyboard(keyboard, txt) => {
                     text = Some(txt.to_string());
                     self.messages.borrow_mut().push(Message {
-                        chat: Chat { id: chat_id },
+                        message_id: 0,
+                        chat: Chat {
+                            id: chat_id,
+                            ..Default::default()
+                        },
                         from: None,
                         video: video.clone(),
                         text: text.clone(),
                     });
                     self.keyboards.borrow_mut().push(keyboard);
                 }
+                Payload::Media {
+                    kind,
+                    file_id,
+                    caption,
+                    keyboard,
+                } => {
+                    text = caption.map(|c| c.to_string());
+                    self.media.borrow_mut().push((kind, file_id.to_string()));
+                    if let Some(keyboard) = keyboard {
+                        self.keyboards.borrow_mut().push(keyboard);
+                    }
+                }
             }
 
+            self.last_message_id.set(self.last_message_id.get() + 1);
+
             Ok(Message {
-                chat: Chat { id: chat_id },
-                from: Some(User {
+                message_id: self.last_message_id.get(),
+                chat: Chat {
                     id: chat_id,
+                    ..Default::default()
+                },
+                from: Some(User {
+                    id: chat_id.unsigned_abs(),
                     is_bot: false,
                 }),
                 video,
@@ -147,8 +343,13 @@ mod tests {
         fn answer_callback_query(&self, id: &str, text: &str) -> Result<bool, Self::Err> {
             self.answers_on_callback.borrow_mut().push(CallbackQuery {
                 id: id.to_string(),
+                from: None,
                 message: Message {
-                    chat: Chat { id: 1 },
+                    message_id: 0,
+                    chat: Chat {
+                        id: 1,
+                        ..Default::default()
+                    },
                     from: None,
                     video: None,
                     text: Some(text.to_string()),
@@ -159,47 +360,267 @@ mod tests {
             Ok(true)
         }
 
-        fn set_commands(&self, commands: Vec<TelegramCommand>) -> Result<bool, Self::Err> {
-            commands.into_iter().for_each(|command| {
-                self.commands.borrow_mut().push(command);
-            });
+        fn set_commands(
+            &self,
+            commands: Vec<TelegramCommand>,
+            scope: CommandScope,
+        ) -> Result<bool, Self::Err> {
+            self.commands.borrow_mut().push((scope, commands));
 
             Ok(true)
         }
+
+        fn get_chat_member(&self, chat_id: i64, user_id: u64) -> Result<ChatMember, Self::Err> {
+            let status = match self.chat_admins.borrow().contains(&(chat_id, user_id)) {
+                true => BotStatus::Admin,
+                false => BotStatus::Member,
+            };
+
+            Ok(ChatMember { status })
+        }
+
+        fn edit_text(
+            &self,
+            chat_id: i64,
+            message_id: i64,
+            text: &str,
+        ) -> Result<Message, Self::Err> {
+            self.edits
+                .borrow_mut()
+                .push((chat_id, message_id, text.to_string()));
+
+            Ok(Message {
+                message_id,
+                chat: Chat {
+                    id: chat_id,
+                    ..Default::default()
+                },
+                from: None,
+                video: None,
+                text: Some(text.to_string()),
+            })
+        }
+    }
+
+    #[derive(Clone, Copy, PartialEq)]
+    enum CrashPoint {
+        Enqueue,
+        SaveChat,
+        MarkProcessed,
+        SetLastUpdateId,
+    }
+
+    /// Store which fails once at the given step, as if the bot was killed there.
+    struct CrashingStore {
+        inner: MemoryStore,
+        crash_on: Option<CrashPoint>,
+    }
+
+    impl CrashingStore {
+        fn new(crash_on: CrashPoint) -> Self {
+            Self {
+                inner: MemoryStore::new(),
+                crash_on: Some(crash_on),
+            }
+        }
+
+        fn step(&mut self, point: CrashPoint) -> Result<(), StoreError> {
+            if self.crash_on == Some(point) {
+                self.crash_on = None;
+                return Err(StoreError::Io(io::Error::other("crash")));
+            }
+
+            Ok(())
+        }
+    }
+
+    impl StateStore for CrashingStore {
+        fn load_chat(&mut self, chat_id: i64) -> Result<ChatState, StoreError> {
+            self.inner.load_chat(chat_id)
+        }
+
+        fn save_chat(&mut self, chat_id: i64, state: &ChatState) -> Result<(), StoreError> {
+            self.step(CrashPoint::SaveChat)?;
+            self.inner.save_chat(chat_id, state)
+        }
+
+        fn chats(&mut self) -> Result<Vec<(i64, ChatState)>, StoreError> {
+            self.inner.chats()
+        }
+
+        fn last_update_id(&self) -> Result<Option<u64>, StoreError> {
+            self.inner.last_update_id()
+        }
+
+        fn set_last_update_id(&mut self, update_id: u64) -> Result<(), StoreError> {
+            self.step(CrashPoint::SetLastUpdateId)?;
+            self.inner.set_last_update_id(update_id)
+        }
+
+        fn is_processed(&self, update_id: u64) -> Result<bool, StoreError> {
+            self.inner.is_processed(update_id)
+        }
+
+        fn mark_processed(&mut self, update_id: u64) -> Result<(), StoreError> {
+            self.step(CrashPoint::MarkProcessed)?;
+            self.inner.mark_processed(update_id)
+        }
+
+        fn enqueue(
+            &mut self,
+            key: &str,
+            chat_id: i64,
+            payload: &serde_json::Value,
+        ) -> Result<bool, StoreError> {
+            self.step(CrashPoint::Enqueue)?;
+            self.inner.enqueue(key, chat_id, payload)
+        }
+
+        fn undelivered(&self) -> Result<Vec<OutboxEntry>, StoreError> {
+            self.inner.undelivered()
+        }
+
+        fn failed_deliveries(&self) -> Result<Vec<OutboxEntry>, StoreError> {
+            self.inner.failed_deliveries()
+        }
+
+        fn save_outbox_entry(&mut self, entry: &OutboxEntry) -> Result<(), StoreError> {
+            self.inner.save_outbox_entry(entry)
+        }
+
+        fn value(&self, key: &str) -> Result<Option<serde_json::Value>, StoreError> {
+            self.inner.value(key)
+        }
+
+        fn values(&self) -> Result<BTreeMap<String, serde_json::Value>, StoreError> {
+            self.inner.values()
+        }
+
+        fn set_value(
+            &mut self,
+            key: &str,
+            value: Option<&serde_json::Value>,
+        ) -> Result<(), StoreError> {
+            self.inner.set_value(key, value)
+        }
+
+        fn append_audit(&mut self, entry: &AuditEntry) -> Result<(), StoreError> {
+            self.inner.append_audit(entry)
+        }
+
+        fn audit_entries(&self) -> Result<Vec<AuditEntry>, StoreError> {
+            self.inner.audit_entries()
+        }
+    }
+
+    fn text_update(update_id: u64, text: &str) -> RawUpdate {
+        RawUpdate {
+            update_id,
+            message: Some(Message {
+                message_id: 0,
+                chat: Chat {
+                    id: 1,
+                    ..Default::default()
+                },
+                from: None,
+                video: None,
+                text: Some(text.to_string()),
+            }),
+            callback_query: None,
+            my_chat_member: None,
+        }
     }
 
-    fn prepare(updates: Vec<RawUpdate>) -> (TelegramState, MockLogger, MockTelegramClient, Config) {
+    fn prepare(updates: Vec<RawUpdate>) -> (MemoryStore, MockLogger, MockTelegramClient, Config) {
         let config = ConfigBuilder::build_default(BotMode::Telegram);
         let logger = MockLogger {};
         let client = MockTelegramClient::new(updates);
-        let state = TelegramState::new();
+        let store = MemoryStore::new();
 
-        (state, logger, client, config)
+        (store, logger, client, config)
     }
 
     #[test]
     fn should_success_set_commands() {
-        let (_, _, client, _) = prepare(vec![]);
-        let commands: Vec<TelegramCommand> = vec![
-            TelegramCommand::new(
-                Command::new("/help"),
-                "provide help message about bot".to_string(),
-            ),
-            TelegramCommand::new(
-                Command::new("/repeat"),
-                "provide menu for choose repeat number".to_string(),
-            ),
-        ];
+        let (_, logger, client, mut config) = prepare(vec![]);
+        config.admins = vec![7];
+        let registry = CommandRegistry::new();
+
+        register_commands(&client, &registry, &config, &logger).unwrap();
+
+        let commands = client.commands.borrow().clone();
+        assert_eq!(commands.len(), 2);
+        assert_eq!(commands[1].0, CommandScope::Chat(7));
+        assert_eq!(
+            commands[1]
+                .1
+                .iter()
+                .map(|c| c.command.as_str())
+                .collect::<Vec<_>>(),
+            vec![
+                "help",
+                "repeat",
+                "cancel",
+                "start",
+                "chats",
+                "status",
+                "broadcast",
+                "ban",
+                "unban",
+                "allow",
+                "budget",
+                "maintenance",
+                "config",
+                "audit",
+                "reload"
+            ]
+        );
+        assert_eq!(
+            commands[0],
+            (
+                CommandScope::Default,
+                vec![
+                    TelegramCommand::new(
+                        "help".to_string(),
+                        "print help message, or help for one command".to_string(),
+                    ),
+                    TelegramCommand::new(
+                        "repeat".to_string(),
+                        "show or set how many times each message is repeated".to_string(),
+                    ),
+                    TelegramCommand::new("cancel".to_string(), "cancel current dialog".to_string(),),
+                    TelegramCommand::new("start".to_string(), "show welcome message".to_string()),
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn should_update_admin_commands_after_admins_change() {
+        let (_, logger, client, mut config) = prepare(vec![]);
+        config.admins = vec![7, 8];
+        let registry = CommandRegistry::new();
 
-        client.set_commands(commands.clone()).unwrap();
+        update_admin_commands(&client, &registry, &[7, 9], &config, &logger);
 
-        assert_eq!(client.commands.borrow().clone(), commands);
+        let commands = client.commands.borrow().clone();
+        assert_eq!(
+            commands
+                .iter()
+                .map(|(scope, commands)| (*scope, commands.len()))
+                .collect::<Vec<_>>(),
+            vec![(CommandScope::Chat(9), 4), (CommandScope::Chat(8), 15)]
+        );
     }
 
     #[test]
     fn should_success_repeat_messages_with_default_number() {
         let msg = Message {
-            chat: Chat { id: 1 },
+            message_id: 0,
+            chat: Chat {
+                id: 1,
+                ..Default::default()
+            },
             from: None,
             video: None,
             text: Some("test".to_string()),
@@ -208,11 +629,13 @@ mod tests {
             update_id: 1,
             message: Some(msg.clone()),
             callback_query: None,
+            my_chat_member: None,
         }];
-        let (mut state, logger, client, config) = prepare(updates);
-        let handler = TelegramHandler::new(&logger, &client);
+        let (mut store, logger, client, config) = prepare(updates);
+        let registry = CommandRegistry::new();
+        let handler = TelegramHandler::new(&logger, &client, &registry);
 
-        communicate(&mut state, &client, &handler, &config).unwrap();
+        communicate(&mut store, &client, &handler, &config).unwrap();
 
         assert_eq!(client.messages.borrow().clone(), vec![msg]);
     }
@@ -220,7 +643,11 @@ mod tests {
     #[test]
     fn should_success_save_last_handled_id() {
         let msg = Message {
-            chat: Chat { id: 1 },
+            message_id: 0,
+            chat: Chat {
+                id: 1,
+                ..Default::default()
+            },
             from: None,
             video: None,
             text: Some("test".to_string()),
@@ -230,27 +657,34 @@ mod tests {
                 update_id: 1,
                 message: Some(msg.clone()),
                 callback_query: None,
+                my_chat_member: None,
             },
             RawUpdate {
                 update_id: 2,
                 message: Some(msg),
                 callback_query: None,
+                my_chat_member: None,
             },
         ];
-        let (mut state, logger, client, config) = prepare(updates);
-        let handler = TelegramHandler::new(&logger, &client);
+        let (mut store, logger, client, config) = prepare(updates);
+        let registry = CommandRegistry::new();
+        let handler = TelegramHandler::new(&logger, &client, &registry);
 
-        communicate(&mut state, &client, &handler, &config).unwrap();
-        communicate(&mut state, &client, &handler, &config).unwrap();
+        communicate(&mut store, &client, &handler, &config).unwrap();
+        communicate(&mut store, &client, &handler, &config).unwrap();
 
-        assert_eq!(state.last_update_id, Some(2));
+        assert_eq!(store.last_update_id().unwrap(), Some(2));
         assert_eq!(client.handled_ids.borrow().clone(), vec![0, 3]);
     }
 
     #[test]
     fn should_ignore_update_if_bot_him_send() {
         let msg = Message {
-            chat: Chat { id: 1 },
+            message_id: 0,
+            chat: Chat {
+                id: 1,
+                ..Default::default()
+            },
             from: Some(User {
                 id: 1,
                 is_bot: true,
@@ -262,20 +696,26 @@ mod tests {
             update_id: 1,
             message: Some(msg),
             callback_query: None,
+            my_chat_member: None,
         }];
-        let (mut state, logger, client, config) = prepare(updates);
-        let handler = TelegramHandler::new(&logger, &client);
+        let (mut store, logger, client, config) = prepare(updates);
+        let registry = CommandRegistry::new();
+        let handler = TelegramHandler::new(&logger, &client, &registry);
 
-        communicate(&mut state, &client, &handler, &config).unwrap();
+        communicate(&mut store, &client, &handler, &config).unwrap();
 
-        assert_eq!(state.last_update_id, Some(1));
+        assert_eq!(store.last_update_id().unwrap(), Some(1));
         assert_eq!(client.messages.borrow().clone(), vec![]);
     }
 
     #[test]
     fn should_success_repeat_video_messages() {
         let msg = Message {
-            chat: Chat { id: 1 },
+            message_id: 0,
+            chat: Chat {
+                id: 1,
+                ..Default::default()
+            },
             from: None,
             video: Some(Video {
                 file_id: "1".to_string(),
@@ -286,11 +726,13 @@ mod tests {
             update_id: 1,
             message: Some(msg.clone()),
             callback_query: None,
+            my_chat_member: None,
         }];
-        let (mut state, logger, client, config) = prepare(updates);
-        let handler = TelegramHandler::new(&logger, &client);
+        let (mut store, logger, client, config) = prepare(updates);
+        let registry = CommandRegistry::new();
+        let handler = TelegramHandler::new(&logger, &client, &registry);
 
-        communicate(&mut state, &client, &handler, &config).unwrap();
+        communicate(&mut store, &client, &handler, &config).unwrap();
 
         assert_eq!(client.videos.borrow().clone(), vec![msg.video.unwrap()]);
     }
@@ -298,19 +740,31 @@ mod tests {
     #[test]
     fn should_success_handle_commands() {
         let mut msg1 = Message {
-            chat: Chat { id: 1 },
+            message_id: 0,
+            chat: Chat {
+                id: 1,
+                ..Default::default()
+            },
             from: None,
             video: None,
             text: Some("/help".to_string()),
         };
         let mut msg2 = Message {
-            chat: Chat { id: 1 },
+            message_id: 0,
+            chat: Chat {
+                id: 1,
+                ..Default::default()
+            },
             from: None,
             video: None,
             text: Some("/repeat".to_string()),
         };
         let mut msg3 = Message {
-            chat: Chat { id: 1 },
+            message_id: 0,
+            chat: Chat {
+                id: 1,
+                ..Default::default()
+            },
             from: None,
             video: None,
             text: Some("/invalid".to_string()),
@@ -320,29 +774,43 @@ mod tests {
                 update_id: 1,
                 message: Some(msg1.clone()),
                 callback_query: None,
+                my_chat_member: None,
             },
             RawUpdate {
                 update_id: 2,
                 message: Some(msg2.clone()),
                 callback_query: None,
+                my_chat_member: None,
             },
             RawUpdate {
                 update_id: 3,
                 message: Some(msg3.clone()),
                 callback_query: None,
+                my_chat_member: None,
             },
         ];
-        let (mut state, logger, client, config) = prepare(updates);
-        let handler = TelegramHandler::new(&logger, &client);
+        let (mut store, logger, client, config) = prepare(updates);
+        let registry = CommandRegistry::new();
+        let handler = TelegramHandler::new(&logger, &client, &registry);
 
-        communicate(&mut state, &client, &handler, &config).unwrap();
+        communicate(&mut store, &client, &handler, &config).unwrap();
 
-        msg1.text = Some(config.help_msg);
+        msg1.text = Some(format!(
+            "{}\n\n\
+             /help [command] - print help message, or help for one command\n\
+             /repeat [number] - show or set how many times each message is repeated\n\
+             /cancel - cancel current dialog\n\
+             /start - show welcome message",
+            config.help_msg
+        ));
         msg2.text = Some(format!(
             "{}\nCurrent repeat number is {}",
             config.repeat_msg, config.default_repeat_number
         ));
-        msg3.text = Some("get unknown command: /invalid".to_string());
+        msg3.text = Some(
+            "Unknown command /invalid. Supported commands: /help, /repeat, /cancel, /start"
+                .to_string(),
+        );
 
         assert_eq!(client.messages.borrow().clone(), vec![msg1, msg2, msg3]);
     }
@@ -350,7 +818,11 @@ mod tests {
     #[test]
     fn should_success_send_keyboard() {
         let msg = Message {
-            chat: Chat { id: 1 },
+            message_id: 0,
+            chat: Chat {
+                id: 1,
+                ..Default::default()
+            },
             from: None,
             video: None,
             text: Some("/repeat".to_string()),
@@ -359,11 +831,13 @@ mod tests {
             update_id: 1,
             message: Some(msg),
             callback_query: None,
+            my_chat_member: None,
         }];
-        let (mut state, logger, client, config) = prepare(updates);
-        let handler = TelegramHandler::new(&logger, &client);
+        let (mut store, logger, client, config) = prepare(updates);
+        let registry = CommandRegistry::new();
+        let handler = TelegramHandler::new(&logger, &client, &registry);
 
-        communicate(&mut state, &client, &handler, &config).unwrap();
+        communicate(&mut store, &client, &handler, &config).unwrap();
 
         assert_eq!(client.keyboards.borrow().clone().len(), 1);
     }
@@ -371,7 +845,11 @@ mod tests {
     #[test]
     fn should_success_change_repeat_number_via_answer_callback() {
         let msg = Message {
-            chat: Chat { id: 1 },
+            message_id: 0,
+            chat: Chat {
+                id: 1,
+                ..Default::default()
+            },
             from: None,
             video: None,
             text: Some("/repeat".to_string()),
@@ -381,29 +859,38 @@ mod tests {
                 update_id: 1,
                 message: Some(msg.clone()),
                 callback_query: None,
+                my_chat_member: None,
             },
             RawUpdate {
                 update_id: 2,
                 message: None,
                 callback_query: Some(CallbackQuery {
                     id: "1".to_string(),
+                    from: None,
                     message: msg,
                     data: "3".to_string(),
                 }),
+                my_chat_member: None,
             },
         ];
-        let (mut state, logger, client, config) = prepare(updates);
-        let handler = TelegramHandler::new(&logger, &client);
+        let (mut store, logger, client, config) = prepare(updates);
+        let registry = CommandRegistry::new();
+        let handler = TelegramHandler::new(&logger, &client, &registry);
 
-        communicate(&mut state, &client, &handler, &config).unwrap();
+        communicate(&mut store, &client, &handler, &config).unwrap();
 
-        assert_eq!(state.repeat_numbers.get(&1), Some(&3));
+        assert_eq!(store.load_chat(1).unwrap().repeat_number, Some(3));
         assert_eq!(
             client.answers_on_callback.borrow().clone(),
             vec![CallbackQuery {
                 id: "1".to_string(),
+                from: None,
                 message: Message {
-                    chat: Chat { id: 1 },
+                    message_id: 0,
+                    chat: Chat {
+                        id: 1,
+                        ..Default::default()
+                    },
                     from: None,
                     video: None,
                     text: Some("Repeats number was changed to 3".to_string())
@@ -416,19 +903,31 @@ mod tests {
     #[test]
     fn should_success_repeat_messages_after_change_repeat_number() {
         let msg = Message {
-            chat: Chat { id: 1 },
+            message_id: 0,
+            chat: Chat {
+                id: 1,
+                ..Default::default()
+            },
             from: None,
             video: None,
             text: Some("/repeat".to_string()),
         };
         let msg1 = Message {
-            chat: Chat { id: 1 },
+            message_id: 0,
+            chat: Chat {
+                id: 1,
+                ..Default::default()
+            },
             from: None,
             video: None,
             text: Some("test".to_string()),
         };
         let msg2 = Message {
-            chat: Chat { id: 1 },
+            message_id: 0,
+            chat: Chat {
+                id: 1,
+                ..Default::default()
+            },
             from: None,
             video: Some(Video {
                 file_id: "1".to_string(),
@@ -440,33 +939,39 @@ mod tests {
                 update_id: 1,
                 message: Some(msg.clone()),
                 callback_query: None,
+                my_chat_member: None,
             },
             RawUpdate {
                 update_id: 2,
                 message: None,
                 callback_query: Some(CallbackQuery {
                     id: "1".to_string(),
+                    from: None,
                     message: msg,
                     data: "2".to_string(),
                 }),
+                my_chat_member: None,
             },
             RawUpdate {
                 update_id: 3,
                 message: Some(msg1.clone()),
                 callback_query: None,
+                my_chat_member: None,
             },
             RawUpdate {
                 update_id: 4,
                 message: Some(msg2.clone()),
                 callback_query: None,
+                my_chat_member: None,
             },
         ];
-        let (mut state, logger, client, config) = prepare(updates);
-        let handler = TelegramHandler::new(&logger, &client);
+        let (mut store, logger, client, config) = prepare(updates);
+        let registry = CommandRegistry::new();
+        let handler = TelegramHandler::new(&logger, &client, &registry);
 
-        communicate(&mut state, &client, &handler, &config).unwrap();
+        communicate(&mut store, &client, &handler, &config).unwrap();
 
-        assert_eq!(state.repeat_numbers.get(&1), Some(&2));
+        assert_eq!(store.load_chat(1).unwrap().repeat_number, Some(2));
         assert_eq!(
             client
                 .messages
@@ -484,4 +989,1325 @@ mod tests {
             vec![msg2.clone().video.unwrap(), msg2.video.unwrap()]
         );
     }
+
+    #[test]
+    fn should_success_handle_commands_with_arguments() {
+        let msg = |text: &str| Message {
+            message_id: 0,
+            chat: Chat {
+                id: 1,
+                ..Default::default()
+            },
+            from: None,
+            video: None,
+            text: Some(text.to_string()),
+        };
+        let updates = vec![
+            RawUpdate {
+                update_id: 1,
+                message: Some(msg("/repeat 3")),
+                callback_query: None,
+                my_chat_member: None,
+            },
+            RawUpdate {
+                update_id: 2,
+                message: Some(msg("/repeat ten")),
+                callback_query: None,
+                my_chat_member: None,
+            },
+            RawUpdate {
+                update_id: 3,
+                message: Some(msg("/help repeat")),
+                callback_query: None,
+                my_chat_member: None,
+            },
+        ];
+        let (mut store, logger, client, config) = prepare(updates);
+        let registry = CommandRegistry::new();
+        let handler = TelegramHandler::new(&logger, &client, &registry);
+
+        communicate(&mut store, &client, &handler, &config).unwrap();
+
+        assert_eq!(store.load_chat(1).unwrap().repeat_number, Some(3));
+        assert_eq!(
+            client.messages.borrow().clone(),
+            vec![
+                msg("Repeats number was changed to 3"),
+                msg("Invalid argument: expected a number from 1 to 255, got ten. Usage: /repeat [number]"),
+                msg("/repeat [number] - show or set how many times each message is repeated"),
+            ]
+        );
+        assert!(client.keyboards.borrow().is_empty());
+    }
+
+    #[test]
+    fn should_suggest_command_with_inline_button() {
+        let msg = Message {
+            message_id: 0,
+            chat: Chat {
+                id: 1,
+                ..Default::default()
+            },
+            from: None,
+            video: None,
+            text: Some("/repaet 2".to_string()),
+        };
+        let updates = vec![
+            RawUpdate {
+                update_id: 1,
+                message: Some(msg.clone()),
+                callback_query: None,
+                my_chat_member: None,
+            },
+            RawUpdate {
+                update_id: 2,
+                message: None,
+                callback_query: Some(CallbackQuery {
+                    id: "1".to_string(),
+                    from: None,
+                    message: msg,
+                    data: "/repeat".to_string(),
+                }),
+                my_chat_member: None,
+            },
+        ];
+        let (mut store, logger, client, config) = prepare(updates);
+        let registry = CommandRegistry::new();
+        let handler = TelegramHandler::new(&logger, &client, &registry);
+
+        communicate(&mut store, &client, &handler, &config).unwrap();
+
+        let texts: Vec<_> = client
+            .messages
+            .borrow()
+            .iter()
+            .map(|m| m.text.clone().unwrap())
+            .collect();
+        assert_eq!(
+            texts,
+            vec![
+                "Unknown command /repaet. Did you mean /repeat?".to_string(),
+                format!(
+                    "{}\nCurrent repeat number is {}",
+                    config.repeat_msg, config.default_repeat_number
+                ),
+            ]
+        );
+        assert_eq!(client.keyboards.borrow().len(), 2);
+        assert_eq!(
+            client.keyboards.borrow()[0].clone().into_json(),
+            vec![vec![
+                serde_json::json!({"text": "/repeat", "callback_data": "/repeat"})
+            ]]
+        );
+        assert_eq!(client.answers_on_callback.borrow().len(), 1);
+    }
+
+    #[test]
+    fn should_register_and_answer_custom_commands_and_auto_replies() {
+        let msg = |text: &str| Message {
+            message_id: 0,
+            chat: Chat {
+                id: 1,
+                ..Default::default()
+            },
+            from: None,
+            video: None,
+            text: Some(text.to_string()),
+        };
+        let updates = vec![
+            RawUpdate {
+                update_id: 1,
+                message: Some(msg("/faq")),
+                callback_query: None,
+                my_chat_member: None,
+            },
+            RawUpdate {
+                update_id: 2,
+                message: Some(msg("What is the PRICE?")),
+                callback_query: None,
+                my_chat_member: None,
+            },
+            RawUpdate {
+                update_id: 3,
+                message: Some(msg("priceless")),
+                callback_query: None,
+                my_chat_member: None,
+            },
+        ];
+        let (mut store, logger, client, mut config) = prepare(updates);
+        let custom: Config = serde_yaml::from_str(
+            r#"
+            mode: telegram
+            help_msg: help
+            repeat_msg: repeat
+            default_repeat_number: 1
+            log_level: Info
+            custom_commands:
+              - name: /faq
+                description: answers to popular questions
+                reply: "See our FAQ"
+                keyboard:
+                  - - text: Site
+                      url: https://example.com/faq
+                    - text: Help
+                      command: /help
+            auto_replies:
+              - keywords: [price, cost]
+                reply: Price list
+                media:
+                  type: photo
+                  file_id: price-photo
+            "#,
+        )
+        .unwrap();
+        config.custom_commands = custom.custom_commands;
+        config.auto_replies = custom.auto_replies;
+        let registry = CommandRegistry::from_config(&config);
+        let handler = TelegramHandler::new(&logger, &client, &registry);
+
+        let commands: Vec<TelegramCommand> = registry
+            .available(Frontend::Telegram)
+            .map(TelegramCommand::from)
+            .collect();
+        assert_eq!(
+            commands.last(),
+            Some(&TelegramCommand::new(
+                "faq".to_string(),
+                "answers to popular questions".to_string()
+            ))
+        );
+
+        communicate(&mut store, &client, &handler, &config).unwrap();
+
+        assert_eq!(
+            client.messages.borrow().clone(),
+            vec![msg("See our FAQ"), msg("priceless")]
+        );
+        assert_eq!(
+            client.keyboards.borrow()[0].clone().into_json(),
+            vec![vec![
+                serde_json::json!({"text": "Site", "url": "https://example.com/faq"}),
+                serde_json::json!({"text": "Help", "callback_data": "/help"}),
+            ]]
+        );
+        assert_eq!(
+            client.media.borrow().clone(),
+            vec![(MediaKind::Photo, "price-photo".to_string())]
+        );
+    }
+
+    #[test]
+    fn should_send_welcome_and_handle_deep_link_payloads() {
+        let msg = |chat_id: i64, text: &str| Message {
+            message_id: 0,
+            chat: Chat {
+                id: chat_id,
+                ..Default::default()
+            },
+            from: None,
+            video: None,
+            text: Some(text.to_string()),
+        };
+        let updates = vec![
+            RawUpdate {
+                update_id: 1,
+                message: Some(msg(1, "/start")),
+                callback_query: None,
+                my_chat_member: None,
+            },
+            RawUpdate {
+                update_id: 2,
+                message: Some(msg(2, "/start repeat_3")),
+                callback_query: None,
+                my_chat_member: None,
+            },
+            RawUpdate {
+                update_id: 3,
+                message: Some(msg(3, "/start spring_sale")),
+                callback_query: None,
+                my_chat_member: None,
+            },
+        ];
+        let (mut store, logger, client, mut config) = prepare(updates);
+        let custom: Config = serde_yaml::from_str(
+            r#"
+            mode: telegram
+            help_msg: help
+            repeat_msg: repeat
+            default_repeat_number: 1
+            log_level: Info
+            start:
+              reply: Welcome!
+              keyboard:
+                - - text: Choose repeat number
+                    command: /repeat
+              payloads:
+                - payload: spring_sale
+                  reply: Spring sale is on
+                  repeat_number: 2
+            "#,
+        )
+        .unwrap();
+        config.start = custom.start;
+        let registry = CommandRegistry::from_config(&config);
+        let handler = TelegramHandler::new(&logger, &client, &registry);
+
+        communicate(&mut store, &client, &handler, &config).unwrap();
+
+        assert_eq!(
+            client.messages.borrow().clone(),
+            vec![
+                msg(1, "Welcome!"),
+                msg(2, "Welcome!"),
+                msg(3, "Spring sale is on")
+            ]
+        );
+        assert_eq!(client.keyboards.borrow().len(), 2);
+        assert_eq!(store.load_chat(1).unwrap().repeat_number, None);
+        assert_eq!(store.load_chat(2).unwrap().repeat_number, Some(3));
+        assert_eq!(store.load_chat(3).unwrap().repeat_number, Some(2));
+        assert_eq!(
+            store
+                .audit_entries()
+                .unwrap()
+                .iter()
+                .map(|entry| (entry.chat_id, entry.action.as_str(), entry.new.to_string()))
+                .collect::<Vec<_>>(),
+            vec![
+                (2, "repeat", "3".to_string()),
+                (3, "repeat", "2".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn should_change_repeat_number_via_typed_number_in_dialog() {
+        let msg = |text: &str| Message {
+            message_id: 0,
+            chat: Chat {
+                id: 1,
+                ..Default::default()
+            },
+            from: None,
+            video: None,
+            text: Some(text.to_string()),
+        };
+        let updates = vec![
+            RawUpdate {
+                update_id: 1,
+                message: Some(msg("/repeat")),
+                callback_query: None,
+                my_chat_member: None,
+            },
+            RawUpdate {
+                update_id: 2,
+                message: Some(msg("zero")),
+                callback_query: None,
+                my_chat_member: None,
+            },
+            RawUpdate {
+                update_id: 3,
+                message: Some(msg("4")),
+                callback_query: None,
+                my_chat_member: None,
+            },
+            RawUpdate {
+                update_id: 4,
+                message: Some(msg("/cancel")),
+                callback_query: None,
+                my_chat_member: None,
+            },
+        ];
+        let (mut store, logger, client, config) = prepare(updates);
+        let registry = CommandRegistry::new();
+        let handler = TelegramHandler::new(&logger, &client, &registry);
+
+        communicate(&mut store, &client, &handler, &config).unwrap();
+
+        assert_eq!(store.load_chat(1).unwrap().repeat_number, Some(4));
+        assert_eq!(store.load_chat(1).unwrap().conversation, None);
+        assert_eq!(
+            client.messages.borrow().clone(),
+            vec![
+                msg(&format!(
+                    "{}\nCurrent repeat number is {}",
+                    config.repeat_msg, config.default_repeat_number
+                )),
+                msg("Try again input number"),
+                msg("Repeats number was changed to 4"),
+                msg("Nothing to cancel"),
+            ]
+        );
+    }
+
+    #[test]
+    fn should_not_echo_replayed_update_twice() {
+        let (mut store, logger, client, config) = prepare(vec![text_update(1, "test")]);
+        let registry = CommandRegistry::new();
+        let handler = TelegramHandler::new(&logger, &client, &registry);
+
+        communicate(&mut store, &client, &handler, &config).unwrap();
+        communicate(&mut store, &client, &handler, &config).unwrap();
+
+        assert_eq!(client.messages.borrow().len(), 1);
+        assert_eq!(store.last_update_id().unwrap(), Some(1));
+    }
+
+    #[test]
+    fn at_least_once_should_handle_update_again_after_crash_while_handling() {
+        let (_, logger, client, config) = prepare(vec![text_update(1, "test")]);
+        let mut store = CrashingStore::new(CrashPoint::Enqueue);
+        let registry = CommandRegistry::new();
+        let handler = TelegramHandler::new(&logger, &client, &registry);
+
+        assert!(communicate(&mut store, &client, &handler, &config).is_err());
+        assert_eq!(store.last_update_id().unwrap(), None);
+
+        communicate(&mut store, &client, &handler, &config).unwrap();
+
+        assert_eq!(client.messages.borrow().len(), 1);
+        assert_eq!(store.last_update_id().unwrap(), Some(1));
+    }
+
+    #[test]
+    fn at_least_once_should_handle_update_again_after_crash_before_state_saved() {
+        let (_, logger, client, config) = prepare(vec![text_update(1, "/repeat 3")]);
+        let mut store = CrashingStore::new(CrashPoint::SaveChat);
+        let registry = CommandRegistry::new();
+        let handler = TelegramHandler::new(&logger, &client, &registry);
+
+        assert!(communicate(&mut store, &client, &handler, &config).is_err());
+        assert_eq!(store.load_chat(1).unwrap().repeat_number, None);
+
+        communicate(&mut store, &client, &handler, &config).unwrap();
+
+        assert_eq!(store.load_chat(1).unwrap().repeat_number, Some(3));
+        assert_eq!(store.last_update_id().unwrap(), Some(1));
+    }
+
+    #[test]
+    fn at_least_once_should_not_echo_twice_after_crash_before_offset_commit() {
+        let (_, logger, client, config) = prepare(vec![text_update(1, "test")]);
+        let mut store = CrashingStore::new(CrashPoint::SetLastUpdateId);
+        let registry = CommandRegistry::new();
+        let handler = TelegramHandler::new(&logger, &client, &registry);
+
+        assert!(communicate(&mut store, &client, &handler, &config).is_err());
+        assert_eq!(store.last_update_id().unwrap(), None);
+
+        communicate(&mut store, &client, &handler, &config).unwrap();
+
+        assert_eq!(client.messages.borrow().len(), 1);
+        assert_eq!(store.last_update_id().unwrap(), Some(1));
+    }
+
+    #[test]
+    fn at_least_once_should_not_queue_replies_twice_after_crash_before_marked_processed() {
+        let (_, logger, client, config) = prepare(vec![text_update(1, "test")]);
+        let mut store = CrashingStore::new(CrashPoint::MarkProcessed);
+        let registry = CommandRegistry::new();
+        let handler = TelegramHandler::new(&logger, &client, &registry);
+
+        assert!(communicate(&mut store, &client, &handler, &config).is_err());
+        communicate(&mut store, &client, &handler, &config).unwrap();
+
+        assert_eq!(client.messages.borrow().len(), 1);
+        assert_eq!(store.last_update_id().unwrap(), Some(1));
+    }
+
+    #[test]
+    fn at_least_once_should_keep_chat_changes_of_committed_update_with_cache() {
+        let (store, logger, client, config) = prepare(vec![text_update(1, "/repeat 3")]);
+        let cache = CacheConfig {
+            capacity: 10,
+            idle_ttl: 3600,
+            flush_interval: 3600,
+        };
+        let mut store = CachedStore::new(Box::new(store), cache);
+        let registry = CommandRegistry::new();
+        let handler = TelegramHandler::new(&logger, &client, &registry);
+
+        communicate(&mut store, &client, &handler, &config).unwrap();
+
+        // the bot is killed: the cache is lost, the storage keeps only what reached it
+        let mut store = store.into_inner();
+        assert_eq!(store.last_update_id().unwrap(), Some(1));
+        assert!(store.is_processed(1).unwrap());
+        assert_eq!(store.load_chat(1).unwrap().repeat_number, Some(3));
+    }
+
+    #[test]
+    fn at_least_once_should_keep_offset_of_batch_handled_before_crash() {
+        let updates = vec![text_update(1, "first"), text_update(2, "second")];
+        let (_, logger, client, config) = prepare(updates);
+        let mut store = CrashingStore::new(CrashPoint::Enqueue);
+        store.crash_on = None;
+        let registry = CommandRegistry::new();
+        let handler = TelegramHandler::new(&logger, &client, &registry);
+
+        communicate(&mut store, &client, &handler, &config).unwrap();
+        *client.updates.borrow_mut() = vec![text_update(2, "second"), text_update(3, "third")];
+        store.crash_on = Some(CrashPoint::Enqueue);
+        assert!(communicate(&mut store, &client, &handler, &config).is_err());
+
+        assert_eq!(store.last_update_id().unwrap(), Some(2));
+    }
+
+    #[test]
+    fn at_most_once_should_lose_update_after_crash_while_handling() {
+        let (_, logger, client, mut config) = prepare(vec![text_update(1, "test")]);
+        config.delivery_guarantee = DeliveryGuarantee::AtMostOnce;
+        let mut store = CrashingStore::new(CrashPoint::Enqueue);
+        let registry = CommandRegistry::new();
+        let handler = TelegramHandler::new(&logger, &client, &registry);
+
+        assert!(communicate(&mut store, &client, &handler, &config).is_err());
+        assert_eq!(store.last_update_id().unwrap(), Some(1));
+
+        communicate(&mut store, &client, &handler, &config).unwrap();
+
+        assert_eq!(client.messages.borrow().len(), 0);
+        assert_eq!(client.handled_ids.borrow().clone(), vec![0, 2]);
+    }
+
+    #[test]
+    fn at_most_once_should_not_handle_update_if_offset_commit_failed() {
+        let (_, logger, client, mut config) = prepare(vec![text_update(1, "test")]);
+        config.delivery_guarantee = DeliveryGuarantee::AtMostOnce;
+        let mut store = CrashingStore::new(CrashPoint::SetLastUpdateId);
+        let registry = CommandRegistry::new();
+        let handler = TelegramHandler::new(&logger, &client, &registry);
+
+        assert!(communicate(&mut store, &client, &handler, &config).is_err());
+        communicate(&mut store, &client, &handler, &config).unwrap();
+
+        assert_eq!(client.messages.borrow().len(), 0);
+        assert_eq!(store.last_update_id().unwrap(), Some(1));
+    }
+
+    fn queued_texts(store: &dyn StateStore) -> Vec<(i64, String, OutboxStatus)> {
+        let mut entries = store.undelivered().unwrap();
+        entries.extend(store.failed_deliveries().unwrap());
+        entries.sort_by_key(|entry| entry.id);
+
+        entries
+            .into_iter()
+            .map(|entry| {
+                (
+                    entry.chat_id,
+                    entry.payload["text"].as_str().unwrap().to_string(),
+                    entry.status,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn should_keep_failed_messages_in_outbox_and_resume_after_restart() {
+        let (mut store, logger, client, mut config) = prepare(vec![text_update(1, "test")]);
+        config.default_repeat_number = 3;
+        let registry = CommandRegistry::new();
+        let handler = TelegramHandler::new(&logger, &client, &registry);
+
+        client.fail_send.set(true);
+        communicate(&mut store, &client, &handler, &config).unwrap();
+
+        assert_eq!(client.messages.borrow().len(), 0);
+        assert_eq!(store.last_update_id().unwrap(), Some(1));
+        assert_eq!(
+            queued_texts(&store),
+            vec![(1, "test".to_string(), OutboxStatus::Pending); 3]
+        );
+
+        // new process with the same store
+        client.fail_send.set(false);
+        let restarted = TelegramHandler::new(&logger, &client, &registry);
+        restarted
+            .deliver(&config, &mut store, Utc::now().timestamp() + 3600)
+            .unwrap();
+
+        assert_eq!(client.messages.borrow().len(), 3);
+        assert_eq!(queued_texts(&store), vec![]);
+    }
+
+    #[test]
+    fn should_keep_order_of_messages_in_chat_while_waiting_for_retry() {
+        let (mut store, logger, client, config) = prepare(vec![]);
+        let registry = CommandRegistry::new();
+        let handler = TelegramHandler::new(&logger, &client, &registry);
+        let text = |text: &str| serde_json::to_value(OutgoingMessage::text(text)).unwrap();
+
+        store.enqueue("1:0", 1, &text("first")).unwrap();
+        store.enqueue("1:1", 1, &text("second")).unwrap();
+        store.enqueue("2:0", 2, &text("other chat")).unwrap();
+
+        let mut first = store.undelivered().unwrap().remove(0);
+        first.attempts = 1;
+        first.next_attempt_at = 100;
+        store.save_outbox_entry(&first).unwrap();
+
+        handler.deliver(&config, &mut store, 50).unwrap();
+
+        let sent: Vec<_> = client
+            .messages
+            .borrow()
+            .iter()
+            .map(|msg| msg.text.clone().unwrap())
+            .collect();
+        assert_eq!(sent, vec!["other chat"]);
+
+        handler.deliver(&config, &mut store, 100).unwrap();
+
+        let sent: Vec<_> = client
+            .messages
+            .borrow()
+            .iter()
+            .map(|msg| msg.text.clone().unwrap())
+            .collect();
+        assert_eq!(sent, vec!["other chat", "first", "second"]);
+    }
+
+    #[test]
+    fn should_give_up_after_max_attempts() {
+        let (mut store, logger, client, config) = prepare(vec![]);
+        let registry = CommandRegistry::new();
+        let handler = TelegramHandler::new(&logger, &client, &registry);
+        let text = serde_json::to_value(OutgoingMessage::text("test")).unwrap();
+
+        store.enqueue("1:0", 1, &text).unwrap();
+        client.fail_send.set(true);
+
+        (0..outbox::MAX_ATTEMPTS as i64).for_each(|attempt| {
+            handler
+                .deliver(&config, &mut store, attempt * 1000)
+                .unwrap()
+        });
+
+        assert_eq!(store.undelivered().unwrap(), vec![]);
+        let entries = store.failed_deliveries().unwrap();
+        assert_eq!(entries[0].status, OutboxStatus::Failed);
+        assert_eq!(entries[0].attempts, outbox::MAX_ATTEMPTS);
+        assert!(entries[0].last_error.is_some());
+
+        store.enqueue("2:0", 1, &text).unwrap();
+        client.fail_send.set(false);
+        handler.deliver(&config, &mut store, 10_000).unwrap();
+
+        assert_eq!(client.messages.borrow().len(), 1);
+    }
+
+    #[test]
+    fn should_move_settings_of_migrated_chat_and_send_to_new_id() {
+        let (mut store, logger, client, config) = prepare(vec![]);
+        let registry = CommandRegistry::new();
+        let handler = TelegramHandler::new(&logger, &client, &registry);
+        let text = serde_json::to_value(OutgoingMessage::text("test")).unwrap();
+        let settings = ChatState {
+            repeat_number: Some(3),
+            ..Default::default()
+        };
+
+        store.save_chat(-1, &settings).unwrap();
+        store.enqueue("1:0", -1, &text).unwrap();
+        client.migrated_chats.borrow_mut().insert(-1, -100);
+
+        handler.deliver(&config, &mut store, 0).unwrap();
+
+        assert_eq!(client.messages.borrow().len(), 1);
+        assert_eq!(client.messages.borrow()[0].chat.id, -100);
+        assert_eq!(store.load_chat(-100).unwrap(), settings);
+        assert_eq!(store.load_chat(-1).unwrap(), ChatState::default());
+        assert_eq!(queued_texts(&store), vec![]);
+    }
+
+    #[test]
+    fn should_stop_sending_to_blocked_chat_until_it_writes_again() {
+        let (mut store, logger, client, config) = prepare(vec![]);
+        let registry = CommandRegistry::new();
+        let handler = TelegramHandler::new(&logger, &client, &registry);
+        let text = serde_json::to_value(OutgoingMessage::text("test")).unwrap();
+
+        store.enqueue("1:0", 1, &text).unwrap();
+        client.blocked_chats.borrow_mut().insert(1);
+        handler.deliver(&config, &mut store, 0).unwrap();
+
+        assert!(store.load_chat(1).unwrap().inactive);
+        assert_eq!(
+            queued_texts(&store),
+            vec![(1, "test".to_string(), OutboxStatus::Failed)]
+        );
+
+        // unblocked, but the bot does not know it yet
+        client.blocked_chats.borrow_mut().clear();
+        store.enqueue("2:0", 1, &text).unwrap();
+        handler.deliver(&config, &mut store, 0).unwrap();
+
+        assert_eq!(client.messages.borrow().len(), 0);
+
+        handler
+            .handle(&config, &mut store, text_update(3, "again"))
+            .unwrap();
+        handler.deliver(&config, &mut store, 0).unwrap();
+
+        assert!(!store.load_chat(1).unwrap().inactive);
+        assert_eq!(client.messages.borrow().len(), 1);
+        assert_eq!(client.messages.borrow()[0].text.as_deref(), Some("again"));
+    }
+
+    #[test]
+    fn should_parse_migration_and_forbidden_api_errors() {
+        let parse = |json: &str| {
+            ClientError::from(serde_json::from_str::<client_types::TelegramApiError>(json).unwrap())
+        };
+
+        assert!(matches!(
+            parse(
+                r#"{"ok": false, "error_code": 400, "description": "Bad Request: group chat was upgraded to a supergroup chat",
+                    "parameters": {"migrate_to_chat_id": -1001}}"#
+            ),
+            ClientError::ChatMigrated { to_chat_id: -1001 }
+        ));
+        assert!(matches!(
+            parse(r#"{"ok": false, "error_code": 403, "description": "Forbidden: bot was blocked by the user"}"#),
+            ClientError::ChatUnavailable(description) if description.contains("blocked")
+        ));
+        assert!(matches!(
+            parse(
+                r#"{"ok": false, "error_code": 400, "description": "Bad Request: message is empty"}"#
+            ),
+            ClientError::Api(_)
+        ));
+    }
+
+    fn member_update(update_id: u64, status: &str, date: i64) -> RawUpdate {
+        serde_json::from_value(serde_json::json!({
+            "update_id": update_id,
+            "my_chat_member": {
+                "chat": {"id": -10, "type": "group", "title": "Team"},
+                "from": {"id": 5, "is_bot": false},
+                "date": date,
+                "old_chat_member": {"status": "left", "user": {"id": 99, "is_bot": true}},
+                "new_chat_member": {"status": status, "user": {"id": 99, "is_bot": true}}
+            }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn should_track_chats_the_bot_is_in() {
+        let (mut store, logger, client, config) = prepare(vec![]);
+        let registry = CommandRegistry::new();
+        let handler = TelegramHandler::new(&logger, &client, &registry);
+
+        handler
+            .handle(&config, &mut store, member_update(1, "member", 100))
+            .unwrap();
+        handler
+            .handle(&config, &mut store, member_update(2, "administrator", 200))
+            .unwrap();
+
+        let info = store.load_chat(-10).unwrap().info.unwrap();
+        assert_eq!(info.kind, "group");
+        assert_eq!(info.title.as_deref(), Some("Team"));
+        assert_eq!(info.status, BotStatus::Admin);
+        assert_eq!(info.joined_at, Some(100));
+        assert_eq!(info.last_activity_at, None);
+
+        let mut message = text_update(3, "hi");
+        message.message.as_mut().unwrap().chat.id = -10;
+        handler.handle(&config, &mut store, message).unwrap();
+
+        assert!(store
+            .load_chat(-10)
+            .unwrap()
+            .info
+            .unwrap()
+            .last_activity_at
+            .is_some());
+
+        handler
+            .handle(&config, &mut store, member_update(4, "kicked", 300))
+            .unwrap();
+
+        let chat = store.load_chat(-10).unwrap();
+        assert!(chat.inactive);
+        assert_eq!(chat.info.unwrap().status, BotStatus::Kicked);
+        // no replies to membership changes
+        assert_eq!(queued_texts(&store).len(), 1);
+    }
+
+    fn command_from(update_id: u64, chat_id: i64, user_id: u64, text: &str) -> RawUpdate {
+        let mut update = text_update(update_id, text);
+        let message = update.message.as_mut().unwrap();
+        message.chat.id = chat_id;
+        message.from = Some(User {
+            id: user_id,
+            is_bot: false,
+        });
+
+        update
+    }
+
+    fn last_queued_text(store: &dyn StateStore) -> String {
+        queued_texts(store).pop().unwrap().1
+    }
+
+    #[test]
+    fn should_run_admin_commands_only_for_admins() {
+        let (mut store, logger, client, mut config) = prepare(vec![]);
+        config.admins = vec![7];
+        let registry = CommandRegistry::new();
+        let handler = TelegramHandler::new(&logger, &client, &registry);
+
+        handler
+            .handle(&config, &mut store, command_from(1, 8, 8, "/status"))
+            .unwrap();
+        assert!(last_queued_text(&store).starts_with("Unknown command /status."));
+
+        handler
+            .handle(&config, &mut store, command_from(2, 8, 8, "/help"))
+            .unwrap();
+        assert!(!last_queued_text(&store).contains("/status"));
+
+        handler
+            .handle(&config, &mut store, command_from(3, 7, 7, "/status"))
+            .unwrap();
+        assert_eq!(
+            last_queued_text(&store),
+            "Chats: 1 known, bot is in 1\nOutbox: 2 pending, 0 failed\nLast update: 2"
+        );
+
+        handler
+            .handle(&config, &mut store, command_from(4, 7, 7, "/help"))
+            .unwrap();
+        assert!(last_queued_text(&store).contains("/status - show state of the bot"));
+    }
+
+    #[test]
+    fn should_treat_group_administrators_as_admins_if_enabled() {
+        let (mut store, logger, client, mut config) = prepare(vec![]);
+        let registry = CommandRegistry::new();
+        let handler = TelegramHandler::new(&logger, &client, &registry);
+        client.chat_admins.borrow_mut().insert((-10, 5));
+
+        handler
+            .handle(&config, &mut store, command_from(1, -10, 5, "/chats"))
+            .unwrap();
+        assert!(last_queued_text(&store).starts_with("Unknown command /chats."));
+
+        config.chat_admins = true;
+        handler
+            .handle(&config, &mut store, command_from(2, -10, 5, "/chats"))
+            .unwrap();
+        assert!(last_queued_text(&store).starts_with("Known chats: 1"));
+
+        handler
+            .handle(&config, &mut store, command_from(3, -10, 6, "/chats"))
+            .unwrap();
+        assert!(last_queued_text(&store).starts_with("Unknown command /chats."));
+    }
+
+    fn callback_from(update_id: u64, chat_id: i64, user_id: u64, data: &str) -> RawUpdate {
+        let message = command_from(update_id, chat_id, user_id, "")
+            .message
+            .unwrap();
+
+        RawUpdate {
+            update_id,
+            message: None,
+            callback_query: Some(CallbackQuery {
+                id: update_id.to_string(),
+                from: message.from.clone(),
+                message,
+                data: data.to_string(),
+            }),
+            my_chat_member: None,
+        }
+    }
+
+    fn texts_to(client: &MockTelegramClient, text: &str) -> Vec<i64> {
+        client
+            .messages
+            .borrow()
+            .iter()
+            .filter(|msg| msg.text.as_deref() == Some(text))
+            .map(|msg| msg.chat.id)
+            .collect()
+    }
+
+    #[test]
+    fn should_broadcast_to_active_chats_after_confirmation() {
+        let (mut store, logger, client, mut config) = prepare(vec![]);
+        config.admins = vec![7];
+        config.broadcast_rate = 2;
+        let registry = CommandRegistry::new();
+        let handler = TelegramHandler::new(&logger, &client, &registry);
+        let chat = |status| ChatState {
+            info: Some(ChatInfo {
+                status,
+                ..ChatInfo::new("private".to_string(), None)
+            }),
+            ..Default::default()
+        };
+
+        store.save_chat(1, &chat(BotStatus::Member)).unwrap();
+        store.save_chat(2, &chat(BotStatus::Admin)).unwrap();
+        store.save_chat(3, &chat(BotStatus::Member)).unwrap();
+        store.save_chat(4, &chat(BotStatus::Left)).unwrap();
+        client.blocked_chats.borrow_mut().insert(3);
+
+        handler
+            .handle(&config, &mut store, command_from(1, 7, 7, "/broadcast"))
+            .unwrap();
+        handler
+            .handle(
+                &config,
+                &mut store,
+                command_from(2, 7, 7, "Maintenance at 10:00"),
+            )
+            .unwrap();
+        assert_eq!(
+            last_queued_text(&store),
+            "Send this message to 4 chats?\n\nMaintenance at 10:00"
+        );
+
+        handler
+            .handle(&config, &mut store, callback_from(3, 7, 7, "Send"))
+            .unwrap();
+        assert_eq!(last_queued_text(&store), "Broadcast to 4 chats is started");
+
+        handler
+            .handle(&config, &mut store, command_from(4, 7, 7, "/broadcast"))
+            .unwrap();
+        assert!(last_queued_text(&store).starts_with("Another broadcast is running"));
+
+        handler.deliver(&config, &mut store, 0).unwrap();
+
+        let mut preview = InlineKeyboardMarkup::new();
+        preview.add(vec![
+            keyboard::InlineKeyboardButton::new("Send".to_string(), "Send".to_string()),
+            keyboard::InlineKeyboardButton::new("Cancel".to_string(), "Cancel".to_string()),
+        ]);
+        assert_eq!(client.keyboards.borrow().clone(), vec![preview]);
+        assert_eq!(texts_to(&client, "Maintenance at 10:00"), vec![1, 2]);
+        assert_eq!(
+            texts_to(&client, "Broadcast: 0 of 4 chats\nDelivered: 0, failed: 0"),
+            vec![7]
+        );
+        let progress_id = client.last_message_id.get() - 2;
+        assert_eq!(
+            client.edits.borrow().clone(),
+            vec![(
+                7,
+                progress_id,
+                "Broadcast: 2 of 4 chats\nDelivered: 2, failed: 0".to_string()
+            )]
+        );
+
+        handler.deliver(&config, &mut store, 1).unwrap();
+        handler.deliver(&config, &mut store, 2).unwrap();
+
+        assert_eq!(texts_to(&client, "Maintenance at 10:00"), vec![1, 2, 7]);
+        assert!(store.load_chat(3).unwrap().inactive);
+        assert!(Broadcast::load(&store).unwrap().unwrap().is_finished());
+        assert_eq!(client.edits.borrow().len(), 2);
+        assert_eq!(
+            client.edits.borrow()[1].2,
+            "Broadcast finished: 4 chats\nDelivered: 3, failed: 1\n\
+             3: Forbidden: bot was blocked by the user"
+        );
+    }
+
+    #[test]
+    fn should_resume_broadcast_after_restart_and_retry_failed_chat() {
+        let (mut store, logger, client, config) = prepare(vec![]);
+        let registry = CommandRegistry::new();
+        let handler = TelegramHandler::new(&logger, &client, &registry);
+
+        // chat 1 got the message before the restart
+        let mut broadcast = Broadcast::new("Update".to_string(), 7, vec![1, 2, 3], 0);
+        broadcast.progress_message_id = Some(42);
+        broadcast.advance(None);
+        broadcast.save(&mut store).unwrap();
+
+        client.fail_send.set(true);
+        handler.deliver(&config, &mut store, 0).unwrap();
+
+        let broadcast = Broadcast::load(&store).unwrap().unwrap();
+        assert_eq!((broadcast.next, broadcast.attempts), (1, 1));
+
+        client.fail_send.set(false);
+        handler.deliver(&config, &mut store, 1).unwrap();
+        assert_eq!(client.messages.borrow().len(), 0);
+
+        handler.deliver(&config, &mut store, 2).unwrap();
+
+        assert_eq!(texts_to(&client, "Update"), vec![2, 3]);
+        assert_eq!(
+            client.edits.borrow().clone(),
+            vec![(
+                7,
+                42,
+                "Broadcast finished: 3 chats\nDelivered: 3, failed: 0".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn should_deny_users_from_config_and_runtime_lists() {
+        let (mut store, logger, client, mut config) = prepare(vec![]);
+        config.admins = vec![7];
+        config.access.deny_users = vec![8];
+        config.access.denied_reply = Some("Sorry, I cannot answer you".to_string());
+        let registry = CommandRegistry::new();
+        let handler = TelegramHandler::new(&logger, &client, &registry);
+
+        handler
+            .handle(&config, &mut store, command_from(1, 8, 8, "hi"))
+            .unwrap();
+        assert_eq!(last_queued_text(&store), "Sorry, I cannot answer you");
+        assert_eq!(store.load_chat(8).unwrap(), ChatState::default());
+
+        handler
+            .handle(&config, &mut store, command_from(2, 7, 7, "/ban 9"))
+            .unwrap();
+        assert_eq!(last_queued_text(&store), "User 9 is banned");
+
+        handler
+            .handle(&config, &mut store, callback_from(3, 9, 9, "3"))
+            .unwrap();
+        assert_eq!(store.load_chat(9).unwrap().repeat_number, None);
+        assert_eq!(
+            client.answers_on_callback.borrow()[0]
+                .message
+                .text
+                .as_deref(),
+            Some("Sorry, I cannot answer you")
+        );
+
+        handler
+            .handle(&config, &mut store, command_from(4, 7, 7, "/unban 8"))
+            .unwrap();
+        assert_eq!(
+            last_queued_text(&store),
+            "User 8 is denied in config, remove it there"
+        );
+
+        handler
+            .handle(&config, &mut store, command_from(5, 7, 7, "/unban 9"))
+            .unwrap();
+        handler
+            .handle(&config, &mut store, command_from(6, 9, 9, "hi"))
+            .unwrap();
+        assert_eq!(
+            queued_texts(&store).pop().unwrap(),
+            (9, "hi".to_string(), OutboxStatus::Pending)
+        );
+    }
+
+    #[test]
+    fn should_answer_only_allowed_users_if_allowlist_is_set() {
+        let (mut store, logger, client, mut config) = prepare(vec![]);
+        config.admins = vec![7];
+        config.access.allow_users = vec![1];
+        let registry = CommandRegistry::new();
+        let handler = TelegramHandler::new(&logger, &client, &registry);
+
+        handler
+            .handle(&config, &mut store, command_from(1, 2, 2, "hi"))
+            .unwrap();
+        assert_eq!(queued_texts(&store), vec![]);
+
+        handler
+            .handle(&config, &mut store, command_from(2, 7, 7, "/allow 2"))
+            .unwrap();
+        assert_eq!(last_queued_text(&store), "User 2 is allowed");
+
+        handler
+            .handle(&config, &mut store, command_from(3, 2, 2, "hi"))
+            .unwrap();
+        assert_eq!(last_queued_text(&store), "hi");
+        assert!(AccessLists::load(&store).unwrap().allowed.contains(&2));
+    }
+
+    #[test]
+    fn should_warn_and_ignore_flooding_user() {
+        let (mut store, logger, client, mut config) = prepare(vec![]);
+        config.flood_control =
+            Some(serde_yaml::from_str("user: {capacity: 2, per_minute: 1}\nmute: 60").unwrap());
+        let registry = CommandRegistry::new();
+        let handler = TelegramHandler::new(&logger, &client, &registry);
+
+        (1..=5).for_each(|update_id| {
+            handler
+                .handle(&config, &mut store, command_from(update_id, 1, 1, "spam"))
+                .unwrap()
+        });
+        handler
+            .handle(&config, &mut store, callback_from(6, 1, 1, "3"))
+            .unwrap();
+
+        assert_eq!(
+            queued_texts(&store)
+                .into_iter()
+                .map(|(_, text, _)| text)
+                .collect::<Vec<_>>(),
+            vec![
+                "spam",
+                "spam",
+                "Too many messages, I will not answer for 60 seconds"
+            ]
+        );
+        assert_eq!(store.load_chat(1).unwrap().repeat_number, None);
+        assert_eq!(client.answers_on_callback.borrow().len(), 1);
+
+        // other users are not affected
+        handler
+            .handle(&config, &mut store, command_from(7, 2, 2, "hello"))
+            .unwrap();
+        assert_eq!(last_queued_text(&store), "hello");
+    }
+
+    #[test]
+    fn should_collapse_repeats_over_outgoing_budget_until_admin_raises_it() {
+        let (mut store, logger, client, mut config) = prepare(vec![]);
+        config.admins = vec![7];
+        config.default_repeat_number = 5;
+        config.outgoing_budget =
+            Some(serde_yaml::from_str("messages_per_minute: 3\nchars_per_minute: 100").unwrap());
+        let registry = CommandRegistry::new();
+        let handler = TelegramHandler::new(&logger, &client, &registry);
+
+        ["hi", "hello", "hey", "yo", "yo"]
+            .into_iter()
+            .zip(1..)
+            .for_each(|(text, update_id)| {
+                handler
+                    .handle(&config, &mut store, command_from(update_id, 1, 1, text))
+                    .unwrap()
+            });
+
+        assert_eq!(
+            queued_texts(&store)
+                .into_iter()
+                .map(|(_, text, _)| text)
+                .collect::<Vec<_>>(),
+            vec![
+                "hi\nhi\nhi\nhi\nhi",
+                "hello\nhello\nhello\nhello\nhello",
+                "hey\nhey\nhey\nhey\nhey",
+                "yo",
+                "Too many messages for this chat, repeats are skipped for a minute",
+                "yo",
+            ]
+        );
+
+        handler
+            .handle(
+                &config,
+                &mut store,
+                command_from(6, 7, 7, "/budget 1 100 1000"),
+            )
+            .unwrap();
+        assert_eq!(
+            last_queued_text(&store),
+            "Outgoing budget of chat 1 is set to 100 messages and 1000 characters per minute"
+        );
+
+        handler
+            .handle(&config, &mut store, command_from(7, 1, 1, "hi"))
+            .unwrap();
+        let texts = queued_texts(&store);
+        assert_eq!(texts.len(), 12);
+        assert!(texts[7..].iter().all(|(_, text, _)| text == "hi"));
+
+        handler
+            .handle(&config, &mut store, command_from(8, 8, 7, "/budget 1"))
+            .unwrap();
+        assert_eq!(
+            last_queued_text(&store),
+            "Outgoing budget of chat 1: 100 messages and 1000 characters per minute, set for this chat"
+        );
+    }
+
+    #[test]
+    fn should_answer_users_with_maintenance_message_except_admins() {
+        let (mut store, logger, client, mut config) = prepare(vec![]);
+        config.admins = vec![7];
+        let registry = CommandRegistry::new();
+        let handler = TelegramHandler::new(&logger, &client, &registry);
+
+        handler
+            .handle(
+                &config,
+                &mut store,
+                command_from(1, 7, 7, "/maintenance on"),
+            )
+            .unwrap();
+        assert_eq!(
+            last_queued_text(&store),
+            "Maintenance is on, users get: The bot is under maintenance, we'll be right back"
+        );
+
+        handler
+            .handle(&config, &mut store, command_from(2, 1, 1, "hello"))
+            .unwrap();
+        assert_eq!(
+            last_queued_text(&store),
+            "The bot is under maintenance, we'll be right back"
+        );
+        handler
+            .handle(&config, &mut store, callback_from(3, 1, 1, "3"))
+            .unwrap();
+        assert_eq!(store.load_chat(1).unwrap().repeat_number, None);
+        assert_eq!(
+            client.answers_on_callback.borrow()[0]
+                .message
+                .text
+                .as_deref(),
+            Some("The bot is under maintenance, we'll be right back")
+        );
+        handler
+            .handle(&config, &mut store, member_update(4, "member", 10))
+            .unwrap();
+        assert!(store.load_chat(-10).unwrap().info.is_some());
+        assert_eq!(store.last_update_id().unwrap(), Some(4));
+
+        handler
+            .handle(&config, &mut store, command_from(5, 7, 7, "hello"))
+            .unwrap();
+        assert_eq!(last_queued_text(&store), "hello");
+        handler
+            .handle(&config, &mut store, command_from(6, 7, 7, "/status"))
+            .unwrap();
+        assert!(last_queued_text(&store).contains("\nMaintenance is on since "));
+
+        handler
+            .handle(
+                &config,
+                &mut store,
+                command_from(7, 7, 7, "/maintenance off"),
+            )
+            .unwrap();
+        handler
+            .handle(&config, &mut store, command_from(8, 1, 1, "hello"))
+            .unwrap();
+        assert_eq!(last_queued_text(&store), "hello");
+    }
+
+    #[test]
+    fn should_record_repeat_changes_and_bans_in_audit_log() {
+        let (mut store, logger, client, mut config) = prepare(vec![]);
+        config.admins = vec![7];
+        let registry = CommandRegistry::new();
+        let handler = TelegramHandler::new(&logger, &client, &registry);
+
+        handler
+            .handle(&config, &mut store, command_from(1, 1, 1, "/repeat"))
+            .unwrap();
+        handler
+            .handle(&config, &mut store, callback_from(2, 1, 1, "3"))
+            .unwrap();
+        handler
+            .handle(&config, &mut store, callback_from(3, 1, 1, "4"))
+            .unwrap();
+        assert_eq!(store.load_chat(1).unwrap().repeat_number, Some(4));
+        handler
+            .handle(&config, &mut store, command_from(4, 7, 7, "/ban 9"))
+            .unwrap();
+
+        let entries = store.audit_entries().unwrap();
+        assert_eq!(
+            entries
+                .iter()
+                .map(|entry| (
+                    entry.actor,
+                    entry.chat_id,
+                    entry.action.as_str(),
+                    entry.old.to_string(),
+                    entry.new.to_string()
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                (Some(1), 1, "repeat", "null".to_string(), "3".to_string()),
+                (Some(1), 1, "repeat", "3".to_string(), "4".to_string()),
+                (
+                    Some(7),
+                    7,
+                    "access.9",
+                    "null".to_string(),
+                    "\"denied\"".to_string()
+                ),
+            ]
+        );
+
+        handler
+            .handle(&config, &mut store, command_from(5, 7, 7, "/audit user=1"))
+            .unwrap();
+        assert_eq!(
+            last_queued_text(&store),
+            [entries[1].line(), entries[0].line()].join("\n")
+        );
+        handler
+            .handle(
+                &config,
+                &mut store,
+                command_from(6, 7, 7, "/audit action=access limit=5"),
+            )
+            .unwrap();
+        assert!(last_queued_text(&store).ends_with("user 7 in chat 7: access.9 null -> \"denied\""));
+        handler
+            .handle(&config, &mut store, command_from(7, 7, 7, "/audit chat=2"))
+            .unwrap();
+        assert_eq!(last_queued_text(&store), "No audit entries");
+        handler
+            .handle(&config, &mut store, command_from(8, 7, 7, "/audit limit=0"))
+            .unwrap();
+        assert!(last_queued_text(&store)
+            .starts_with("Invalid argument: limit must be a number from 1 to 100"));
+
+        handler
+            .handle(&config, &mut store, command_from(9, 1, 1, "/audit"))
+            .unwrap();
+        assert!(last_queued_text(&store).starts_with("Unknown command /audit."));
+    }
+
+    #[test]
+    fn should_fit_long_audit_into_one_message() {
+        let (mut store, logger, client, mut config) = prepare(vec![]);
+        config.admins = vec![7];
+        let registry = CommandRegistry::new();
+        let handler = TelegramHandler::new(&logger, &client, &registry);
+        let long = serde_json::Value::from("x".repeat(1000));
+        for at in 0..100 {
+            store
+                .append_audit(&AuditEntry::new(
+                    at,
+                    Some(7),
+                    7,
+                    "broadcast",
+                    serde_json::Value::Null,
+                    long.clone(),
+                ))
+                .unwrap();
+        }
+
+        handler
+            .handle(
+                &config,
+                &mut store,
+                command_from(1, 7, 7, "/audit limit=100"),
+            )
+            .unwrap();
+
+        let text = last_queued_text(&store);
+        let lines: Vec<_> = text.lines().collect();
+        assert!(text.chars().count() <= 4096);
+        assert!(lines[0].ends_with(&format!("broadcast null -> \"{}…", "x".repeat(99))));
+        assert_eq!(
+            lines.last().unwrap(),
+            &format!("…{} more, narrow the filter", 100 - (lines.len() - 1))
+        );
+    }
 }
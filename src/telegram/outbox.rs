@@ -0,0 +1,232 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    custom::MediaKind,
+    logger::Logger,
+    storage::{ChatState, OutboxEntry, OutboxStatus, StateStore, StoreError},
+};
+
+use super::{
+    client::TelegramClient,
+    client_types::{ClientError, Payload},
+    keyboard::InlineKeyboardMarkup,
+};
+
+/// Delivery is given up after this number of failed attempts.
+pub const MAX_ATTEMPTS: u32 = 5;
+/// Delay before the first retry, doubled after every next failure.
+const RETRY_DELAY_SECS: i64 = 2;
+const MAX_RETRY_DELAY_SECS: i64 = 300;
+
+/// Owned version of `Payload` which is kept in the outbox until it is sent.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type")]
+pub enum OutgoingMessage {
+    #[serde(rename = "text")]
+    Text {
+        text: String,
+        keyboard: Option<InlineKeyboardMarkup>,
+    },
+    #[serde(rename = "video")]
+    Video { file_id: String },
+    #[serde(rename = "media")]
+    Media {
+        kind: MediaKind,
+        file_id: String,
+        caption: Option<String>,
+        keyboard: Option<InlineKeyboardMarkup>,
+    },
+}
+
+impl OutgoingMessage {
+    pub fn text(text: impl Into<String>) -> Self {
+        OutgoingMessage::Text {
+            text: text.into(),
+            keyboard: None,
+        }
+    }
+
+    /// Characters of the text or the caption, spent from the outgoing budget of the chat.
+    pub fn chars(&self) -> usize {
+        match self {
+            OutgoingMessage::Text { text, .. } => text.chars().count(),
+            OutgoingMessage::Media {
+                caption: Some(caption),
+                ..
+            } => caption.chars().count(),
+            _ => 0,
+        }
+    }
+
+    fn payload(&self) -> Payload<'_> {
+        match self {
+            OutgoingMessage::Text {
+                text,
+                keyboard: None,
+            } => Payload::Text(text),
+            OutgoingMessage::Text {
+                text,
+                keyboard: Some(keyboard),
+            } => Payload::TextWithKeyboard(keyboard.clone(), text),
+            OutgoingMessage::Video { file_id } => Payload::Video(file_id),
+            OutgoingMessage::Media {
+                kind,
+                file_id,
+                caption,
+                keyboard,
+            } => Payload::Media {
+                kind: *kind,
+                file_id,
+                caption: caption.as_deref(),
+                keyboard: keyboard.clone(),
+            },
+        }
+    }
+}
+
+pub(super) fn retry_delay(attempts: u32) -> i64 {
+    let exponent = attempts.saturating_sub(1).min(16);
+
+    (RETRY_DELAY_SECS << exponent).min(MAX_RETRY_DELAY_SECS)
+}
+
+/// Send due messages of the outbox. Messages of one chat are sent in queue order:
+/// while a message waits for a retry, the next messages of its chat wait too.
+/// Messages to a migrated chat follow it, messages to a chat which blocked
+/// the bot or was deleted fail at once and the chat is marked inactive.
+pub fn deliver<T: TelegramClient<Err = ClientError>>(
+    client: &T,
+    logger: &dyn Logger,
+    store: &mut dyn StateStore,
+    now: i64,
+) -> Result<(), StoreError> {
+    let mut blocked_chats = HashSet::new();
+
+    for mut entry in store.undelivered()? {
+        if blocked_chats.contains(&entry.chat_id) {
+            continue;
+        }
+
+        if entry.next_attempt_at > now {
+            blocked_chats.insert(entry.chat_id);
+            continue;
+        }
+
+        let message: OutgoingMessage = match serde_json::from_value(entry.payload.clone()) {
+            Ok(message) => message,
+            Err(e) => {
+                give_up(logger, store, &mut entry, format!("broken payload: {}", e))?;
+                continue;
+            }
+        };
+
+        if store.load_chat(entry.chat_id)?.inactive {
+            give_up(logger, store, &mut entry, "chat is inactive".to_string())?;
+            continue;
+        }
+
+        let mut result = client.send(entry.chat_id, message.payload());
+
+        if let Err(ClientError::ChatMigrated { to_chat_id }) = result {
+            migrate_chat(logger, store, entry.chat_id, to_chat_id)?;
+            entry.chat_id = to_chat_id;
+            result = client.send(entry.chat_id, message.payload());
+        }
+
+        match result {
+            Ok(_) => {
+                entry.status = OutboxStatus::Delivered;
+                entry.attempts += 1;
+                entry.last_error = None;
+                store.save_outbox_entry(&entry)?;
+            }
+            Err(ClientError::ChatUnavailable(description)) => {
+                mark_inactive(logger, store, entry.chat_id, &description)?;
+                entry.attempts += 1;
+                give_up(logger, store, &mut entry, description)?;
+            }
+            Err(e) if entry.attempts + 1 >= MAX_ATTEMPTS => {
+                entry.attempts += 1;
+                give_up(logger, store, &mut entry, format!("{:?}", e))?;
+            }
+            Err(e) => {
+                entry.attempts += 1;
+                entry.next_attempt_at = now + retry_delay(entry.attempts);
+                entry.last_error = Some(format!("{:?}", e));
+                logger.log_warn(
+                    format!(
+                        "Failed to send message {} to chat {}, attempt {}: {:?}",
+                        entry.key, entry.chat_id, entry.attempts, e
+                    )
+                    .as_str(),
+                );
+                store.save_outbox_entry(&entry)?;
+                blocked_chats.insert(entry.chat_id);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Move the settings of a group which became a supergroup to its new id.
+pub(super) fn migrate_chat(
+    logger: &dyn Logger,
+    store: &mut dyn StateStore,
+    from_chat_id: i64,
+    to_chat_id: i64,
+) -> Result<(), StoreError> {
+    logger.log_info(format!("Chat {} migrated to {}", from_chat_id, to_chat_id).as_str());
+
+    let chat = store.load_chat(from_chat_id)?;
+    if chat == ChatState::default() {
+        return Ok(());
+    }
+
+    store.save_chat(to_chat_id, &chat)?;
+    store.save_chat(from_chat_id, &ChatState::default())
+}
+
+/// Nothing is sent to the chat until it writes to the bot again.
+pub(super) fn mark_inactive(
+    logger: &dyn Logger,
+    store: &mut dyn StateStore,
+    chat_id: i64,
+    description: &str,
+) -> Result<(), StoreError> {
+    let mut chat = store.load_chat(chat_id)?;
+    chat.inactive = true;
+    store.save_chat(chat_id, &chat)?;
+
+    logger.log_warn(
+        format!(
+            "Chat {} is unavailable, it is marked inactive: {}",
+            chat_id, description
+        )
+        .as_str(),
+    );
+
+    Ok(())
+}
+
+fn give_up(
+    logger: &dyn Logger,
+    store: &mut dyn StateStore,
+    entry: &mut OutboxEntry,
+    error: String,
+) -> Result<(), StoreError> {
+    logger.log_error(
+        format!(
+            "Give up sending message {} to chat {}: {}",
+            entry.key, entry.chat_id, error
+        )
+        .as_str(),
+    );
+
+    entry.status = OutboxStatus::Failed;
+    entry.last_error = Some(error);
+
+    store.save_outbox_entry(entry)
+}
@@ -1,24 +1,48 @@
-use crate::commands::{Command, IsCommand};
+use crate::commands::IsCommand;
 
-use super::client_types::RawUpdate;
+use crate::storage::BotStatus;
+
+use super::client_types::{Chat, RawUpdate};
 
 #[derive(Debug)]
 pub enum TelegramUpdate {
     Message {
         update_id: u64,
-        chat_id: u64,
+        chat: Chat,
+        /// Sender, unknown for channel posts.
+        user_id: Option<u64>,
         content: MessageContent,
     },
     CallbackQuery {
         update_id: u64,
-        chat_id: u64,
+        chat: Chat,
+        user_id: Option<u64>,
         content: CallbackData,
     },
+    /// The bot was added to a chat, removed from it or its rights changed.
+    MemberStatus {
+        update_id: u64,
+        chat: Chat,
+        status: BotStatus,
+        /// Unix timestamp of the change.
+        date: i64,
+    },
     Ignore {
         update_id: u64,
     },
 }
 
+impl TelegramUpdate {
+    pub fn update_id(&self) -> u64 {
+        match self {
+            TelegramUpdate::Message { update_id, .. }
+            | TelegramUpdate::CallbackQuery { update_id, .. }
+            | TelegramUpdate::MemberStatus { update_id, .. }
+            | TelegramUpdate::Ignore { update_id } => *update_id,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct CallbackData {
     pub id: String,
@@ -27,7 +51,7 @@ pub struct CallbackData {
 
 #[derive(Debug)]
 pub enum MessageContent {
-    Command(Command, String),
+    Command(String),
     Video { file_id: String },
     Text(String),
 }
@@ -51,37 +75,18 @@ impl From<RawUpdate> for TelegramUpdate {
         if let Some(msg) = &value.message {
             if let Some(text) = &msg.text {
                 if text.is_command() {
-                    let command = text.parse::<Command>().unwrap();
-
-                    match command {
-                        Command::Help => {
-                            return TelegramUpdate::Message {
-                                update_id: value.update_id,
-                                chat_id: msg.chat.id,
-                                content: MessageContent::Command(Command::Help, text.to_owned()),
-                            }
-                        }
-                        Command::Repeat => {
-                            return TelegramUpdate::Message {
-                                update_id: value.update_id,
-                                chat_id: msg.chat.id,
-                                content: MessageContent::Command(Command::Repeat, text.to_owned()),
-                            }
-                        }
-                        Command::Unknown => {
-                            return TelegramUpdate::Message {
-                                update_id: value.update_id,
-                                chat_id: msg.chat.id,
-                                content: MessageContent::Command(Command::Unknown, text.to_owned()),
-                            }
-                        }
-                        _ => {}
-                    }
+                    return TelegramUpdate::Message {
+                        update_id: value.update_id,
+                        chat: msg.chat.clone(),
+                        user_id: msg.from.as_ref().map(|user| user.id),
+                        content: MessageContent::Command(text.to_owned()),
+                    };
                 }
 
                 return TelegramUpdate::Message {
                     update_id: value.update_id,
-                    chat_id: msg.chat.id,
+                    chat: msg.chat.clone(),
+                    user_id: msg.from.as_ref().map(|user| user.id),
                     content: MessageContent::Text(text.clone()),
                 };
             }
@@ -89,7 +94,8 @@ impl From<RawUpdate> for TelegramUpdate {
             if let Some(video) = msg.video.as_ref() {
                 return TelegramUpdate::Message {
                     update_id: value.update_id,
-                    chat_id: msg.chat.id,
+                    chat: msg.chat.clone(),
+                    user_id: msg.from.as_ref().map(|user| user.id),
                     content: MessageContent::Video {
                         file_id: video.file_id.clone(),
                     },
@@ -100,7 +106,8 @@ impl From<RawUpdate> for TelegramUpdate {
         if let Some(query) = &value.callback_query {
             return TelegramUpdate::CallbackQuery {
                 update_id: value.update_id,
-                chat_id: query.message.chat.id,
+                chat: query.message.chat.clone(),
+                user_id: query.from.as_ref().map(|user| user.id),
                 content: CallbackData {
                     id: query.id.clone(),
                     data: query.data.clone(),
@@ -108,6 +115,15 @@ impl From<RawUpdate> for TelegramUpdate {
             };
         }
 
+        if let Some(member) = value.my_chat_member {
+            return TelegramUpdate::MemberStatus {
+                update_id: value.update_id,
+                chat: member.chat,
+                status: member.new_chat_member.status,
+                date: member.date,
+            };
+        }
+
         TelegramUpdate::Ignore {
             update_id: value.update_id,
         }